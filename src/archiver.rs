@@ -0,0 +1,344 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::database::Database;
+use crate::environment_extractor::EnvironmentVariables;
+use crate::file_lister;
+use crate::library_paths::{self, LibraryPaths};
+use crate::settings_parser::ArchiveSettings;
+use crate::song_storage::SongStorage;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Move the songs of the previous run from the downloaded directory into a new dated batch in the
+/// archive directory, then prune the batches the retention policy no longer keeps. Archived songs
+/// are synced to the storage again, which uploads the ones that failed or changed since they were
+/// downloaded
+pub fn archive_downloaded_songs(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    archive_settings: &ArchiveSettings,
+    song_storage: Option<&SongStorage>,
+    sidecar_directory: Option<&Path>,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let downloaded_directory = library_paths.downloaded_directory();
+
+    let downloaded_files = file_lister::list_files(&downloaded_directory)?;
+
+    if !downloaded_files.is_empty() {
+        let archived_at = Local::now();
+
+        // one batch per run, named after when it was archived
+        let mut batch_name = archived_at.format("%Y-%m-%d_%H-%M-%S").to_string();
+        let mut batch_number = 1;
+
+        while library_paths.archive_directory().join(&batch_name).exists() {
+            batch_name = format!(
+                "{}_{}",
+                archived_at.format("%Y-%m-%d_%H-%M-%S"),
+                batch_number
+            );
+            batch_number += 1;
+        }
+
+        let batch_directory = library_paths.archive_directory().join(&batch_name);
+
+        // index the batch first, so an interrupted archive is still pruned later
+        database_context.put_archive_batch(
+            &batch_name,
+            archived_at.timestamp(),
+            environment_variables,
+        )?;
+
+        for downloaded_file in downloaded_files.iter() {
+            // keep the folders the file name template created
+            let relative_path = match downloaded_file.strip_prefix(&downloaded_directory) {
+                Ok(relative_path) => relative_path,
+                Err(e) => {
+                    return Err(format!(
+                        "Downloaded file {} is not in the downloaded directory: {}",
+                        downloaded_file.to_string_lossy(),
+                        e
+                    ))
+                }
+            };
+
+            let archived_file = batch_directory.join(relative_path);
+
+            move_file(downloaded_file, &archived_file)?;
+
+            database_context.update_song_file_path(
+                &library_paths.to_stored_path(downloaded_file),
+                &library_paths.to_stored_path(&archived_file),
+                environment_variables,
+            )?;
+
+            // the key of the song has its batch now
+            if let Some(song_storage) = song_storage {
+                if library_paths::is_song_path(&archived_file) {
+                    song_storage.try_move_song(downloaded_file, &archived_file, library_paths);
+                }
+            }
+        }
+
+        remove_empty_directories(&downloaded_directory)?;
+
+        // the sidecar folder mirrors the library, its downloaded folder becomes the batch too
+        if let Some(sidecar_directory) = sidecar_directory {
+            let sidecar_downloaded_directory =
+                sidecar_directory.join(library_paths.to_stored_path(&downloaded_directory));
+
+            let sidecar_batch_directory =
+                sidecar_directory.join(library_paths.to_stored_path(&batch_directory));
+
+            if sidecar_downloaded_directory.is_dir()
+                && sidecar_downloaded_directory != downloaded_directory
+            {
+                move_directory(&sidecar_downloaded_directory, &sidecar_batch_directory)?;
+            }
+        }
+
+        println!(
+            "Archived {} songs into {}",
+            downloaded_files.len(),
+            batch_directory.to_string_lossy()
+        );
+    }
+
+    prune_archive(
+        database_context,
+        environment_variables,
+        archive_settings,
+        sidecar_directory,
+        library_paths,
+    )?;
+
+    return Ok(());
+}
+
+// delete the archive batches the retention policy no longer keeps
+fn prune_archive(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    archive_settings: &ArchiveSettings,
+    sidecar_directory: Option<&Path>,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let archive_batches = database_context.get_archive_batches(environment_variables)?;
+
+    let pruned_batch_names =
+        get_batches_to_prune(&archive_batches, archive_settings, Local::now().timestamp());
+
+    for batch_name in pruned_batch_names {
+        let batch_directory = library_paths.archive_directory().join(&batch_name);
+
+        // with the sidecars of its songs, when they are in the sidecar folder
+        let sidecar_batch_directory = sidecar_directory.map(|sidecar_directory| {
+            sidecar_directory.join(library_paths.to_stored_path(&batch_directory))
+        });
+
+        for directory in [Some(batch_directory.to_owned()), sidecar_batch_directory]
+            .into_iter()
+            .flatten()
+        {
+            if !directory.exists() {
+                continue;
+            }
+
+            if let Err(e) = std::fs::remove_dir_all(&directory) {
+                return Err(format!(
+                    "Could not delete archive batch {}: {}",
+                    directory.to_string_lossy(),
+                    e
+                ));
+            }
+        }
+
+        database_context.delete_archive_batch(
+            &batch_name,
+            &format!("{}/", library_paths.to_stored_path(&batch_directory)),
+            environment_variables,
+        )?;
+
+        println!("Pruned archive batch {}", batch_name);
+    }
+
+    return Ok(());
+}
+
+// names of the batches, ordered oldest first, that are past the count or age limit
+fn get_batches_to_prune(
+    archive_batches: &[(String, i64)],
+    archive_settings: &ArchiveSettings,
+    now: i64,
+) -> Vec<String> {
+    let pruned_by_count = match archive_settings.keep_batches() {
+        Some(keep_batches) => archive_batches.len().saturating_sub(*keep_batches),
+        None => 0,
+    };
+
+    let oldest_kept = archive_settings
+        .max_age_days()
+        .map(|max_age_days| now - max_age_days as i64 * SECONDS_PER_DAY);
+
+    return archive_batches
+        .iter()
+        .enumerate()
+        .filter(|(batch_index, (_, archived_at))| {
+            let too_old = match oldest_kept {
+                Some(oldest_kept) => *archived_at < oldest_kept,
+                None => false,
+            };
+
+            return *batch_index < pruned_by_count || too_old;
+        })
+        .map(|(_, (batch_name, _))| batch_name.to_owned())
+        .collect();
+}
+
+// rename the directory, which stays in the same folder so on the same file system
+fn move_directory(from_path: &Path, to_path: &Path) -> Result<(), String> {
+    if let Some(parent_directory) = to_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent_directory) {
+            return Err(format!(
+                "Could not create directory {}: {}",
+                parent_directory.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    return match std::fs::rename(from_path, to_path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!(
+            "Could not move {} to {}: {}",
+            from_path.to_string_lossy(),
+            to_path.to_string_lossy(),
+            e
+        )),
+    };
+}
+
+// rename the file, falling back to copying it when it is moved to another file system
+fn move_file(from_path: &Path, to_path: &Path) -> Result<(), String> {
+    if let Some(parent_directory) = to_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent_directory) {
+            return Err(format!(
+                "Could not create directory {}: {}",
+                parent_directory.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    match std::fs::rename(from_path, to_path) {
+        Ok(_) => return Ok(()),
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => (),
+        Err(e) => {
+            return Err(format!(
+                "Could not move {} to {}: {}",
+                from_path.to_string_lossy(),
+                to_path.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    // copy next to the destination first, so the destination is never a partial file
+    let mut temporary_file_name = to_path.as_os_str().to_owned();
+    temporary_file_name.push(".part");
+    let temporary_path = PathBuf::from(temporary_file_name);
+
+    if let Err(e) = std::fs::copy(from_path, &temporary_path) {
+        let _ = std::fs::remove_file(&temporary_path);
+
+        return Err(format!(
+            "Could not copy {} to {}: {}",
+            from_path.to_string_lossy(),
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    if let Err(e) = std::fs::rename(&temporary_path, to_path) {
+        return Err(format!(
+            "Could not move {} into place: {}",
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    if let Err(e) = std::fs::remove_file(from_path) {
+        return Err(format!(
+            "Could not remove {} after copying it: {}",
+            from_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+// remove the folders left empty under the directory, keeping the directory itself
+fn remove_empty_directories(directory: &Path) -> Result<(), String> {
+    let directory_entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(format!(
+                "Could not read directory {}: {}",
+                directory.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    for entry in directory_entries.flatten() {
+        let path = entry.path();
+
+        if !path.is_dir() {
+            continue;
+        }
+
+        remove_empty_directories(&path)?;
+
+        // fails when the folder is not empty, which is fine
+        let _ = std::fs::remove_dir(&path);
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_batches_to_prune, SECONDS_PER_DAY};
+    use crate::settings_parser::ArchiveSettings;
+
+    #[test]
+    fn test_get_batches_to_prune() {
+        let now = 100 * SECONDS_PER_DAY;
+        let archive_batches = vec![
+            ("first".to_string(), now - 30 * SECONDS_PER_DAY),
+            ("second".to_string(), now - 10 * SECONDS_PER_DAY),
+            ("third".to_string(), now - SECONDS_PER_DAY),
+        ];
+
+        let keep_everything = ArchiveSettings::default();
+        assert!(get_batches_to_prune(&archive_batches, &keep_everything, now).is_empty());
+
+        let keep_two: ArchiveSettings = serde_json::from_str(r#"{"keep_batches": 2}"#).unwrap();
+        assert_eq!(
+            get_batches_to_prune(&archive_batches, &keep_two, now),
+            vec!["first"]
+        );
+
+        let keep_week: ArchiveSettings =
+            serde_json::from_str(r#"{"keep_batches": 3, "max_age_days": 7}"#).unwrap();
+        assert_eq!(
+            get_batches_to_prune(&archive_batches, &keep_week, now),
+            vec!["first", "second"]
+        );
+    }
+}
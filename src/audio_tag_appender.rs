@@ -1,39 +1,330 @@
-use audiotags::Tag;
-use std::fs::OpenOptions;
+use std::path::Path;
 
+use crate::cover_art;
+use crate::lyrics_fetcher::Lyrics;
 use crate::music_sources;
+use crate::settings_parser::TaggingSettings;
 
-pub fn append_metadata(song_information: &music_sources::DownloadedSong) -> Result<(), String> {
-    let mut audio_file = match OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(&song_information.file_location)
-    {
-        Ok(audio_file) => audio_file,
-        Err(e) => {
-            return Err(format!("Could not get file: {}", e));
+pub mod flac_tag_format;
+pub mod id3_tag_format;
+pub mod mp4_tag_format;
+pub mod ogg_tag_format;
+mod vorbis_comments;
+
+// replay gain tag names, the same in every container
+pub const TRACK_GAIN_KEY: &str = "REPLAYGAIN_TRACK_GAIN";
+pub const TRACK_PEAK_KEY: &str = "REPLAYGAIN_TRACK_PEAK";
+pub const ALBUM_GAIN_KEY: &str = "REPLAYGAIN_ALBUM_GAIN";
+pub const ALBUM_PEAK_KEY: &str = "REPLAYGAIN_ALBUM_PEAK";
+
+// user defined tag with the url of the playlist the song was downloaded from
+pub const PLAYLIST_URL_KEY: &str = "PLAYLIST_URL";
+
+/// Tags of a song, independent of the container they are stored in
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SongTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub total_tracks: Option<u32>,
+    // YYYY-MM-DD, or only the year
+    pub release_date: Option<String>,
+    // url the song was downloaded from
+    pub source_url: Option<String>,
+    // url of the playlist the song was downloaded for, to rebuild the database from the library
+    pub playlist_url: Option<String>,
+    pub isrc: Option<String>,
+    pub lyrics: Option<Lyrics>,
+    // jpeg bytes of the front cover
+    pub cover_art: Option<Vec<u8>>,
+    pub track_replay_gain: Option<ReplayGain>,
+    // the playlist is the album
+    pub album_replay_gain: Option<ReplayGain>,
+}
+
+/// Gain players apply to reach the replay gain reference loudness, and the peak to keep from clipping
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReplayGain {
+    pub gain_db: f64,
+    // linear, 1.0 is full scale
+    pub peak: f64,
+}
+
+impl SongTags {
+    /// The replay gain tags as (name, value) text pairs, for the containers that store text
+    pub fn get_replay_gain_texts(&self) -> Vec<(&'static str, String)> {
+        let mut replay_gain_texts = Vec::new();
+
+        if let Some(replay_gain) = &self.track_replay_gain {
+            replay_gain_texts.push((TRACK_GAIN_KEY, format!("{:.2} dB", replay_gain.gain_db)));
+            replay_gain_texts.push((TRACK_PEAK_KEY, format!("{:.6}", replay_gain.peak)));
+        }
+
+        if let Some(replay_gain) = &self.album_replay_gain {
+            replay_gain_texts.push((ALBUM_GAIN_KEY, format!("{:.2} dB", replay_gain.gain_db)));
+            replay_gain_texts.push((ALBUM_PEAK_KEY, format!("{:.6}", replay_gain.peak)));
         }
+
+        return replay_gain_texts;
+    }
+}
+
+impl ReplayGain {
+    /// Parse the gain and peak texts, ex. "-3.25 dB" and "0.988553"
+    pub fn from_texts(gain_text: Option<String>, peak_text: Option<String>) -> Option<ReplayGain> {
+        let gain_text = gain_text?;
+        let gain_db = gain_text
+            .trim()
+            .trim_end_matches(|character: char| character.is_alphabetic())
+            .trim()
+            .parse()
+            .ok()?;
+
+        let peak = peak_text?.trim().parse().ok()?;
+
+        return Some(ReplayGain { gain_db, peak });
+    }
+}
+
+/* Common trait for reading and writing the tags of one kind of container */
+pub trait TagFormat {
+    // tags that are None are left as they are in the file
+    fn write_tags(&self, file_path: &Path, song_tags: &SongTags) -> Result<(), String>;
+    fn read_tags(&self, file_path: &Path) -> Result<SongTags, String>;
+}
+
+/// Get the tag format for the container of the file, None if its container can not be tagged
+pub fn get_tag_format_from_path(
+    file_path: &Path,
+    tagging_settings: &TaggingSettings,
+) -> Option<Box<dyn TagFormat>> {
+    let file_extension = match file_path.extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => return None,
     };
 
-    //read metatdata from existing file
-    let mut current_tags = match Tag::default().read_from_path(&song_information.file_location) {
-        Ok(val) => val,
-        Err(e) => {
-            return Err(format!("Could not get tags from audio file: {}", e));
+    return match file_extension.as_str() {
+        "mp3" | "wav" => Some(Box::new(id3_tag_format::Id3TagFormat::new(
+            *tagging_settings.id3_version(),
+        ))),
+        "m4a" | "mp4" => Some(Box::new(mp4_tag_format::Mp4TagFormat {})),
+        "flac" => Some(Box::new(flac_tag_format::FlacTagFormat {})),
+        "opus" | "ogg" | "oga" => Some(Box::new(ogg_tag_format::OggTagFormat {})),
+        // raw aac streams have no place for tags
+        _ => None,
+    };
+}
+
+pub fn append_metadata(
+    song_information: &music_sources::DownloadedSong,
+    playlist_url: &str,
+    tagging_settings: &TaggingSettings,
+    track_replay_gain: Option<ReplayGain>,
+) -> Result<(), String> {
+    let song_tags = SongTags {
+        playlist_url: Some(playlist_url.to_string()),
+        ..get_song_tags(song_information, tagging_settings, track_replay_gain)
+    };
+
+    return write_song_tags(
+        &song_information.file_location,
+        &song_tags,
+        tagging_settings,
+    );
+}
+
+/// The tags of a downloaded song, with its cover art fetched
+pub fn get_song_tags(
+    song_information: &music_sources::DownloadedSong,
+    tagging_settings: &TaggingSettings,
+    track_replay_gain: Option<ReplayGain>,
+) -> SongTags {
+    return SongTags {
+        title: Some(song_information.title.to_owned()),
+        artist: Some(song_information.artist.to_owned()),
+        genre: Some(song_information.genre.to_owned()),
+        // the playlist is the album unless an override sets one
+        album: Some(
+            song_information
+                .album
+                .to_owned()
+                .unwrap_or(song_information.playlist_name.to_owned()),
+        ),
+        album_artist: song_information.album_artist.to_owned(),
+        track_number: song_information.track_number,
+        total_tracks: song_information.total_tracks,
+        release_date: song_information.release_date.to_owned(),
+        // keep where the song came from
+        source_url: Some(song_information.url.to_owned()),
+        // the song does not know its playlist, callers that do add it
+        playlist_url: None,
+        isrc: song_information.isrc.to_owned(),
+        // lyrics are looked up once the song is tagged
+        lyrics: None,
+        cover_art: get_cover_art(song_information, tagging_settings),
+        track_replay_gain,
+        // the album gain needs the whole playlist, it is written once the playlist is downloaded
+        album_replay_gain: None,
+    };
+}
+
+/// Write the tags to the file in the format of its container, files that can not be tagged are skipped
+pub fn write_song_tags(
+    file_path: &Path,
+    song_tags: &SongTags,
+    tagging_settings: &TaggingSettings,
+) -> Result<(), String> {
+    let tag_format = match get_tag_format_from_path(file_path, tagging_settings) {
+        Some(tag_format) => tag_format,
+        None => {
+            println!(
+                "Tagging is not supported for this container, skipping tags for {}",
+                file_path.to_string_lossy()
+            );
+
+            return Ok(());
         }
     };
 
-    //set appropriate tags
-    current_tags.set_title(&song_information.title);
-    current_tags.set_artist(&song_information.artist);
-    current_tags.set_genre(&song_information.genre);
+    return tag_format.write_tags(file_path, song_tags);
+}
+
+// the artwork of the source, or the thumbnail yt-dlp found, as a square jpeg
+fn get_cover_art(
+    song_information: &music_sources::DownloadedSong,
+    tagging_settings: &TaggingSettings,
+) -> Option<Vec<u8>> {
+    if !*tagging_settings.embed_cover_art() {
+        return None;
+    }
 
-    match current_tags.write_to(&mut audio_file) {
-        Ok(_) => (),
+    let artwork_url = song_information
+        .artwork_url
+        .as_ref()
+        .or(song_information.download_information.thumbnail.as_ref())?;
+
+    return match cover_art::fetch_cover_art(artwork_url, *tagging_settings.cover_art_size()) {
+        Ok(cover_art) => Some(cover_art),
         Err(e) => {
-            return Err(format!("Cannot write to audio file: {}", e));
+            println!(
+                "Could not embed cover art for {}: {}",
+                song_information.url, e
+            );
+
+            None
         }
     };
+}
 
-    return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::{get_tag_format_from_path, ReplayGain, SongTags};
+    use crate::lyrics_fetcher::Lyrics;
+    use crate::settings_parser::TaggingSettings;
+    use std::path::{Path, PathBuf};
+
+    const FIXTURE_NAMES: [&str; 6] = [
+        "silence.mp3",
+        "silence.wav",
+        "silence.m4a",
+        "silence.flac",
+        "silence.opus",
+        "silence.ogg",
+    ];
+
+    // copy a fixture so the test can write to it
+    fn copy_fixture(fixture_name: &str, id3_version: &str) -> PathBuf {
+        let fixture_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/tagging")
+            .join(fixture_name);
+
+        let file_path = std::env::temp_dir().join(format!(
+            "musix_tagging_{}_{}_{}",
+            std::process::id(),
+            id3_version,
+            fixture_name
+        ));
+
+        std::fs::copy(fixture_path, &file_path).unwrap();
+
+        return file_path;
+    }
+
+    fn get_song_tags() -> SongTags {
+        return SongTags {
+            title: Some("Caf\u{e9} del Mar".to_string()),
+            artist: Some("Energy 52".to_string()),
+            genre: Some("Trance".to_string()),
+            album: Some("Classics".to_string()),
+            album_artist: Some("Playlist Owner".to_string()),
+            track_number: Some(3),
+            total_tracks: Some(12),
+            release_date: Some("1997-05-21".to_string()),
+            source_url: Some("https://www.youtube.com/watch?v=abc123".to_string()),
+            playlist_url: Some("https://www.youtube.com/playlist?list=xyz789".to_string()),
+            isrc: Some("GBUM71029604".to_string()),
+            // mp4 only keeps plain lyrics
+            lyrics: Some(Lyrics {
+                plain: "One more time\nWe're gonna celebrate".to_string(),
+                synced_lines: Vec::new(),
+            }),
+            // not a real jpeg, but large enough to span several ogg pages
+            cover_art: Some((0..100_000).map(|i| (i % 251) as u8).collect()),
+            track_replay_gain: Some(ReplayGain {
+                gain_db: -3.25,
+                peak: 0.5,
+            }),
+            album_replay_gain: Some(ReplayGain {
+                gain_db: 1.5,
+                peak: 0.75,
+            }),
+        };
+    }
+
+    #[test]
+    fn test_tag_round_trip() {
+        for id3_version in ["2.3", "2.4"] {
+            let tagging_settings: TaggingSettings =
+                serde_json::from_str(&format!(r#"{{"id3_version": "{}"}}"#, id3_version)).unwrap();
+
+            for fixture_name in FIXTURE_NAMES {
+                let file_path = copy_fixture(fixture_name, id3_version);
+                let tag_format = get_tag_format_from_path(&file_path, &tagging_settings).unwrap();
+
+                let song_tags = get_song_tags();
+                tag_format.write_tags(&file_path, &song_tags).unwrap();
+                assert_eq!(
+                    tag_format.read_tags(&file_path).unwrap(),
+                    song_tags,
+                    "{}",
+                    fixture_name
+                );
+
+                // retagging replaces the values, tags that are not given are kept
+                let retagged_song_tags = SongTags {
+                    title: Some("Sunset".to_string()),
+                    cover_art: Some(vec![1, 2, 3]),
+                    ..SongTags::default()
+                };
+                tag_format
+                    .write_tags(&file_path, &retagged_song_tags)
+                    .unwrap();
+                assert_eq!(
+                    tag_format.read_tags(&file_path).unwrap(),
+                    SongTags {
+                        title: Some("Sunset".to_string()),
+                        cover_art: Some(vec![1, 2, 3]),
+                        ..song_tags
+                    },
+                    "{}",
+                    fixture_name
+                );
+
+                std::fs::remove_file(&file_path).unwrap();
+            }
+        }
+    }
 }
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use metaflac::block::{Block, BlockType, PictureType};
+
+use super::{vorbis_comments, SongTags, TagFormat};
+
+/// Vorbis comments and picture blocks of flac files
+pub struct FlacTagFormat {}
+
+impl TagFormat for FlacTagFormat {
+    fn write_tags(&self, file_path: &Path, song_tags: &SongTags) -> Result<(), String> {
+        let mut tag = read_flac_tag(file_path)?;
+
+        let vorbis_comment = tag.vorbis_comments().cloned().unwrap_or_default();
+
+        tag.remove_blocks(BlockType::VorbisComment);
+        tag.push_block(Block::VorbisComment(vorbis_comments::with_song_tags(
+            vorbis_comment,
+            song_tags,
+        )));
+
+        if let Some(cover_art) = &song_tags.cover_art {
+            tag.remove_picture_type(PictureType::CoverFront);
+            tag.push_block(Block::Picture(vorbis_comments::get_cover_picture(
+                cover_art,
+            )));
+        }
+
+        match tag.save() {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not write flac tags to {}: {}",
+                    file_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        return Ok(());
+    }
+
+    fn read_tags(&self, file_path: &Path) -> Result<SongTags, String> {
+        let tag = read_flac_tag(file_path)?;
+
+        let mut song_tags = match tag.vorbis_comments() {
+            Some(vorbis_comment) => vorbis_comments::get_song_tags(vorbis_comment),
+            None => SongTags::default(),
+        };
+
+        song_tags.cover_art = tag
+            .pictures()
+            .find(|picture| picture.picture_type == PictureType::CoverFront)
+            .map(|picture| picture.data.to_owned());
+
+        return Ok(song_tags);
+    }
+}
+
+fn read_flac_tag(file_path: &Path) -> Result<metaflac::Tag, String> {
+    return match metaflac::Tag::read_from_path(file_path) {
+        Ok(tag) => Ok(tag),
+        Err(e) => Err(format!(
+            "Could not read flac tags from {}: {}",
+            file_path.to_string_lossy(),
+            e
+        )),
+    };
+}
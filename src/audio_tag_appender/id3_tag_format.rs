@@ -0,0 +1,279 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use id3::frame::{
+    Comment, ExtendedText, Lyrics as LyricsFrame, Picture, PictureType, SynchronisedLyrics,
+    SynchronisedLyricsType, TimestampFormat,
+};
+use id3::{Frame, TagLike, Timestamp, Version};
+
+use super::{
+    ReplayGain, SongTags, TagFormat, ALBUM_GAIN_KEY, ALBUM_PEAK_KEY, PLAYLIST_URL_KEY,
+    TRACK_GAIN_KEY, TRACK_PEAK_KEY,
+};
+use crate::lyrics_fetcher::Lyrics;
+use crate::settings_parser::Id3Version;
+
+// id3 has a link frame meant for the page the audio was taken from
+const SOURCE_URL_FRAME: &str = "WOAS";
+
+const ISRC_FRAME: &str = "TSRC";
+
+const COMMENT_LANGUAGE: &str = "eng";
+
+const JPEG_MIME_TYPE: &str = "image/jpeg";
+
+/// Id3 tags of mp3 files, and of wav files where they are stored in a chunk
+pub struct Id3TagFormat {
+    id3_version: Id3Version,
+}
+
+impl Id3TagFormat {
+    pub fn new(id3_version: Id3Version) -> Self {
+        return Id3TagFormat { id3_version };
+    }
+}
+
+impl TagFormat for Id3TagFormat {
+    fn write_tags(&self, file_path: &Path, song_tags: &SongTags) -> Result<(), String> {
+        let mut tag = read_id3_tag(file_path)?;
+
+        if let Some(title) = &song_tags.title {
+            tag.set_title(title);
+        }
+
+        if let Some(artist) = &song_tags.artist {
+            tag.set_artist(artist);
+        }
+
+        if let Some(genre) = &song_tags.genre {
+            tag.set_genre(genre);
+        }
+
+        if let Some(album) = &song_tags.album {
+            tag.set_album(album);
+        }
+
+        if let Some(album_artist) = &song_tags.album_artist {
+            tag.set_album_artist(album_artist);
+        }
+
+        if let Some(track_number) = song_tags.track_number {
+            tag.set_track(track_number);
+        }
+
+        if let Some(total_tracks) = song_tags.total_tracks {
+            tag.set_total_tracks(total_tracks);
+        }
+
+        if let Some(release_date) = &song_tags.release_date {
+            if let Ok(timestamp) = Timestamp::from_str(release_date) {
+                tag = with_release_date(tag, timestamp, self.id3_version);
+            }
+        }
+
+        if let Some(source_url) = &song_tags.source_url {
+            tag.add_frame(Frame::link(SOURCE_URL_FRAME, source_url));
+            tag.add_frame(Comment {
+                lang: COMMENT_LANGUAGE.to_string(),
+                description: String::new(),
+                text: source_url.to_owned(),
+            });
+        }
+
+        if let Some(playlist_url) = &song_tags.playlist_url {
+            tag.add_frame(ExtendedText {
+                description: PLAYLIST_URL_KEY.to_string(),
+                value: playlist_url.to_owned(),
+            });
+        }
+
+        if let Some(isrc) = &song_tags.isrc {
+            tag.set_text(ISRC_FRAME, isrc);
+        }
+
+        // plain lyrics in USLT, and synchronized lyrics in SYLT when there are any
+        if let Some(lyrics) = &song_tags.lyrics {
+            tag.remove_all_lyrics();
+            tag.add_frame(LyricsFrame {
+                lang: COMMENT_LANGUAGE.to_string(),
+                description: String::new(),
+                text: lyrics.plain.to_owned(),
+            });
+
+            tag.remove_all_synchronised_lyrics();
+
+            if !lyrics.synced_lines.is_empty() {
+                tag.add_frame(SynchronisedLyrics {
+                    lang: COMMENT_LANGUAGE.to_string(),
+                    timestamp_format: TimestampFormat::Ms,
+                    content_type: SynchronisedLyricsType::Lyrics,
+                    description: String::new(),
+                    content: lyrics.synced_lines.to_owned(),
+                });
+            }
+        }
+
+        if let Some(cover_art) = &song_tags.cover_art {
+            tag.add_frame(Picture {
+                mime_type: JPEG_MIME_TYPE.to_string(),
+                picture_type: PictureType::CoverFront,
+                description: String::new(),
+                data: cover_art.to_owned(),
+            });
+        }
+
+        // replay gain is stored in user defined text frames
+        for (description, value) in song_tags.get_replay_gain_texts() {
+            tag.add_frame(ExtendedText {
+                description: description.to_string(),
+                value,
+            });
+        }
+
+        let version = match self.id3_version {
+            Id3Version::V23 => Version::Id3v23,
+            Id3Version::V24 => Version::Id3v24,
+        };
+
+        // the container, mp3 or wav, is detected from the file
+        match tag.write_to_path(file_path, version) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not write id3 tags to {}: {}",
+                    file_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        return Ok(());
+    }
+
+    fn read_tags(&self, file_path: &Path) -> Result<SongTags, String> {
+        let tag = read_id3_tag(file_path)?;
+
+        let source_url = tag
+            .get(SOURCE_URL_FRAME)
+            .and_then(|frame| frame.content().link())
+            .map(|link| link.to_string())
+            .or(tag
+                .comments()
+                .map(|comment| comment.text.to_owned())
+                .find(|comment| comment.starts_with("http")));
+
+        // players write the descriptions in either case
+        let get_extended_text = |description: &str| -> Option<String> {
+            return tag
+                .extended_texts()
+                .find(|extended_text| extended_text.description.eq_ignore_ascii_case(description))
+                .map(|extended_text| extended_text.value.to_owned());
+        };
+
+        return Ok(SongTags {
+            title: tag.title().map(|title| title.to_string()),
+            artist: tag.artist().map(|artist| artist.to_string()),
+            genre: tag.genre_parsed().map(|genre| genre.to_string()),
+            album: tag.album().map(|album| album.to_string()),
+            album_artist: tag
+                .album_artist()
+                .map(|album_artist| album_artist.to_string()),
+            track_number: tag.track(),
+            total_tracks: tag.total_tracks(),
+            release_date: get_release_date(&tag),
+            source_url,
+            playlist_url: get_extended_text(PLAYLIST_URL_KEY),
+            lyrics: get_lyrics(&tag),
+            isrc: tag
+                .get(ISRC_FRAME)
+                .and_then(|frame| frame.content().text())
+                .map(|isrc| isrc.to_string()),
+            cover_art: tag
+                .pictures()
+                .find(|picture| picture.picture_type == PictureType::CoverFront)
+                .map(|picture| picture.data.to_owned()),
+            track_replay_gain: ReplayGain::from_texts(
+                get_extended_text(TRACK_GAIN_KEY),
+                get_extended_text(TRACK_PEAK_KEY),
+            ),
+            album_replay_gain: ReplayGain::from_texts(
+                get_extended_text(ALBUM_GAIN_KEY),
+                get_extended_text(ALBUM_PEAK_KEY),
+            ),
+        });
+    }
+}
+
+// the plain lyrics, with the synchronized lines if there are any in millisecond timestamps
+fn get_lyrics(tag: &id3::Tag) -> Option<Lyrics> {
+    let synced_lines = tag
+        .synchronised_lyrics()
+        .find(|synced_lyrics| synced_lyrics.timestamp_format == TimestampFormat::Ms)
+        .map(|synced_lyrics| synced_lyrics.content.to_owned())
+        .unwrap_or_default();
+
+    return tag.lyrics().next().map(|lyrics| Lyrics {
+        plain: lyrics.text.to_owned(),
+        synced_lines,
+    });
+}
+
+// the tag in the file, or an empty one if the file has none yet
+fn read_id3_tag(file_path: &Path) -> Result<id3::Tag, String> {
+    return match id3::Tag::read_from_path(file_path) {
+        Ok(tag) => Ok(tag),
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => Ok(id3::Tag::new()),
+        Err(e) => Err(format!(
+            "Could not read id3 tags from {}: {}",
+            file_path.to_string_lossy(),
+            e
+        )),
+    };
+}
+
+// 2.4 has a timestamp frame, 2.3 has a year frame and a DDMM date frame
+fn with_release_date(tag: id3::Tag, timestamp: Timestamp, id3_version: Id3Version) -> id3::Tag {
+    let mut tag = tag;
+
+    // the frames of the other version are dropped, as players would pick either
+    match id3_version {
+        Id3Version::V24 => {
+            tag.remove("TYER");
+            tag.remove("TDAT");
+            tag.set_date_recorded(timestamp);
+        }
+        Id3Version::V23 => {
+            tag.remove("TDRC");
+            tag.set_year(timestamp.year);
+
+            match (timestamp.month, timestamp.day) {
+                (Some(month), Some(day)) => tag.set_text("TDAT", format!("{:02}{:02}", day, month)),
+                _ => {
+                    tag.remove("TDAT");
+                }
+            };
+        }
+    };
+
+    return tag;
+}
+
+fn get_release_date(tag: &id3::Tag) -> Option<String> {
+    if let Some(timestamp) = tag.date_recorded() {
+        return Some(timestamp.to_string());
+    }
+
+    let year = tag.year()?;
+
+    // TDAT is DDMM
+    let date = tag
+        .get("TDAT")
+        .and_then(|frame| frame.content().text())
+        .filter(|date| date.len() == 4 && date.is_ascii());
+
+    return match date {
+        Some(date) => Some(format!("{:04}-{}-{}", year, &date[2..], &date[..2])),
+        None => Some(format!("{:04}", year)),
+    };
+}
@@ -0,0 +1,172 @@
+use std::path::Path;
+
+use mp4ameta::{Data, FreeformIdent, Img};
+
+use super::{
+    ReplayGain, SongTags, TagFormat, ALBUM_GAIN_KEY, ALBUM_PEAK_KEY, PLAYLIST_URL_KEY,
+    TRACK_GAIN_KEY, TRACK_PEAK_KEY,
+};
+use crate::lyrics_fetcher::Lyrics;
+
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+
+// freeform atom holding the source url, next to the comment atom
+const SOURCE_URL_IDENT: FreeformIdent<'static> = FreeformIdent::new(FREEFORM_MEAN, "SOURCE_URL");
+
+const ISRC_IDENT: FreeformIdent<'static> = FreeformIdent::new(FREEFORM_MEAN, "ISRC");
+
+const PLAYLIST_URL_IDENT: FreeformIdent<'static> =
+    FreeformIdent::new(FREEFORM_MEAN, PLAYLIST_URL_KEY);
+
+/// Metadata atoms of m4a and mp4 files
+pub struct Mp4TagFormat {}
+
+impl TagFormat for Mp4TagFormat {
+    fn write_tags(&self, file_path: &Path, song_tags: &SongTags) -> Result<(), String> {
+        let mut tag = read_mp4_tag(file_path)?;
+
+        if let Some(title) = &song_tags.title {
+            tag.set_title(title);
+        }
+
+        if let Some(artist) = &song_tags.artist {
+            tag.set_artist(artist);
+        }
+
+        if let Some(genre) = &song_tags.genre {
+            tag.set_genre(genre);
+        }
+
+        if let Some(album) = &song_tags.album {
+            tag.set_album(album);
+        }
+
+        if let Some(album_artist) = &song_tags.album_artist {
+            tag.set_album_artist(album_artist);
+        }
+
+        // track numbers are 16 bit in mp4
+        if let Some(track_number) = song_tags.track_number {
+            tag.set_track_number(track_number.min(u16::MAX as u32) as u16);
+        }
+
+        if let Some(total_tracks) = song_tags.total_tracks {
+            tag.set_total_tracks(total_tracks.min(u16::MAX as u32) as u16);
+        }
+
+        // ©day takes the full date
+        if let Some(release_date) = &song_tags.release_date {
+            tag.set_year(release_date);
+        }
+
+        if let Some(source_url) = &song_tags.source_url {
+            tag.set_comment(source_url);
+            tag.set_data(SOURCE_URL_IDENT, Data::Utf8(source_url.to_owned()));
+        }
+
+        if let Some(playlist_url) = &song_tags.playlist_url {
+            tag.set_data(PLAYLIST_URL_IDENT, Data::Utf8(playlist_url.to_owned()));
+        }
+
+        if let Some(isrc) = &song_tags.isrc {
+            tag.set_data(ISRC_IDENT, Data::Utf8(isrc.to_owned()));
+        }
+
+        // ©lyr only holds plain lyrics
+        if let Some(lyrics) = &song_tags.lyrics {
+            tag.set_lyrics(&lyrics.plain);
+        }
+
+        if let Some(cover_art) = &song_tags.cover_art {
+            tag.set_artwork(Img::jpeg(cover_art.to_owned()));
+        }
+
+        // replay gain is stored in lowercase freeform atoms, as other taggers write it
+        for (name, value) in song_tags.get_replay_gain_texts() {
+            let freeform_name = name.to_lowercase();
+
+            tag.set_data(
+                FreeformIdent::new(FREEFORM_MEAN, &freeform_name),
+                Data::Utf8(value),
+            );
+        }
+
+        match tag.write_to_path(file_path) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not write mp4 tags to {}: {}",
+                    file_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        return Ok(());
+    }
+
+    fn read_tags(&self, file_path: &Path) -> Result<SongTags, String> {
+        let tag = read_mp4_tag(file_path)?;
+
+        let comment_url = tag.comment().filter(|comment| comment.starts_with("http"));
+
+        let get_freeform_text = |name: &str| -> Option<String> {
+            let freeform_name = name.to_lowercase();
+
+            return tag
+                .strings_of(&FreeformIdent::new(FREEFORM_MEAN, &freeform_name))
+                .next()
+                .map(|value| value.to_string());
+        };
+
+        return Ok(SongTags {
+            title: tag.title().map(|title| title.to_string()),
+            artist: tag.artist().map(|artist| artist.to_string()),
+            genre: tag.genre().map(|genre| genre.to_string()),
+            album: tag.album().map(|album| album.to_string()),
+            album_artist: tag
+                .album_artist()
+                .map(|album_artist| album_artist.to_string()),
+            track_number: tag.track_number().map(|track_number| track_number as u32),
+            total_tracks: tag.total_tracks().map(|total_tracks| total_tracks as u32),
+            release_date: tag.year().map(|year| year.to_string()),
+            source_url: tag
+                .strings_of(&SOURCE_URL_IDENT)
+                .next()
+                .or(comment_url)
+                .map(|source_url| source_url.to_string()),
+            playlist_url: tag
+                .strings_of(&PLAYLIST_URL_IDENT)
+                .next()
+                .map(|playlist_url| playlist_url.to_string()),
+            lyrics: tag.lyrics().map(|lyrics| Lyrics {
+                plain: lyrics.to_string(),
+                synced_lines: Vec::new(),
+            }),
+            isrc: tag
+                .strings_of(&ISRC_IDENT)
+                .next()
+                .map(|isrc| isrc.to_string()),
+            cover_art: tag.artwork().map(|artwork| artwork.data.to_vec()),
+            track_replay_gain: ReplayGain::from_texts(
+                get_freeform_text(TRACK_GAIN_KEY),
+                get_freeform_text(TRACK_PEAK_KEY),
+            ),
+            album_replay_gain: ReplayGain::from_texts(
+                get_freeform_text(ALBUM_GAIN_KEY),
+                get_freeform_text(ALBUM_PEAK_KEY),
+            ),
+        });
+    }
+}
+
+fn read_mp4_tag(file_path: &Path) -> Result<mp4ameta::Tag, String> {
+    return match mp4ameta::Tag::read_from_path(file_path) {
+        Ok(tag) => Ok(tag),
+        Err(e) => Err(format!(
+            "Could not read mp4 tags from {}: {}",
+            file_path.to_string_lossy(),
+            e
+        )),
+    };
+}
@@ -0,0 +1,463 @@
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+use metaflac::block::{Picture, PictureType, VorbisComment};
+
+use super::{vorbis_comments, SongTags, TagFormat};
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+// fixed part of the page header, followed by the segment table
+const PAGE_HEADER_LENGTH: usize = 27;
+
+const CONTINUED_PACKET_FLAG: u8 = 0x01;
+
+const MAX_SEGMENTS_PER_PAGE: usize = 255;
+
+const MAX_SEGMENT_LENGTH: usize = 255;
+
+// granule position of pages on which no packet ends
+const NO_GRANULE_POSITION: u64 = u64::MAX;
+
+// ogg stores the cover as a base64 encoded flac picture block
+const PICTURE_KEY: &str = "METADATA_BLOCK_PICTURE";
+
+// opus players read the gain relative to -23 LUFS from these, instead of replay gain
+const OPUS_TRACK_GAIN_KEY: &str = "R128_TRACK_GAIN";
+const OPUS_ALBUM_GAIN_KEY: &str = "R128_ALBUM_GAIN";
+
+// replay gain is relative to -18 LUFS, opus gains to -23 LUFS
+const OPUS_REFERENCE_OFFSET_DB: f64 = -5.0;
+
+/// Vorbis comments of ogg opus and ogg vorbis files
+pub struct OggTagFormat {}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum OggCodec {
+    Opus,
+    Vorbis,
+}
+
+#[derive(Clone, Debug)]
+struct OggPage {
+    header_type: u8,
+    granule_position: u64,
+    serial_number: u32,
+    sequence_number: u32,
+    segment_table: Vec<u8>,
+    body: Vec<u8>,
+}
+
+impl OggCodec {
+    // the first packet of the stream identifies the codec
+    fn from_identification_packet(packet: &[u8]) -> Result<OggCodec, String> {
+        if packet.starts_with(b"OpusHead") {
+            return Ok(OggCodec::Opus);
+        }
+
+        if packet.starts_with(b"\x01vorbis") {
+            return Ok(OggCodec::Vorbis);
+        }
+
+        return Err("Only ogg files with opus or vorbis audio can be tagged".to_string());
+    }
+
+    // identification and comment headers, vorbis also has a setup header
+    fn header_packet_count(&self) -> usize {
+        return match self {
+            OggCodec::Opus => 2,
+            OggCodec::Vorbis => 3,
+        };
+    }
+
+    fn comment_packet_prefix(&self) -> &'static [u8] {
+        return match self {
+            OggCodec::Opus => b"OpusTags",
+            OggCodec::Vorbis => b"\x03vorbis",
+        };
+    }
+}
+
+impl TagFormat for OggTagFormat {
+    fn write_tags(&self, file_path: &Path, song_tags: &SongTags) -> Result<(), String> {
+        let file_bytes = read_file(file_path)?;
+        let pages = parse_pages(&file_bytes)?;
+        let (codec, header_packets, header_page_count) = get_header_packets(&pages)?;
+
+        let mut vorbis_comment = vorbis_comments::with_song_tags(
+            parse_comment_packet(&header_packets[1], codec)?,
+            song_tags,
+        );
+
+        if let Some(cover_art) = &song_tags.cover_art {
+            let picture = vorbis_comments::get_cover_picture(cover_art);
+
+            vorbis_comment.set(
+                PICTURE_KEY,
+                vec![base64::engine::general_purpose::STANDARD.encode(picture.to_bytes())],
+            );
+        }
+
+        if codec == OggCodec::Opus {
+            let opus_gains = [
+                (OPUS_TRACK_GAIN_KEY, song_tags.track_replay_gain),
+                (OPUS_ALBUM_GAIN_KEY, song_tags.album_replay_gain),
+            ];
+
+            for (key, replay_gain) in opus_gains {
+                if let Some(replay_gain) = replay_gain {
+                    vorbis_comment.set(key, vec![get_opus_gain(replay_gain.gain_db).to_string()]);
+                }
+            }
+        }
+
+        let mut comment_packet = codec.comment_packet_prefix().to_vec();
+        comment_packet.extend(vorbis_comment.to_bytes());
+
+        // vorbis headers end with a framing bit
+        if codec == OggCodec::Vorbis {
+            comment_packet.push(1);
+        }
+
+        // the identification header is alone on the first page, which is kept as it is
+        let first_page = &pages[0];
+
+        let mut rewritten_header_packets = vec![comment_packet];
+        rewritten_header_packets.extend(header_packets[2..].iter().cloned());
+
+        let rewritten_header_pages = paginate_packets(
+            &rewritten_header_packets,
+            first_page.serial_number,
+            first_page.sequence_number + 1,
+        );
+
+        // the pages after the headers move by the number of header pages that were added or removed
+        let sequence_offset = rewritten_header_pages.len() as i64 - (header_page_count as i64 - 1);
+
+        let mut rewritten_bytes = page_to_bytes(first_page);
+
+        for page in rewritten_header_pages.iter() {
+            rewritten_bytes.extend(page_to_bytes(page));
+        }
+
+        for page in pages[header_page_count..].iter() {
+            let mut page = page.clone();
+
+            // other logical streams in the file keep their numbering
+            if page.serial_number == first_page.serial_number {
+                page.sequence_number = (page.sequence_number as i64 + sequence_offset) as u32;
+            }
+
+            rewritten_bytes.extend(page_to_bytes(&page));
+        }
+
+        return write_file_atomically(file_path, &rewritten_bytes);
+    }
+
+    fn read_tags(&self, file_path: &Path) -> Result<SongTags, String> {
+        let file_bytes = read_file(file_path)?;
+        let pages = parse_pages(&file_bytes)?;
+        let (codec, header_packets, _) = get_header_packets(&pages)?;
+
+        let vorbis_comment = parse_comment_packet(&header_packets[1], codec)?;
+
+        let mut song_tags = vorbis_comments::get_song_tags(&vorbis_comment);
+
+        song_tags.cover_art = vorbis_comment
+            .get(PICTURE_KEY)
+            .into_iter()
+            .flatten()
+            .filter_map(|encoded_picture| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded_picture)
+                    .ok()
+            })
+            .filter_map(|picture_bytes| Picture::from_bytes(&picture_bytes).ok())
+            .find(|picture| picture.picture_type == PictureType::CoverFront)
+            .map(|picture| picture.data);
+
+        return Ok(song_tags);
+    }
+}
+
+// opus gains are Q7.8 fixed point numbers of dB
+fn get_opus_gain(replay_gain_db: f64) -> i16 {
+    let opus_gain = ((replay_gain_db + OPUS_REFERENCE_OFFSET_DB) * 256.0).round();
+
+    return opus_gain.clamp(i16::MIN as f64, i16::MAX as f64) as i16;
+}
+
+fn read_file(file_path: &Path) -> Result<Vec<u8>, String> {
+    return match std::fs::read(file_path) {
+        Ok(file_bytes) => Ok(file_bytes),
+        Err(e) => Err(format!(
+            "Could not read ogg file {}: {}",
+            file_path.to_string_lossy(),
+            e
+        )),
+    };
+}
+
+// write next to the file first, so an interrupted write does not leave a broken song
+fn write_file_atomically(file_path: &Path, file_bytes: &[u8]) -> Result<(), String> {
+    let mut temporary_file_name = file_path.as_os_str().to_owned();
+    temporary_file_name.push(".part");
+    let temporary_path = PathBuf::from(temporary_file_name);
+
+    if let Err(e) = std::fs::write(&temporary_path, file_bytes) {
+        let _ = std::fs::remove_file(&temporary_path);
+
+        return Err(format!(
+            "Could not write ogg tags to {}: {}",
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    if let Err(e) = std::fs::rename(&temporary_path, file_path) {
+        return Err(format!(
+            "Could not move {} into place: {}",
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+fn parse_pages(file_bytes: &[u8]) -> Result<Vec<OggPage>, String> {
+    let mut pages = Vec::<OggPage>::new();
+    let mut offset = 0;
+
+    while offset < file_bytes.len() {
+        let header = match file_bytes.get(offset..offset + PAGE_HEADER_LENGTH) {
+            Some(header) if header.starts_with(CAPTURE_PATTERN) => header,
+            _ => return Err(format!("Invalid ogg page at byte {}", offset)),
+        };
+
+        let segment_count = header[26] as usize;
+        let segment_table_end = offset + PAGE_HEADER_LENGTH + segment_count;
+
+        let segment_table = match file_bytes.get(offset + PAGE_HEADER_LENGTH..segment_table_end) {
+            Some(segment_table) => segment_table.to_vec(),
+            None => return Err(format!("Truncated ogg page at byte {}", offset)),
+        };
+
+        let body_length: usize = segment_table.iter().map(|lacing| *lacing as usize).sum();
+
+        let body = match file_bytes.get(segment_table_end..segment_table_end + body_length) {
+            Some(body) => body.to_vec(),
+            None => return Err(format!("Truncated ogg page at byte {}", offset)),
+        };
+
+        pages.push(OggPage {
+            header_type: header[5],
+            granule_position: u64::from_le_bytes(header[6..14].try_into().unwrap()),
+            serial_number: u32::from_le_bytes(header[14..18].try_into().unwrap()),
+            sequence_number: u32::from_le_bytes(header[18..22].try_into().unwrap()),
+            segment_table,
+            body,
+        });
+
+        offset = segment_table_end + body_length;
+    }
+
+    if pages.is_empty() {
+        return Err("Ogg file has no pages".to_string());
+    }
+
+    return Ok(pages);
+}
+
+// the codec, its header packets and the number of pages they take up
+fn get_header_packets(pages: &[OggPage]) -> Result<(OggCodec, Vec<Vec<u8>>, usize), String> {
+    let codec = OggCodec::from_identification_packet(&pages[0].body)?;
+    let serial_number = pages[0].serial_number;
+
+    let mut packets = Vec::<Vec<u8>>::new();
+    let mut packet = Vec::<u8>::new();
+
+    for (page_index, page) in pages.iter().enumerate() {
+        // pages of other logical streams can not be between the headers
+        if page.serial_number != serial_number {
+            return Err("Ogg file has interleaved streams in its headers".to_string());
+        }
+
+        let mut body_offset = 0;
+
+        for (segment_index, lacing) in page.segment_table.iter().enumerate() {
+            let segment_length = *lacing as usize;
+
+            packet.extend(&page.body[body_offset..body_offset + segment_length]);
+            body_offset += segment_length;
+
+            // a segment shorter than the maximum ends the packet
+            if segment_length == MAX_SEGMENT_LENGTH {
+                continue;
+            }
+
+            packets.push(std::mem::take(&mut packet));
+
+            if packets.len() < codec.header_packet_count() {
+                continue;
+            }
+
+            // audio has to start on a new page, or it would have to be repaginated as well
+            if segment_index != page.segment_table.len() - 1 {
+                return Err("Ogg file has audio on the same page as its headers".to_string());
+            }
+
+            return Ok((codec, packets, page_index + 1));
+        }
+    }
+
+    return Err("Ogg file ends before its headers do".to_string());
+}
+
+fn parse_comment_packet(packet: &[u8], codec: OggCodec) -> Result<VorbisComment, String> {
+    let comment_bytes = match packet.strip_prefix(codec.comment_packet_prefix()) {
+        Some(comment_bytes) => comment_bytes,
+        None => return Err("Ogg file has no comment header".to_string()),
+    };
+
+    return match VorbisComment::from_bytes(comment_bytes) {
+        Ok(vorbis_comment) => Ok(vorbis_comment),
+        Err(e) => Err(format!("Could not read ogg comment header: {}", e)),
+    };
+}
+
+// lay the packets out over as many pages as they need, starting at the given sequence number
+fn paginate_packets(
+    packets: &[Vec<u8>],
+    serial_number: u32,
+    first_sequence_number: u32,
+) -> Vec<OggPage> {
+    let new_page = |header_type: u8, sequence_number: u32| -> OggPage {
+        return OggPage {
+            header_type,
+            granule_position: 0,
+            serial_number,
+            sequence_number,
+            segment_table: Vec::new(),
+            body: Vec::new(),
+        };
+    };
+
+    let mut pages = Vec::<OggPage>::new();
+    let mut page = new_page(0, first_sequence_number);
+
+    for packet in packets.iter() {
+        let mut remaining = packet.as_slice();
+
+        loop {
+            if page.segment_table.len() == MAX_SEGMENTS_PER_PAGE {
+                let is_continued = remaining.len() != packet.len();
+
+                let header_type = match is_continued {
+                    true => CONTINUED_PACKET_FLAG,
+                    false => 0,
+                };
+
+                let next_page = new_page(header_type, page.sequence_number + 1);
+                pages.push(std::mem::replace(&mut page, next_page));
+            }
+
+            // packets that are a multiple of the segment length end with an empty segment
+            let segment_length = remaining.len().min(MAX_SEGMENT_LENGTH);
+
+            page.segment_table.push(segment_length as u8);
+            page.body.extend(&remaining[..segment_length]);
+            remaining = &remaining[segment_length..];
+
+            if segment_length < MAX_SEGMENT_LENGTH {
+                break;
+            }
+        }
+    }
+
+    pages.push(page);
+
+    for page in pages.iter_mut() {
+        // header pages have a granule position of 0, unless no packet ends on them
+        if page.segment_table.last() == Some(&(MAX_SEGMENT_LENGTH as u8)) {
+            page.granule_position = NO_GRANULE_POSITION;
+        }
+    }
+
+    return pages;
+}
+
+fn page_to_bytes(page: &OggPage) -> Vec<u8> {
+    let mut page_bytes =
+        Vec::<u8>::with_capacity(PAGE_HEADER_LENGTH + page.segment_table.len() + page.body.len());
+
+    page_bytes.extend(CAPTURE_PATTERN);
+    // stream structure version
+    page_bytes.push(0);
+    page_bytes.push(page.header_type);
+    page_bytes.extend(page.granule_position.to_le_bytes());
+    page_bytes.extend(page.serial_number.to_le_bytes());
+    page_bytes.extend(page.sequence_number.to_le_bytes());
+    // the checksum is computed with its own field set to 0
+    page_bytes.extend([0, 0, 0, 0]);
+    page_bytes.push(page.segment_table.len() as u8);
+    page_bytes.extend(&page.segment_table);
+    page_bytes.extend(&page.body);
+
+    let checksum = get_page_checksum(&page_bytes);
+    page_bytes[22..26].copy_from_slice(&checksum.to_le_bytes());
+
+    return page_bytes;
+}
+
+// crc-32 with polynomial 0x04c11db7, no reflection and no final xor
+fn get_page_checksum(page_bytes: &[u8]) -> u32 {
+    let mut checksum: u32 = 0;
+
+    for byte in page_bytes.iter() {
+        checksum ^= (*byte as u32) << 24;
+
+        for _ in 0..8 {
+            checksum = match checksum & 0x8000_0000 {
+                0 => checksum << 1,
+                _ => (checksum << 1) ^ 0x04c1_1db7,
+            };
+        }
+    }
+
+    return checksum;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_header_packets, page_to_bytes, paginate_packets, parse_pages};
+    use std::path::Path;
+
+    #[test]
+    fn test_paginate_packets() {
+        let fixture_bytes = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tagging/silence.opus"),
+        )
+        .unwrap();
+
+        // pages that are parsed and written again are the same, checksums included
+        let pages = parse_pages(&fixture_bytes).unwrap();
+        let rewritten_bytes: Vec<u8> = pages.iter().flat_map(page_to_bytes).collect();
+        assert_eq!(rewritten_bytes, fixture_bytes);
+
+        let (_, header_packets, header_page_count) = get_header_packets(&pages).unwrap();
+        assert_eq!((header_packets.len(), header_page_count), (2, 2));
+
+        // a packet too large for one page continues on the next, a packet of exactly
+        // 255 bytes ends with an empty segment
+        let packets = vec![vec![7; 255 * 300], vec![8; 255]];
+        let paginated_pages = paginate_packets(&packets, 1, 5);
+
+        assert_eq!(paginated_pages.len(), 2);
+        assert_eq!(paginated_pages[0].granule_position, u64::MAX);
+        assert_eq!(paginated_pages[1].header_type, 1);
+        assert_eq!(paginated_pages[1].sequence_number, 6);
+        assert_eq!(paginated_pages[1].segment_table.len(), 45 + 1 + 2);
+        assert_eq!(paginated_pages[1].segment_table.last(), Some(&0));
+    }
+}
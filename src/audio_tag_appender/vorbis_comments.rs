@@ -0,0 +1,116 @@
+use metaflac::block::{Picture, PictureType, VorbisComment};
+
+use super::{
+    ReplayGain, SongTags, ALBUM_GAIN_KEY, ALBUM_PEAK_KEY, PLAYLIST_URL_KEY, TRACK_GAIN_KEY,
+    TRACK_PEAK_KEY,
+};
+use crate::lyrics_fetcher::Lyrics;
+
+// the source url also gets a key of its own, comments are often edited by hand
+const SOURCE_URL_KEY: &str = "SOURCE_URL";
+
+const JPEG_MIME_TYPE: &str = "image/jpeg";
+
+/// Vorbis comments with the song tags set over the existing ones, used by flac and ogg files
+pub fn with_song_tags(vorbis_comment: VorbisComment, song_tags: &SongTags) -> VorbisComment {
+    let mut vorbis_comment = vorbis_comment;
+
+    let values = [
+        ("TITLE", song_tags.title.to_owned()),
+        ("ARTIST", song_tags.artist.to_owned()),
+        ("GENRE", song_tags.genre.to_owned()),
+        ("ALBUM", song_tags.album.to_owned()),
+        ("ALBUMARTIST", song_tags.album_artist.to_owned()),
+        (
+            "TRACKNUMBER",
+            song_tags.track_number.map(|track| track.to_string()),
+        ),
+        (
+            "TRACKTOTAL",
+            song_tags.total_tracks.map(|total| total.to_string()),
+        ),
+        ("DATE", song_tags.release_date.to_owned()),
+        ("COMMENT", song_tags.source_url.to_owned()),
+        (SOURCE_URL_KEY, song_tags.source_url.to_owned()),
+        (PLAYLIST_URL_KEY, song_tags.playlist_url.to_owned()),
+        ("ISRC", song_tags.isrc.to_owned()),
+        // synchronized lyrics are kept as lrc text, which players show in sync
+        (
+            "LYRICS",
+            song_tags.lyrics.as_ref().map(|lyrics| lyrics.to_lrc()),
+        ),
+    ];
+
+    for (key, value) in values {
+        if let Some(value) = value {
+            vorbis_comment.set(key, vec![value]);
+        }
+    }
+
+    for (key, value) in song_tags.get_replay_gain_texts() {
+        vorbis_comment.set(key, vec![value]);
+    }
+
+    return vorbis_comment;
+}
+
+/// Song tags from the vorbis comments, without the cover art which is stored separately
+pub fn get_song_tags(vorbis_comment: &VorbisComment) -> SongTags {
+    let get_value = |key: &str| -> Option<String> {
+        return vorbis_comment
+            .get(key)
+            .and_then(|values| values.first())
+            .map(|value| value.to_owned());
+    };
+
+    // the track number can be written as "3/12"
+    let get_number = |key: &str| -> Option<u32> {
+        return get_value(key).and_then(|value| value.split('/').next()?.trim().parse().ok());
+    };
+
+    let comment_url = get_value("COMMENT").filter(|comment| comment.starts_with("http"));
+
+    return SongTags {
+        title: get_value("TITLE"),
+        artist: get_value("ARTIST"),
+        genre: get_value("GENRE"),
+        album: get_value("ALBUM"),
+        album_artist: get_value("ALBUMARTIST"),
+        track_number: get_number("TRACKNUMBER"),
+        total_tracks: get_number("TRACKTOTAL").or(get_number("TOTALTRACKS")),
+        release_date: get_value("DATE"),
+        source_url: get_value(SOURCE_URL_KEY).or(comment_url),
+        playlist_url: get_value(PLAYLIST_URL_KEY),
+        isrc: get_value("ISRC"),
+        lyrics: get_value("LYRICS").and_then(|lrc_text| Lyrics::from_lrc(&lrc_text).ok()),
+        cover_art: None,
+        track_replay_gain: ReplayGain::from_texts(
+            get_value(TRACK_GAIN_KEY),
+            get_value(TRACK_PEAK_KEY),
+        ),
+        album_replay_gain: ReplayGain::from_texts(
+            get_value(ALBUM_GAIN_KEY),
+            get_value(ALBUM_PEAK_KEY),
+        ),
+    };
+}
+
+/// Front cover picture block, as flac stores it and ogg stores it base64 encoded
+pub fn get_cover_picture(cover_art: &[u8]) -> Picture {
+    // the dimensions are informational, players decode the image anyway
+    let (width, height) = match image::load_from_memory(cover_art) {
+        Ok(image) => (image.width(), image.height()),
+        Err(_) => (0, 0),
+    };
+
+    return Picture {
+        picture_type: PictureType::CoverFront,
+        mime_type: JPEG_MIME_TYPE.to_string(),
+        description: String::new(),
+        width,
+        height,
+        depth: 24,
+        num_colors: 0,
+        data: cover_art.to_vec(),
+    };
+}
@@ -1,21 +1,46 @@
 use clap::{Args, Parser, Subcommand};
-use rand::{self, Rng};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
 
+use crate::tool_locator::{self, ToolSource};
+use crate::{archiver, environment_initializer, loudness_normalizer, url_enforcer};
 use crate::{
     database::Database,
+    download_error::{DownloadError, RetryPolicy},
     environment_extractor::EnvironmentVariables,
-    music_sources::{get_music_source_from_enum, get_music_source_from_url, MusicSource},
+    library_paths::LibraryPaths,
+    library_rebuilder,
+    lyrics_fetcher::LyricsFetcher,
+    metadata_enricher::MetadataEnricher,
+    music_sources::{
+        get_music_source_from_enum, get_music_source_from_url, DownloadedSong, MusicSource,
+        MusicSources, SongInformation, StoredSongMetadata,
+    },
     post_processor,
+    progress_reporter::ProgressReporter,
+    retagger,
+    settings_parser::Settings,
+    song_override::SongOverride,
+    song_sidecar::{self, SongSidecar},
+    song_storage::SongStorage,
+    title_extractor::{title_rules, EmptyTitleExtractor},
 };
-use crate::{environment_initializer, url_enforcer};
 
 #[derive(Debug, Parser)]
 pub struct App {
     #[clap(subcommand)]
     command: Command,
+    // path of the settings file, defaults to MUSIX_SETTINGS_PATH and then settings/settings.json
+    #[clap(long, global = true)]
+    pub settings: Option<PathBuf>,
+    // directory the library (downloaded, archive and data folders) lives in, defaults to the working directory
+    #[clap(long, global = true)]
+    pub library_root: Option<PathBuf>,
+    // path of the sqlite database, defaults to data/database/sqlite.db in the library root
+    #[clap(long, global = true)]
+    pub db_path: Option<PathBuf>,
 }
 
 //TODO add help for each one
@@ -26,6 +51,26 @@ pub enum Command {
     DeletePlaylist(DeletePlaylistArguments),
     ListPlaylists,
     Run,
+    // download yt-dlp again, at the latest or pinned version
+    UpdateYtDlp,
+    // report the versions of yt-dlp, ffmpeg and ffprobe, and whether they work
+    Doctor,
+    // measure the songs in the library and write their replay gain tags
+    Normalize(NormalizeArguments),
+    // show how a title is extracted, and which title rules matched
+    TestTitle(TestTitleArguments),
+    // set metadata by hand for a song, used instead of the metadata extracted from its source
+    SetMeta(SetMetaArguments),
+    // remove metadata set by hand for a song
+    UnsetMeta(UnsetMetaArguments),
+    // write the tags of downloaded songs again, from their stored metadata and overrides
+    Retag(RetagArguments),
+    // put the songs in the library back into the database from their sidecars and tags
+    RebuildDb(RebuildDbArguments),
+    // upload the songs in the library the storage does not have yet
+    SyncStorage,
+    // forget that songs failed permanently, so the next run tries to download them again
+    RetryFailed(RetryFailedArguments),
 }
 
 #[derive(Debug, Args)]
@@ -38,12 +83,92 @@ pub struct DeletePlaylistArguments {
     playlist_url: String,
 }
 
-pub fn parse_args(
+#[derive(Debug, Args)]
+pub struct NormalizeArguments {
+    // measure songs again even if they already have replay gain tags
+    #[clap(long)]
+    force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TestTitleArguments {
+    // the raw title, as it is uploaded
+    title: String,
+    // channel that uploaded the song, the artist when the title has none
+    #[clap(long, default_value = "")]
+    channel: String,
+    // url of the playlist, for rules scoped to a playlist
+    #[clap(long)]
+    playlist: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct SetMetaArguments {
+    song_url: String,
+    #[clap(long)]
+    title: Option<String>,
+    #[clap(long)]
+    artist: Option<String>,
+    #[clap(long)]
+    genre: Option<String>,
+    #[clap(long)]
+    album: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct UnsetMetaArguments {
+    song_url: String,
+    // the fields to remove, all of them if none is given
+    #[clap(long)]
+    title: bool,
+    #[clap(long)]
+    artist: bool,
+    #[clap(long)]
+    genre: bool,
+    #[clap(long)]
+    album: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RetagArguments {
+    // only the songs of the playlist with this url
+    #[clap(long, required_unless_present = "all", conflicts_with = "all")]
+    playlist: Option<String>,
+    // the songs of every playlist
+    #[clap(long)]
+    all: bool,
+    // also move the songs to the path the file name template gives them now
+    #[clap(long)]
+    rename: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RebuildDbArguments {
+    // playlist of the songs whose sidecars and tags do not name one
+    #[clap(long)]
+    playlist: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RetryFailedArguments {
+    // only the songs of the playlist with this url, the songs of every playlist if not given
+    #[clap(long)]
+    playlist: Option<String>,
+}
+
+/// Parse the command line arguments
+pub fn parse_args() -> App {
+    return App::parse();
+}
+
+/// Execute the parsed command
+pub fn execute_args(
+    args: App,
     database_context: &mut Database,
     environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
 ) -> Result<(), String> {
-    let args = App::parse();
-
     match args.command {
         Command::CreatePlaylist(args) => {
             handle_create_playlist(args, database_context, environment_variables)?
@@ -52,7 +177,44 @@ pub fn parse_args(
             handle_delete_playlist(args, database_context, environment_variables)?
         }
         Command::ListPlaylists => handle_list_playlists(database_context, environment_variables)?,
-        Command::Run => handle_run(database_context, environment_variables)?,
+        Command::Run => handle_run(
+            database_context,
+            environment_variables,
+            settings,
+            library_paths,
+        )?,
+        Command::UpdateYtDlp => handle_update_yt_dlp(settings, library_paths)?,
+        Command::Doctor => handle_doctor(settings, library_paths)?,
+        Command::Normalize(args) => handle_normalize(
+            args,
+            database_context,
+            environment_variables,
+            settings,
+            library_paths,
+        )?,
+        Command::TestTitle(args) => handle_test_title(args, settings)?,
+        Command::SetMeta(args) => handle_set_meta(args, database_context, environment_variables)?,
+        Command::UnsetMeta(args) => {
+            handle_unset_meta(args, database_context, environment_variables)?
+        }
+        Command::Retag(args) => handle_retag(
+            args,
+            database_context,
+            environment_variables,
+            settings,
+            library_paths,
+        )?,
+        Command::RebuildDb(args) => handle_rebuild_db(
+            args,
+            database_context,
+            environment_variables,
+            settings,
+            library_paths,
+        )?,
+        Command::SyncStorage => handle_sync_storage(settings, library_paths)?,
+        Command::RetryFailed(args) => {
+            handle_retry_failed(args, database_context, environment_variables)?
+        }
     }
 
     return Ok(());
@@ -96,10 +258,305 @@ pub fn handle_list_playlists(
     return Ok(());
 }
 
+/// Download yt-dlp again, at the latest or pinned version
+pub fn handle_update_yt_dlp(
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    // only the managed download is ours to update
+    if let Ok(yt_dlp) = tool_locator::locate_yt_dlp(settings.yt_dlp(), library_paths) {
+        if yt_dlp.source != ToolSource::Managed {
+            return Err(format!(
+                "yt-dlp at {} comes from the {}, update it with the tool that installed it",
+                yt_dlp.path.to_string_lossy(),
+                yt_dlp.source
+            ));
+        }
+    }
+
+    if !*settings.yt_dlp().managed() {
+        return Err(
+            "The managed yt-dlp download is not enabled, set yt_dlp.managed in the settings"
+                .to_string(),
+        );
+    }
+
+    return environment_initializer::update_yt_dlp_executable(settings.yt_dlp(), library_paths);
+}
+
+/// Report which yt-dlp, ffmpeg and ffprobe are used, their versions, and whether they work
+pub fn handle_doctor(settings: &Settings, library_paths: &LibraryPaths) -> Result<(), String> {
+    let tools = [
+        (
+            "yt-dlp",
+            tool_locator::locate_yt_dlp(settings.yt_dlp(), library_paths),
+            "--version",
+        ),
+        (
+            "ffmpeg",
+            tool_locator::locate_ffmpeg(settings.ffmpeg()),
+            "-version",
+        ),
+        (
+            "ffprobe",
+            tool_locator::locate_ffprobe(settings.ffmpeg()),
+            "-version",
+        ),
+    ];
+
+    let mut all_tools_work = true;
+
+    for (tool_name, located_tool, version_argument) in tools {
+        let located_tool = match located_tool {
+            Ok(located_tool) => located_tool,
+            Err(e) => {
+                println!("{}: NOT FOUND - {}", tool_name, e);
+                all_tools_work = false;
+                continue;
+            }
+        };
+
+        match tool_locator::get_tool_version(&located_tool.path, version_argument) {
+            Ok(version) => println!(
+                "{}: OK - {} ({}, from {})",
+                tool_name,
+                version,
+                located_tool.path.to_string_lossy(),
+                located_tool.source
+            ),
+            Err(e) => {
+                println!(
+                    "{}: BROKEN - {} ({}, from {})",
+                    tool_name,
+                    e,
+                    located_tool.path.to_string_lossy(),
+                    located_tool.source
+                );
+                all_tools_work = false;
+            }
+        }
+    }
+
+    if !all_tools_work {
+        return Err("Some of the required tools are missing or do not work".to_string());
+    }
+
+    return Ok(());
+}
+
+/// Write replay gain tags to the songs already in the library
+pub fn handle_normalize(
+    args: NormalizeArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    return loudness_normalizer::normalize_library(
+        database_context,
+        environment_variables,
+        settings,
+        library_paths,
+        args.force,
+    );
+}
+
+/// Write the tags of downloaded songs again, and optionally rename them
+pub fn handle_retag(
+    args: RetagArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let song_storage = SongStorage::from_settings(settings)?;
+
+    return retagger::retag_library(
+        database_context,
+        environment_variables,
+        settings,
+        library_paths,
+        song_storage.as_ref(),
+        args.playlist.as_deref(),
+        args.rename,
+    );
+}
+
+/// Put the songs in the library back into the database, for when it is lost
+pub fn handle_rebuild_db(
+    args: RebuildDbArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    return library_rebuilder::rebuild_database(
+        database_context,
+        environment_variables,
+        settings,
+        library_paths,
+        args.playlist.as_deref(),
+    );
+}
+
+/// Reset the songs that failed permanently, ex. after a video was restored or a login was added
+pub fn handle_retry_failed(
+    args: RetryFailedArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+) -> Result<(), String> {
+    let reset_count =
+        database_context.reset_failed_songs(args.playlist.as_deref(), environment_variables)?;
+
+    println!(
+        "{} failed songs will be downloaded again on the next run",
+        reset_count
+    );
+
+    return Ok(());
+}
+
+/// Upload the songs already in the library to the storage, skipping the ones it has
+pub fn handle_sync_storage(
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let song_storage = match SongStorage::from_settings(settings)? {
+        Some(song_storage) => song_storage,
+        None => return Err("No storage is set, set storage.uri in the settings".to_string()),
+    };
+
+    let sync_summary = song_storage.sync_library(library_paths)?;
+
+    println!(
+        "Synced the library to {}, {} uploaded, {} already stored, {} failed",
+        song_storage.name(),
+        sync_summary.uploaded,
+        sync_summary.skipped,
+        sync_summary.failed
+    );
+
+    if sync_summary.failed > 0 {
+        return Err(format!(
+            "{} songs could not be uploaded",
+            sync_summary.failed
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Print the fields extracted from a title, and the title rules that matched
+pub fn handle_test_title(args: TestTitleArguments, settings: &Settings) -> Result<(), String> {
+    let title_rules = title_rules::load_title_rules(settings.titles().rules_path().as_deref())?;
+
+    let finished_title_extractor = EmptyTitleExtractor::init(args.title, args.channel)
+        .extract_with_rules(&title_rules, args.playlist.as_deref())?;
+
+    let matched_rules = match finished_title_extractor.matched_rules().is_empty() {
+        true => "none".to_string(),
+        false => finished_title_extractor.matched_rules().join(", "),
+    };
+
+    println!("Matched rules: {}", matched_rules);
+    println!("Artist: {}", finished_title_extractor.artist());
+    println!("Artists: {}", finished_title_extractor.artists().join(", "));
+    println!(
+        "Featured artists: {}",
+        finished_title_extractor.featured_artists().join(", ")
+    );
+    println!("Title: {}", finished_title_extractor.name());
+    println!(
+        "Versions: {}",
+        finished_title_extractor.versions().join(", ")
+    );
+    println!("Noise: {}", finished_title_extractor.noise().join(", "));
+    println!(
+        "Tagged as: {} - {}",
+        finished_title_extractor.full_artist(),
+        finished_title_extractor.full_title()
+    );
+
+    return Ok(());
+}
+
+/// Set metadata by hand for a song, keeping the fields set before that are not given
+pub fn handle_set_meta(
+    args: SetMetaArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+) -> Result<(), String> {
+    let new_override = SongOverride {
+        title: args.title,
+        artist: args.artist,
+        genre: args.genre,
+        album: args.album,
+    };
+
+    if new_override.is_empty() {
+        return Err(
+            "Nothing to set, give at least one of --title, --artist, --genre or --album"
+                .to_string(),
+        );
+    }
+
+    let song_override = database_context
+        .get_song_override(&args.song_url, environment_variables)?
+        .unwrap_or_default()
+        .merge(new_override);
+
+    database_context.put_song_override(&args.song_url, &song_override, environment_variables)?;
+
+    println!(
+        "Metadata of {} is used the next time it is downloaded or retagged",
+        args.song_url
+    );
+
+    return Ok(());
+}
+
+/// Remove metadata set by hand for a song, all of it if no field is given
+pub fn handle_unset_meta(
+    args: UnsetMetaArguments,
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+) -> Result<(), String> {
+    let song_override =
+        match database_context.get_song_override(&args.song_url, environment_variables)? {
+            Some(song_override) => song_override,
+            None => {
+                println!("{} has no metadata set", args.song_url);
+                return Ok(());
+            }
+        };
+
+    let unset_all = !(args.title || args.artist || args.genre || args.album);
+
+    let song_override = SongOverride {
+        title: song_override.title.filter(|_| !(unset_all || args.title)),
+        artist: song_override.artist.filter(|_| !(unset_all || args.artist)),
+        genre: song_override.genre.filter(|_| !(unset_all || args.genre)),
+        album: song_override.album.filter(|_| !(unset_all || args.album)),
+    };
+
+    match song_override.is_empty() {
+        true => database_context.delete_song_override(&args.song_url, environment_variables)?,
+        false => database_context.put_song_override(
+            &args.song_url,
+            &song_override,
+            environment_variables,
+        )?,
+    };
+
+    return Ok(());
+}
+
 // Handle run, which will attempt to download all the undownloaded songs from all the playlists in the database
 pub fn handle_run(
     database_context: &mut Database,
     environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
 ) -> Result<(), String> {
     // get list of playlists
     let playlists = database_context.get_all_playlists(environment_variables)?;
@@ -116,28 +573,84 @@ pub fn handle_run(
         downloaded_playlist_song_urls.iter().for_each(|song_url| {
             downloaded_song_urls.insert(song_url.to_owned());
         });
+
+        // songs that can never be downloaded are skipped as if they were downloaded
+        let failed_playlist_song_urls = database_context
+            .get_permanently_failed_songs_from_playlist(playlist_url, environment_variables)?;
+
+        failed_playlist_song_urls.iter().for_each(|song_url| {
+            downloaded_song_urls.insert(song_url.to_owned());
+        });
     }
 
-    // downloaded yt-dlp if it does not exist
-    environment_initializer::init_yt_dlp_executable()?;
+    // find yt-dlp, downloading the managed executable if it is the one to use and it does not exist
+    tool_locator::ensure_yt_dlp(settings.yt_dlp(), library_paths)?;
+
+    // where finished songs are uploaded to, if anywhere
+    let song_storage = SongStorage::from_settings(settings)?;
 
     // move any current songs in downloaded folder from last possible session into
-    // the archive folder
-    environment_initializer::move_downloaded_songs_to_archive()?;
+    // a new batch in the archive folder
+    archiver::archive_downloaded_songs(
+        database_context,
+        environment_variables,
+        settings.archive(),
+        song_storage.as_ref(),
+        song_sidecar::get_sidecar_directory(settings, library_paths).as_deref(),
+        library_paths,
+    )?;
+
+    // no run is downloading now, so anything in the temporary directory is left from one that failed
+    environment_initializer::clear_temporary_directory(library_paths)?;
+
+    // downloads run on a single worker
+    let mut progress_reporter = ProgressReporter::new(1);
+
+    // yt-dlp is updated at most once per run
+    let mut yt_dlp_updated = false;
+
+    // sources that kept rate limiting us, their remaining songs are left for the next run
+    let mut paused_sources = HashSet::<MusicSources>::new();
+
+    // a broken rules file should not quietly give every song the default extraction
+    let title_rules = title_rules::load_title_rules(settings.titles().rules_path().as_deref())?;
+
+    // indexes songs are looked up in for their canonical metadata
+    let metadata_enricher = MetadataEnricher::from_settings(settings)?;
+
+    // music sources by type, which carry the rate limiting state of the source
+    let mut music_sources = HashMap::<MusicSources, Box<dyn MusicSource>>::new();
 
     // for every playlist, download the songs that are in the playlist
     // but are not downloaded
     for playlist_url in playlists.iter() {
         // get music source type
         // this is unique for each playlist as a playlist can only have one source type
-        let music_source_type = get_music_source_from_url(&playlist_url)?;
+        let music_source_type = get_music_source_from_url(playlist_url)?;
+
+        // source is paused, leave its playlists for the next run
+        if paused_sources.contains(&music_source_type) {
+            continue;
+        }
 
-        // Create the designated music source
+        // Create the designated music source, once per source type so they share a rate limiter
         // TODO how can it return both but yet be a generic for one?????
-        let music_source: Box<dyn MusicSource> = get_music_source_from_enum(music_source_type);
+        let music_source: &dyn MusicSource =
+            &**music_sources.entry(music_source_type).or_insert_with(|| {
+                get_music_source_from_enum(music_source_type, settings, &title_rules, library_paths)
+            });
+
+        // settings with the playlist's overrides applied
+        let playlist_settings = settings.for_playlist(playlist_url)?;
+
+        // the lyrics folder is read once for the songs of the playlist
+        let lyrics_fetcher = LyricsFetcher::from_settings(playlist_settings.lyrics());
 
         // get songs
-        let playlist_song_urls = music_source.get_playlist_song_information(&playlist_url)?;
+        let playlist_song_urls = music_source.get_playlist_song_information(playlist_url)?;
+
+        // the album gain of the playlist changes when new songs are measured
+        let mut playlist_loudness_changed = false;
 
         // for each song in playlist song ids
         for to_download_song in playlist_song_urls {
@@ -149,10 +662,34 @@ pub fn handle_run(
                 continue;
             }
 
+            // source is paused, do not download or mark the song
+            if paused_sources.contains(&music_source_type) {
+                continue;
+            }
+
+            // metadata set by hand wins over the metadata extracted from the source
+            let song_override =
+                database_context.get_song_override(&song_url, environment_variables)?;
+
+            let overridden_song = match &song_override {
+                Some(song_override) => {
+                    song_override.apply_to_song_information(to_download_song.clone())
+                }
+                None => to_download_song.clone(),
+            };
+
             println!("Downloading {}", song_url);
 
             // download song
-            let downloaded_song_result = music_source.download_song(&to_download_song);
+            let downloaded_song_result = download_song_with_retries(
+                music_source,
+                &overridden_song,
+                &playlist_settings,
+                library_paths,
+                &mut progress_reporter,
+                &mut yt_dlp_updated,
+            );
+
             let downloaded_song = match downloaded_song_result {
                 Ok(song_info) => song_info,
                 Err(e) => {
@@ -161,11 +698,21 @@ pub fn handle_run(
                         song_url, e
                     );
 
+                    if let RetryPolicy::PauseSource { .. } = e.class.retry_policy() {
+                        println!(
+                            "Pausing downloads from {:?} for the rest of the run",
+                            music_source_type
+                        );
+
+                        paused_sources.insert(music_source_type);
+                    }
+
                     // put download song information into databse
                     database_context.put_downloaded_song(
                         song_url.to_owned(),
                         playlist_url.to_owned(),
-                        true,
+                        Some(e.class),
+                        None,
                         environment_variables,
                     )?;
 
@@ -173,22 +720,245 @@ pub fn handle_run(
                 }
             };
 
+            // the extracted metadata is kept, so retagging picks up overrides that change later
+            let song_metadata =
+                StoredSongMetadata::new(&to_download_song, &downloaded_song.download_information);
+
+            let stored_metadata = match serde_json::to_string(&song_metadata) {
+                Ok(stored_metadata) => stored_metadata,
+                Err(e) => return Err(format!("Could not serialize the song metadata: {}", e)),
+            };
+
+            // the source metadata is also kept next to the song, for when the database is lost
+            let song_sidecar = SongSidecar::new(
+                &song_url,
+                playlist_url,
+                &song_metadata,
+                &downloaded_song.download_information,
+            );
+
+            // look the song up once its length is known, metadata set by hand still wins
+            let downloaded_song =
+                match (&metadata_enricher, playlist_settings.enrichment().enabled()) {
+                    (Some(metadata_enricher), true) => {
+                        let enriched_song = metadata_enricher.enrich(
+                            to_download_song.clone(),
+                            downloaded_song.download_information.duration,
+                        );
+
+                        let enriched_song = match &song_override {
+                            Some(song_override) => {
+                                song_override.apply_to_song_information(enriched_song)
+                            }
+                            None => enriched_song,
+                        };
+
+                        DownloadedSong::from_song_information(
+                            &enriched_song,
+                            downloaded_song.file_location,
+                            downloaded_song.download_information,
+                        )
+                    }
+                    _ => downloaded_song,
+                };
+
             // post process song
-            post_processor::post_process_downloaded_song(downloaded_song)?;
+            let processed_song = post_processor::post_process_downloaded_song(
+                downloaded_song,
+                &song_sidecar,
+                song_storage.as_ref(),
+                &lyrics_fetcher,
+                &playlist_settings,
+                library_paths,
+            )?;
+
+            // the tracks of a split mix are stored on their own
+            if !processed_song.split_tracks.is_empty() {
+                database_context.put_downloaded_song(
+                    song_url.to_owned(),
+                    playlist_url.to_owned(),
+                    None,
+                    None,
+                    environment_variables,
+                )?;
+
+                database_context.update_song_metadata(
+                    &song_url,
+                    playlist_url,
+                    &stored_metadata,
+                    environment_variables,
+                )?;
+
+                for split_track in processed_song.split_tracks.iter() {
+                    database_context.put_split_track(
+                        &song_url,
+                        playlist_url,
+                        split_track.track_number,
+                        &library_paths.to_stored_path(&split_track.file_path),
+                        (
+                            split_track.mix_track.start_time,
+                            split_track.mix_track.end_time,
+                        ),
+                        environment_variables,
+                    )?;
+                }
+
+                continue;
+            }
+
+            // a song skipped because another song has its path is stored without a file
+            let stored_file_path = processed_song
+                .file_path
+                .as_ref()
+                .map(|file_path| library_paths.to_stored_path(file_path));
 
             // put download song information into databse
             database_context.put_downloaded_song(
                 song_url.to_owned(),
                 playlist_url.to_owned(),
-                false,
+                None,
+                stored_file_path.to_owned(),
                 environment_variables,
             )?;
 
-            // random sleep so we don't give the music provider sneaky suspicions *__*
-            let sleep_time = rand::rng().random_range(2..7);
-            thread::sleep(Duration::from_secs(sleep_time));
+            database_context.update_song_metadata(
+                &song_url,
+                playlist_url,
+                &stored_metadata,
+                environment_variables,
+            )?;
+
+            let stored_file_path = match stored_file_path {
+                Some(stored_file_path) => stored_file_path,
+                None => continue,
+            };
+
+            if let Some(loudness) = processed_song.loudness {
+                database_context.update_song_loudness(
+                    &stored_file_path,
+                    loudness.loudness,
+                    loudness.true_peak,
+                    environment_variables,
+                )?;
+
+                playlist_loudness_changed = true;
+            }
+
+            if !processed_song.trims.is_empty() {
+                let trims = match serde_json::to_string(&processed_song.trims) {
+                    Ok(trims) => trims,
+                    Err(e) => return Err(format!("Could not serialize the song trims: {}", e)),
+                };
+
+                database_context.update_song_trims(
+                    &stored_file_path,
+                    &trims,
+                    environment_variables,
+                )?;
+            }
+        }
+
+        if playlist_loudness_changed && *playlist_settings.normalization().replay_gain() {
+            loudness_normalizer::update_playlist_album_gain(
+                database_context,
+                environment_variables,
+                playlist_url,
+                playlist_settings.tagging(),
+                library_paths,
+            )?;
         }
     }
 
     return Ok(());
 }
+
+// Download a song, retrying failures according to the retry policy of their error class
+fn download_song_with_retries(
+    music_source: &dyn MusicSource,
+    song_information: &SongInformation,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+    progress_reporter: &mut ProgressReporter,
+    yt_dlp_updated: &mut bool,
+) -> Result<DownloadedSong, DownloadError> {
+    let mut retries: u32 = 0;
+
+    loop {
+        let download_result =
+            music_source.download_song(song_information, settings, &mut |event| {
+                progress_reporter.report(0, event)
+            });
+
+        progress_reporter.end_block();
+
+        let download_error = match download_result {
+            Ok(downloaded_song) => return Ok(downloaded_song),
+            Err(e) => e,
+        };
+
+        let wait = match download_error.class.retry_policy() {
+            RetryPolicy::Never => return Err(download_error),
+            RetryPolicy::Backoff {
+                max_retries,
+                backoff,
+            } => {
+                if retries >= max_retries {
+                    return Err(download_error);
+                }
+
+                // exponential backoff
+                backoff * 2u32.pow(retries)
+            }
+            RetryPolicy::PauseSource { wait } => {
+                if retries >= 1 {
+                    return Err(download_error);
+                }
+
+                wait
+            }
+            RetryPolicy::UpdateAndRetry => {
+                // an update already happened, or is not allowed, so retrying will not help
+                let yt_dlp_settings = settings.yt_dlp();
+                let yt_dlp_is_managed =
+                    match tool_locator::locate_yt_dlp(yt_dlp_settings, library_paths) {
+                        Ok(yt_dlp) => yt_dlp.source == ToolSource::Managed,
+                        Err(_) => false,
+                    };
+
+                if *yt_dlp_updated
+                    || !yt_dlp_is_managed
+                    || !*yt_dlp_settings.auto_update()
+                    || yt_dlp_settings.version().is_some()
+                {
+                    return Err(download_error);
+                }
+
+                println!("yt-dlp looks out of date, updating it");
+
+                *yt_dlp_updated = true;
+
+                if let Err(e) = environment_initializer::update_yt_dlp_executable(
+                    yt_dlp_settings,
+                    library_paths,
+                ) {
+                    println!("Could not update yt-dlp: {}", e);
+
+                    return Err(download_error);
+                }
+
+                Duration::from_secs(0)
+            }
+        };
+
+        println!(
+            "Download of {} failed with {}, retrying in {} seconds",
+            song_information.url,
+            download_error.class.as_str(),
+            wait.as_secs()
+        );
+
+        thread::sleep(wait);
+
+        retries += 1;
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{Cursor, Read};
+
+use image::imageops::FilterType;
+
+// thumbnails are small, anything this big is not an image
+const MAX_ARTWORK_BYTES: u64 = 20 * 1024 * 1024;
+
+const JPEG_QUALITY: u8 = 90;
+
+/// Download the artwork and turn it into a square jpeg of the given size
+pub fn fetch_cover_art(artwork_url: &str, size: u32) -> Result<Vec<u8>, String> {
+    let response = match ureq::get(artwork_url).call() {
+        Ok(response) => response,
+        Err(e) => {
+            return Err(format!(
+                "Error making request to download artwork {}: {}",
+                artwork_url, e
+            ))
+        }
+    };
+
+    let mut artwork_bytes = Vec::<u8>::new();
+
+    match response
+        .into_reader()
+        .take(MAX_ARTWORK_BYTES)
+        .read_to_end(&mut artwork_bytes)
+    {
+        Ok(_) => (),
+        Err(e) => return Err(format!("Could not read artwork {}: {}", artwork_url, e)),
+    };
+
+    return make_square_jpeg(&artwork_bytes, size);
+}
+
+/// Crop the image to a centered square, resize it and encode it as a jpeg
+pub fn make_square_jpeg(image_bytes: &[u8], size: u32) -> Result<Vec<u8>, String> {
+    let image = match image::load_from_memory(image_bytes) {
+        Ok(image) => image,
+        Err(e) => return Err(format!("Could not decode artwork: {}", e)),
+    };
+
+    // video thumbnails are 16:9, keep the middle of them
+    let side = image.width().min(image.height());
+    let x = (image.width() - side) / 2;
+    let y = (image.height() - side) / 2;
+
+    let square_image = image
+        .crop_imm(x, y, side, side)
+        .resize_exact(size, size, FilterType::Lanczos3)
+        // jpegs have no alpha channel
+        .into_rgb8();
+
+    let mut jpeg_bytes = Cursor::new(Vec::<u8>::new());
+
+    let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg_bytes, JPEG_QUALITY);
+
+    match square_image.write_with_encoder(encoder) {
+        Ok(_) => (),
+        Err(e) => return Err(format!("Could not encode artwork as jpeg: {}", e)),
+    };
+
+    return Ok(jpeg_bytes.into_inner());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::make_square_jpeg;
+    use image::{ImageFormat, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_make_square_jpeg() {
+        // a 16:9 png with transparency
+        let mut png_bytes = Cursor::new(Vec::<u8>::new());
+        RgbaImage::new(64, 36)
+            .write_to(&mut png_bytes, ImageFormat::Png)
+            .unwrap();
+
+        let jpeg_bytes = make_square_jpeg(png_bytes.get_ref(), 20).unwrap();
+
+        let jpeg_image = image::load_from_memory(&jpeg_bytes).unwrap();
+        assert_eq!(image::guess_format(&jpeg_bytes).unwrap(), ImageFormat::Jpeg);
+        assert_eq!((jpeg_image.width(), jpeg_image.height()), (20, 20));
+    }
+}
@@ -1,5 +1,10 @@
+use crate::database_sync::DatabaseSync;
+use crate::download_error::DownloadErrorClass;
 use crate::environment_extractor::EnvironmentVariables;
+use crate::library_paths::LibraryPaths;
+use crate::song_override::SongOverride;
 use rusqlite::{self, params};
+use std::path::PathBuf;
 
 pub struct Database {
     state: DatabaseState,
@@ -10,22 +15,46 @@ pub enum DatabaseState {
     InitializedDatabase(InitializedDatabase),
 }
 
-#[derive(Default)]
-pub struct UninitializedDatabase {}
+pub struct UninitializedDatabase {
+    database_path: PathBuf,
+    // the database is downloaded when it is first used, None if it is only kept locally
+    database_sync: Option<DatabaseSync>,
+}
 
 pub struct InitializedDatabase {
     connection: rusqlite::Connection,
+    database_path: PathBuf,
+    database_sync: Option<DatabaseSync>,
+}
+
+/// A downloaded song that has a file in the library
+pub struct LibrarySong {
+    pub song_url: String,
+    pub playlist_url: String,
+    // relative to the library root
+    pub file_path: String,
+    // None for songs downloaded before their metadata was stored
+    pub metadata: Option<String>,
 }
 
-impl Default for Database {
-    fn default() -> Self {
+impl Database {
+    pub fn new(library_paths: &LibraryPaths, database_sync: Option<DatabaseSync>) -> Self {
         return Database {
-            state: DatabaseState::UninitializedDatabase(UninitializedDatabase::default()),
+            state: DatabaseState::UninitializedDatabase(UninitializedDatabase {
+                database_path: library_paths.database_path().to_path_buf(),
+                database_sync,
+            }),
+        };
+    }
+
+    /// Close the database, uploading it if it is synced and was used
+    pub fn close(self) -> Result<(), String> {
+        return match self.state {
+            DatabaseState::UninitializedDatabase(_) => Ok(()),
+            DatabaseState::InitializedDatabase(state) => state.close(),
         };
     }
-}
 
-impl Database {
     /// Initialize the database if the database has not been initialized
     fn initialize_if_required(
         &mut self,
@@ -69,16 +98,169 @@ impl Database {
         return initialzied_database.get_downloaded_songs_from_playlist(playlist_url);
     }
 
+    pub fn get_permanently_failed_songs_from_playlist(
+        &mut self,
+        playlist_url: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Vec<String>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_permanently_failed_songs_from_playlist(playlist_url);
+    }
+
+    pub fn reset_failed_songs(
+        &mut self,
+        playlist_url: Option<&str>,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<usize, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.reset_failed_songs(playlist_url);
+    }
+
     pub fn put_downloaded_song(
         &mut self,
-        playlist_url: String,
         song_url: String,
-        failed: bool,
+        playlist_url: String,
+        failure_class: Option<DownloadErrorClass>,
+        file_path: Option<String>,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.put_downloaded_song(
+            song_url,
+            playlist_url,
+            failure_class,
+            file_path,
+        );
+    }
+
+    pub fn put_split_track(
+        &mut self,
+        song_url: &str,
+        playlist_url: &str,
+        track_number: u32,
+        file_path: &str,
+        time_range: (f64, Option<f64>),
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.put_split_track(
+            song_url,
+            playlist_url,
+            track_number,
+            file_path,
+            time_range,
+        );
+    }
+
+    pub fn update_song_file_path(
+        &mut self,
+        old_file_path: &str,
+        new_file_path: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.update_song_file_path(old_file_path, new_file_path);
+    }
+
+    pub fn update_song_loudness(
+        &mut self,
+        file_path: &str,
+        loudness: f64,
+        true_peak: f64,
         environment_variables: &EnvironmentVariables,
     ) -> Result<(), String> {
         let initialzied_database = self.get_initialized_state_always(environment_variables)?;
 
-        return initialzied_database.put_downloaded_song(playlist_url, song_url, failed);
+        return initialzied_database.update_song_loudness(file_path, loudness, true_peak);
+    }
+
+    pub fn update_song_trims(
+        &mut self,
+        file_path: &str,
+        trims: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.update_song_trims(file_path, trims);
+    }
+
+    pub fn update_song_metadata(
+        &mut self,
+        song_url: &str,
+        playlist_url: &str,
+        metadata: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.update_song_metadata(song_url, playlist_url, metadata);
+    }
+
+    pub fn get_library_songs(
+        &mut self,
+        playlist_url: Option<&str>,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Vec<LibrarySong>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_library_songs(playlist_url);
+    }
+
+    pub fn get_split_mix_urls(
+        &mut self,
+        playlist_url: Option<&str>,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Vec<String>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_split_mix_urls(playlist_url);
+    }
+
+    pub fn get_playlist_song_loudness(
+        &mut self,
+        playlist_url: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Vec<(String, f64, f64)>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_playlist_song_loudness(playlist_url);
+    }
+
+    pub fn put_archive_batch(
+        &mut self,
+        batch_name: &str,
+        archived_at: i64,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.put_archive_batch(batch_name, archived_at);
+    }
+
+    pub fn get_archive_batches(
+        &mut self,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Vec<(String, i64)>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_archive_batches();
+    }
+
+    pub fn delete_archive_batch(
+        &mut self,
+        batch_name: &str,
+        batch_file_path_prefix: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.delete_archive_batch(batch_name, batch_file_path_prefix);
     }
 
     pub fn put_playlist(
@@ -109,20 +291,59 @@ impl Database {
 
         return initialzied_database.get_all_playlists();
     }
+
+    pub fn get_song_override(
+        &mut self,
+        song_url: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<Option<SongOverride>, String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.get_song_override(song_url);
+    }
+
+    pub fn put_song_override(
+        &mut self,
+        song_url: &str,
+        song_override: &SongOverride,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.put_song_override(song_url, song_override);
+    }
+
+    pub fn delete_song_override(
+        &mut self,
+        song_url: &str,
+        environment_variables: &EnvironmentVariables,
+    ) -> Result<(), String> {
+        let initialzied_database = self.get_initialized_state_always(environment_variables)?;
+
+        return initialzied_database.delete_song_override(song_url);
+    }
 }
 
 impl InitializedDatabase {
     /// Create an InitializedDatabase from a UnintializedDatabase
     pub fn new(
-        _: &mut UninitializedDatabase,
+        uninitialized_database: &mut UninitializedDatabase,
         _environment_variables: &EnvironmentVariables,
     ) -> Result<InitializedDatabase, String> {
+        // lock and download the database before it is opened
+        let mut database_sync = uninitialized_database.database_sync.take();
+
+        if let Some(database_sync) = &mut database_sync {
+            database_sync.pull(&uninitialized_database.database_path)?;
+        }
+
         //initialize the connection
-        let connection = match rusqlite::Connection::open("data/database/sqlite.db") {
+        let connection = match rusqlite::Connection::open(&uninitialized_database.database_path) {
             Ok(conn) => conn,
             Err(e) => {
                 return Err(format!(
-                    "Could not open connection to local sqlite database: {}",
+                    "Could not open connection to local sqlite database {}: {}",
+                    uninitialized_database.database_path.to_string_lossy(),
                     e
                 ));
             }
@@ -133,7 +354,13 @@ impl InitializedDatabase {
             "CREATE TABLE IF NOT EXISTS playlists (playlist_url VARCHAR(11))",
             "CREATE TABLE IF NOT EXISTS downloaded_songs (song_url VARCHAR(11), playlist_url VARCHAR(11), failed BOOLEAN)",
             "CREATE UNIQUE INDEX IF NOT EXISTS playlists_playlists_id_index ON playlists (playlist_url)",
-            "CREATE UNIQUE INDEX IF NOT EXISTS downloaded_songs_song_url ON downloaded_songs (song_url, playlist_url)"
+            "CREATE UNIQUE INDEX IF NOT EXISTS downloaded_songs_song_url ON downloaded_songs (song_url, playlist_url)",
+            "CREATE TABLE IF NOT EXISTS archive_batches (batch_name TEXT PRIMARY KEY, archived_at INTEGER)",
+            // the tracks a mix was split into, times are in seconds of the mix
+            "CREATE TABLE IF NOT EXISTS split_tracks (song_url TEXT, playlist_url TEXT, track_number INTEGER, file_path TEXT, start_time REAL, end_time REAL)",
+            "CREATE UNIQUE INDEX IF NOT EXISTS split_tracks_track_number ON split_tracks (song_url, playlist_url, track_number)",
+            // metadata set by hand, a null field keeps the extracted metadata
+            "CREATE TABLE IF NOT EXISTS song_overrides (song_url TEXT PRIMARY KEY, title TEXT, artist TEXT, genre TEXT, album TEXT)"
         ];
 
         //for each create table query
@@ -152,7 +379,45 @@ impl InitializedDatabase {
             }
         }
 
-        return Ok(InitializedDatabase { connection });
+        // add columns introduced after the tables were first created
+        let added_columns = [
+            ("downloaded_songs", "failure_class", "TEXT"),
+            // relative to the library root
+            ("downloaded_songs", "file_path", "TEXT"),
+            // integrated loudness in LUFS and true peak in dBTP of the file
+            ("downloaded_songs", "loudness", "REAL"),
+            ("downloaded_songs", "true_peak", "REAL"),
+            // json list of the parts cut from the start and end of the song
+            ("downloaded_songs", "trims", "TEXT"),
+            // json of the metadata extracted from the source, to retag the song later
+            ("downloaded_songs", "metadata", "TEXT"),
+        ];
+
+        for (table, column, definition) in added_columns.iter() {
+            add_column_if_missing(&connection, table, column, definition)?;
+        }
+
+        return Ok(InitializedDatabase {
+            connection,
+            database_path: uninitialized_database.database_path.to_owned(),
+            database_sync,
+        });
+    }
+
+    /// Close the connection, then upload the database and unlock it if it is synced
+    pub fn close(self) -> Result<(), String> {
+        if let Err((_, e)) = self.connection.close() {
+            return Err(format!(
+                "Could not close database {}: {}",
+                self.database_path.to_string_lossy(),
+                e
+            ));
+        }
+
+        return match self.database_sync {
+            Some(mut database_sync) => database_sync.push(&self.database_path),
+            None => Ok(()),
+        };
     }
 
     pub fn get_downloaded_songs_from_playlist(
@@ -202,22 +467,103 @@ impl InitializedDatabase {
         return Ok(song_urls);
     }
 
-    /// Put downloaded song information into database
-    ///   If already exists, will silently ignore
+    /// Get the songs of the playlist that failed in a way that should never be retried
+    pub fn get_permanently_failed_songs_from_playlist(
+        &self,
+        playlist_url: &str,
+    ) -> Result<Vec<String>, String> {
+        //create query
+        let query = "SELECT song_url, failure_class FROM downloaded_songs WHERE playlist_url = ?1 AND failed = True";
+
+        let permanent_classes = DownloadErrorClass::permanent_classes()
+            .iter()
+            .map(|class| class.as_str())
+            .collect::<Vec<&str>>();
+
+        //list of song urls
+        let mut song_urls: Vec<String> = Vec::new();
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in get permanently failed songs : {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let songs = match statement.query_map(params![playlist_url], |row| {
+            let song_url: String = row.get(0)?;
+            let failure_class: Option<String> = row.get(1)?;
+            Ok((song_url, failure_class))
+        }) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in get permanently failed songs: {}: {}", query, e));
+            }
+        };
+
+        for song_result in songs {
+            let (song_url, failure_class) = match song_result {
+                Ok(some) => some,
+                Err(e) => {
+                    return Err(format!("Error fetching a row for prepared statement {} in get permanently failed songs: {}", query, e));
+                }
+            };
+
+            // songs that failed before failures were classified are retried
+            if let Some(failure_class) = failure_class {
+                if permanent_classes.contains(&failure_class.as_str()) {
+                    song_urls.push(song_url);
+                }
+            }
+        }
+
+        return Ok(song_urls);
+    }
+
+    /// Forget why the failed songs of the playlist, or of every playlist, failed, so the next run
+    /// downloads them again. Returns the number of songs reset
+    pub fn reset_failed_songs(&self, playlist_url: Option<&str>) -> Result<usize, String> {
+        let query = "UPDATE downloaded_songs SET failure_class = NULL WHERE failed = True AND failure_class IS NOT NULL AND (?1 IS NULL OR playlist_url = ?1)";
+
+        return match self.connection.execute(query, params![playlist_url]) {
+            Ok(reset_count) => Ok(reset_count),
+            Err(e) => Err(format!(
+                "Could not execute reset failed songs query: {}: {}",
+                query, e
+            )),
+        };
+    }
+
+    /// Put downloaded song information into database, a failure class marks the download as failed
+    ///   If already exists, will update the failure and file path
     pub fn put_downloaded_song(
         &self,
-        playlist_url: String,
         song_url: String,
-        failed: bool,
+        playlist_url: String,
+        failure_class: Option<DownloadErrorClass>,
+        file_path: Option<String>,
     ) -> Result<(), String> {
+        if let Some(file_path) = &file_path {
+            self.release_file_path(file_path)?;
+        }
+
         //create query
         let query =
-            "INSERT INTO downloaded_songs VALUES (?1, ?2, ?3) ON CONFLICT DO UPDATE SET failed = (?4)";
+            "INSERT INTO downloaded_songs (song_url, playlist_url, failed, failure_class, file_path) VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT DO UPDATE SET failed = (?3), failure_class = (?4), file_path = (?5)";
+
+        let failed = failure_class.is_some();
+        let failure_class = failure_class.map(|class| class.as_str());
 
         // execute statement
-        let statement_result = self
-            .connection
-            .execute(query, params![playlist_url, song_url, failed, failed]);
+        let statement_result = self.connection.execute(
+            query,
+            params![song_url, playlist_url, failed, failure_class, file_path],
+        );
 
         //execute query, parse result
         match statement_result {
@@ -233,6 +579,417 @@ impl InitializedDatabase {
         return Ok(());
     }
 
+    /// Put a track a mix was split into, replacing the track with the same number
+    pub fn put_split_track(
+        &self,
+        song_url: &str,
+        playlist_url: &str,
+        track_number: u32,
+        file_path: &str,
+        time_range: (f64, Option<f64>),
+    ) -> Result<(), String> {
+        self.release_file_path(file_path)?;
+
+        let query = "INSERT OR REPLACE INTO split_tracks (song_url, playlist_url, track_number, file_path, start_time, end_time) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+
+        let (start_time, end_time) = time_range;
+
+        match self.connection.execute(
+            query,
+            params![
+                song_url,
+                playlist_url,
+                track_number,
+                file_path,
+                start_time,
+                end_time
+            ],
+        ) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute put split track query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    // the song or split track stored at the file path loses it, when a song overwrote its file
+    fn release_file_path(&self, file_path: &str) -> Result<(), String> {
+        let queries = [
+            "UPDATE downloaded_songs SET file_path = NULL WHERE file_path = ?1",
+            "DELETE FROM split_tracks WHERE file_path = ?1",
+        ];
+
+        for query in queries {
+            match self.connection.execute(query, params![file_path]) {
+                Ok(_) => (),
+                Err(e) => {
+                    return Err(format!(
+                        "Could not execute release file path query: {}: {}",
+                        query, e
+                    ));
+                }
+            };
+        }
+
+        return Ok(());
+    }
+
+    /// Point the songs and split tracks stored at the old file path to the new file path
+    pub fn update_song_file_path(
+        &self,
+        old_file_path: &str,
+        new_file_path: &str,
+    ) -> Result<(), String> {
+        let queries = [
+            "UPDATE downloaded_songs SET file_path = ?2 WHERE file_path = ?1",
+            "UPDATE split_tracks SET file_path = ?2 WHERE file_path = ?1",
+        ];
+
+        for query in queries {
+            match self
+                .connection
+                .execute(query, params![old_file_path, new_file_path])
+            {
+                Ok(_) => (),
+                Err(e) => {
+                    return Err(format!(
+                        "Could not execute update song file path query: {}: {}",
+                        query, e
+                    ));
+                }
+            };
+        }
+
+        return Ok(());
+    }
+
+    /// Set the loudness of the song stored at the file path
+    pub fn update_song_loudness(
+        &self,
+        file_path: &str,
+        loudness: f64,
+        true_peak: f64,
+    ) -> Result<(), String> {
+        let query =
+            "UPDATE downloaded_songs SET loudness = ?2, true_peak = ?3 WHERE file_path = ?1";
+
+        match self
+            .connection
+            .execute(query, params![file_path, loudness, true_peak])
+        {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute update song loudness query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Set the trims applied to the song stored at the file path
+    pub fn update_song_trims(&self, file_path: &str, trims: &str) -> Result<(), String> {
+        let query = "UPDATE downloaded_songs SET trims = ?2 WHERE file_path = ?1";
+
+        match self.connection.execute(query, params![file_path, trims]) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute update song trims query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Set the metadata the song of the playlist was downloaded with
+    pub fn update_song_metadata(
+        &self,
+        song_url: &str,
+        playlist_url: &str,
+        metadata: &str,
+    ) -> Result<(), String> {
+        let query =
+            "UPDATE downloaded_songs SET metadata = ?3 WHERE song_url = ?1 AND playlist_url = ?2";
+
+        match self
+            .connection
+            .execute(query, params![song_url, playlist_url, metadata])
+        {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute update song metadata query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Get the downloaded songs that have a file, of one playlist or of every playlist
+    pub fn get_library_songs(
+        &self,
+        playlist_url: Option<&str>,
+    ) -> Result<Vec<LibrarySong>, String> {
+        let query = "SELECT song_url, playlist_url, file_path, metadata FROM downloaded_songs WHERE (?1 IS NULL OR playlist_url = ?1) AND failed = False AND file_path IS NOT NULL";
+
+        let mut library_songs: Vec<LibrarySong> = Vec::new();
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in get library songs: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let song_results = match statement.query_map(params![playlist_url], |row| {
+            Ok(LibrarySong {
+                song_url: row.get(0)?,
+                playlist_url: row.get(1)?,
+                file_path: row.get(2)?,
+                metadata: row.get(3)?,
+            })
+        }) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in get library songs: {}: {}", query, e));
+            }
+        };
+
+        for song_result in song_results {
+            let library_song = match song_result {
+                Ok(some) => some,
+                Err(e) => {
+                    return Err(format!(
+                        "Error fetching a row for prepared statement {} in get library songs: {}",
+                        query, e
+                    ));
+                }
+            };
+
+            library_songs.push(library_song);
+        }
+
+        return Ok(library_songs);
+    }
+
+    /// Get the urls of the split mixes that have tracks in the library, of one playlist or of
+    /// every playlist
+    pub fn get_split_mix_urls(&self, playlist_url: Option<&str>) -> Result<Vec<String>, String> {
+        let query = "SELECT DISTINCT song_url FROM split_tracks WHERE (?1 IS NULL OR playlist_url = ?1) AND file_path IS NOT NULL ORDER BY song_url";
+
+        let mut split_mix_urls: Vec<String> = Vec::new();
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in get split mix urls: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let url_results = match statement.query_map(params![playlist_url], |row| row.get(0)) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in get split mix urls: {}: {}", query, e));
+            }
+        };
+
+        for url_result in url_results {
+            let split_mix_url = match url_result {
+                Ok(some) => some,
+                Err(e) => {
+                    return Err(format!(
+                        "Error fetching a row for prepared statement {} in get split mix urls: {}",
+                        query, e
+                    ));
+                }
+            };
+
+            split_mix_urls.push(split_mix_url);
+        }
+
+        return Ok(split_mix_urls);
+    }
+
+    /// Get the (file path, loudness, true peak) of the songs of the playlist that have a file and were measured
+    pub fn get_playlist_song_loudness(
+        &self,
+        playlist_url: &str,
+    ) -> Result<Vec<(String, f64, f64)>, String> {
+        let query = "SELECT file_path, loudness, true_peak FROM downloaded_songs WHERE playlist_url = ?1 AND file_path IS NOT NULL AND loudness IS NOT NULL AND true_peak IS NOT NULL";
+
+        let mut song_loudness: Vec<(String, f64, f64)> = Vec::new();
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in get playlist song loudness: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let loudness_results = match statement.query_map(params![playlist_url], |row| {
+            let file_path: String = row.get(0)?;
+            let loudness: f64 = row.get(1)?;
+            let true_peak: f64 = row.get(2)?;
+
+            Ok((file_path, loudness, true_peak))
+        }) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in get playlist song loudness: {}: {}", query, e));
+            }
+        };
+
+        for loudness_result in loudness_results {
+            let loudness = match loudness_result {
+                Ok(some) => some,
+                Err(e) => {
+                    return Err(format!("Error fetching a row for prepared statement {} in get playlist song loudness: {}", query, e));
+                }
+            };
+
+            song_loudness.push(loudness);
+        }
+
+        return Ok(song_loudness);
+    }
+
+    /// Put an archive batch into the index of archive batches
+    pub fn put_archive_batch(&self, batch_name: &str, archived_at: i64) -> Result<(), String> {
+        let query =
+            "INSERT OR REPLACE INTO archive_batches (batch_name, archived_at) VALUES (?1, ?2)";
+
+        match self
+            .connection
+            .execute(query, params![batch_name, archived_at])
+        {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute put archive batch query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Get the (batch name, archived at) of every archive batch, oldest first
+    pub fn get_archive_batches(&self) -> Result<Vec<(String, i64)>, String> {
+        let query = "SELECT batch_name, archived_at FROM archive_batches ORDER BY archived_at ASC";
+
+        let mut archive_batches: Vec<(String, i64)> = Vec::new();
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in get archive batches: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let batch_results = match statement.query_map([], |row| {
+            let batch_name: String = row.get(0)?;
+            let archived_at: i64 = row.get(1)?;
+
+            Ok((batch_name, archived_at))
+        }) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in get archive batches: {}: {}", query, e));
+            }
+        };
+
+        for batch_result in batch_results {
+            let archive_batch = match batch_result {
+                Ok(some) => some,
+                Err(e) => {
+                    return Err(format!(
+                        "Error fetching a row for prepared statement {} in get archive batches: {}",
+                        query, e
+                    ));
+                }
+            };
+
+            archive_batches.push(archive_batch);
+        }
+
+        return Ok(archive_batches);
+    }
+
+    /// Remove an archive batch from the index, the songs in it no longer have a file
+    pub fn delete_archive_batch(
+        &self,
+        batch_name: &str,
+        batch_file_path_prefix: &str,
+    ) -> Result<(), String> {
+        // the songs stay downloaded, so they are not downloaded again
+        let queries = [
+            "UPDATE downloaded_songs SET file_path = NULL WHERE substr(file_path, 1, length(?1)) = ?1",
+            "UPDATE split_tracks SET file_path = NULL WHERE substr(file_path, 1, length(?1)) = ?1",
+        ];
+
+        for query in queries {
+            match self
+                .connection
+                .execute(query, params![batch_file_path_prefix])
+            {
+                Ok(_) => (),
+                Err(e) => {
+                    return Err(format!(
+                        "Could not execute clear archived file paths query: {}: {}",
+                        query, e
+                    ));
+                }
+            };
+        }
+
+        let query = "DELETE FROM archive_batches WHERE batch_name = ?1";
+
+        match self.connection.execute(query, params![batch_name]) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute delete archive batch query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
     /// Put playlist information into database
     ///   If already exists, will silently ignore
     pub fn put_playlist(&self, playlist_url: String) -> Result<(), String> {
@@ -333,4 +1090,112 @@ impl InitializedDatabase {
 
         return Ok(playlists);
     }
+
+    /// Get the metadata set by hand for the song, None if nothing was set
+    pub fn get_song_override(&self, song_url: &str) -> Result<Option<SongOverride>, String> {
+        let query = "SELECT title, artist, genre, album FROM song_overrides WHERE song_url = ?1";
+
+        let song_override = self.connection.query_row(query, params![song_url], |row| {
+            Ok(SongOverride {
+                title: row.get(0)?,
+                artist: row.get(1)?,
+                genre: row.get(2)?,
+                album: row.get(3)?,
+            })
+        });
+
+        return match song_override {
+            Ok(song_override) => Ok(Some(song_override)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(format!(
+                "Could not execute get song override query: {}: {}",
+                query, e
+            )),
+        };
+    }
+
+    /// Put the metadata set by hand for the song, replacing the previous override
+    pub fn put_song_override(
+        &self,
+        song_url: &str,
+        song_override: &SongOverride,
+    ) -> Result<(), String> {
+        let query = "INSERT OR REPLACE INTO song_overrides (song_url, title, artist, genre, album) VALUES (?1, ?2, ?3, ?4, ?5)";
+
+        match self.connection.execute(
+            query,
+            params![
+                song_url,
+                song_override.title,
+                song_override.artist,
+                song_override.genre,
+                song_override.album
+            ],
+        ) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute put song override query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Remove the metadata set by hand for the song, does nothing if there is none
+    pub fn delete_song_override(&self, song_url: &str) -> Result<(), String> {
+        let query = "DELETE FROM song_overrides WHERE song_url = ?1";
+
+        match self.connection.execute(query, params![song_url]) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not execute delete song override query: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        return Ok(());
+    }
+}
+
+/// Add a column to a table if the table does not have it yet,
+/// so databases created by older versions pick up new columns
+fn add_column_if_missing(
+    connection: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), String> {
+    let query = format!(
+        "SELECT COUNT(*) FROM pragma_table_info('{}') WHERE name = ?1",
+        table
+    );
+
+    let column_count: i64 = match connection.query_row(&query, params![column], |row| row.get(0)) {
+        Ok(count) => count,
+        Err(e) => {
+            return Err(format!(
+                "Could not check if column {} exists in table {}: {}",
+                column, table, e
+            ));
+        }
+    };
+
+    if column_count > 0 {
+        return Ok(());
+    }
+
+    let query = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+
+    return match connection.execute(&query, params![]) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!(
+            "Could not add column {} to table {}: {}",
+            column, table, e
+        )),
+    };
 }
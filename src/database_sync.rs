@@ -0,0 +1,484 @@
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::environment_extractor::EnvironmentVariables;
+use crate::s3_client::{S3Client, S3Uri, WriteCondition};
+use crate::settings_parser::Settings;
+use crate::song_storage;
+
+// the lock object is next to the database, ex. "sqlite.db.lock"
+const LOCK_SUFFIX: &str = ".lock";
+
+const SECONDS_PER_MINUTE: i64 = 60;
+
+/// Keeps the database in an s3 bucket, downloading it before it is opened and uploading it after
+/// the command. A lock object next to it stops two machines from using it at once, and the upload
+/// only replaces the database it downloaded, so a run whose lock was taken over can not clobber it
+pub struct DatabaseSync {
+    s3_client: S3Client,
+    database_uri: S3Uri,
+    lock_uri: S3Uri,
+    lock_ttl_minutes: u64,
+    // identifies this run in the lock
+    owner: String,
+    // etag of the lock object while it is held
+    lock_etag: Option<String>,
+    // etag of the database as it was downloaded, None if the bucket had none
+    database_etag: Option<String>,
+    // sha256 of the database as it was downloaded, a database the command did not change is not
+    // uploaded again
+    database_hash: Option<String>,
+}
+
+/// Contents of the lock object
+#[derive(Debug, Serialize, Deserialize)]
+struct DatabaseLock {
+    owner: String,
+    host: String,
+    // unix seconds
+    acquired_at: i64,
+    expires_at: i64,
+}
+
+impl DatabaseSync {
+    /// The database sync of the s3 uri in the environment or settings, None if neither sets one
+    pub fn from_settings(
+        environment_variables: &EnvironmentVariables,
+        settings: &Settings,
+    ) -> Result<Option<DatabaseSync>, String> {
+        let database_s3_uri = match environment_variables
+            .get_database_s3_uri()
+            .as_ref()
+            .or(settings.library().database_s3_uri().as_ref())
+        {
+            Some(database_s3_uri) => database_s3_uri,
+            None => return Ok(None),
+        };
+
+        let database_uri = S3Uri::parse(database_s3_uri)?;
+
+        let lock_uri = S3Uri {
+            bucket: database_uri.bucket.to_owned(),
+            key: format!("{}{}", database_uri.key, LOCK_SUFFIX),
+        };
+
+        return Ok(Some(DatabaseSync {
+            s3_client: S3Client::new(settings.s3())?,
+            database_uri,
+            lock_uri,
+            lock_ttl_minutes: *settings.library().database_lock_ttl_minutes(),
+            owner: uuid::Uuid::new_v4().to_string(),
+            lock_etag: None,
+            database_etag: None,
+            database_hash: None,
+        }));
+    }
+
+    /// Lock the database and download it over the local one, the local database is kept when the
+    /// bucket has none yet, and is uploaded as the first one
+    pub fn pull(&mut self, database_path: &Path) -> Result<(), String> {
+        self.acquire_lock()?;
+
+        let database_object = match self.s3_client.get_object(&self.database_uri) {
+            Ok(database_object) => database_object,
+            Err(e) => {
+                self.release_lock();
+                return Err(e.to_string());
+            }
+        };
+
+        let database_object = match database_object {
+            Some(database_object) => database_object,
+            None => {
+                println!(
+                    "{} does not exist yet, it is created from the local database",
+                    self.get_database_uri_text()
+                );
+                return Ok(());
+            }
+        };
+
+        // written next to it first, an interrupted download leaves the local database as it was
+        let download_path = get_sibling_path(database_path, ".download");
+
+        let write_result = match std::fs::write(&download_path, &database_object.body) {
+            Ok(_) => std::fs::rename(&download_path, database_path),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = write_result {
+            self.release_lock();
+            return Err(format!(
+                "Could not write the downloaded database to {}: {}",
+                database_path.to_string_lossy(),
+                e
+            ));
+        }
+
+        self.database_etag = database_object.etag;
+        self.database_hash = song_storage::get_file_hash(database_path).ok();
+
+        return Ok(());
+    }
+
+    /// Upload the database, only over the one that was downloaded and only if it changed, then
+    /// unlock it. If the bucket has another database by then, the local one is kept next to it as
+    /// a conflict copy
+    pub fn push(&mut self, database_path: &Path) -> Result<(), String> {
+        // ex. a command that only read it
+        if self.database_etag.is_some()
+            && self.database_hash.is_some()
+            && song_storage::get_file_hash(database_path).ok() == self.database_hash
+        {
+            self.release_lock();
+            return Ok(());
+        }
+
+        let database_bytes = match std::fs::read(database_path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.release_lock();
+                return Err(format!(
+                    "Could not read database {}: {}",
+                    database_path.to_string_lossy(),
+                    e
+                ));
+            }
+        };
+
+        let write_condition = match &self.database_etag {
+            Some(database_etag) => WriteCondition::IfMatch(database_etag.to_owned()),
+            None => WriteCondition::IfAbsent,
+        };
+
+        let upload_result =
+            self.s3_client
+                .put_object(&self.database_uri, database_bytes, &write_condition);
+
+        self.release_lock();
+
+        return match upload_result {
+            Ok(database_etag) => {
+                self.database_etag = database_etag;
+                self.database_hash = song_storage::get_file_hash(database_path).ok();
+                Ok(())
+            }
+            Err(e) if e.is_condition_failed() => {
+                let conflict_path = get_sibling_path(
+                    database_path,
+                    &format!(".conflict-{}", Local::now().format("%Y-%m-%d_%H-%M-%S")),
+                );
+
+                if let Err(e) = std::fs::copy(database_path, &conflict_path) {
+                    println!(
+                        "Could not keep the conflicting database at {}: {}",
+                        conflict_path.to_string_lossy(),
+                        e
+                    );
+                }
+
+                Err(format!(
+                    "{} was changed by another run since it was downloaded, it was not replaced. The database of this run is kept at {}",
+                    self.get_database_uri_text(),
+                    conflict_path.to_string_lossy()
+                ))
+            }
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    // create the lock object, or take over a lock that expired
+    fn acquire_lock(&mut self) -> Result<(), String> {
+        let now = Local::now().timestamp();
+
+        let database_lock = DatabaseLock {
+            owner: self.owner.to_owned(),
+            host: get_host_name(),
+            acquired_at: now,
+            expires_at: now + self.lock_ttl_minutes as i64 * SECONDS_PER_MINUTE,
+        };
+
+        let lock_body = match serde_json::to_vec(&database_lock) {
+            Ok(lock_body) => lock_body,
+            Err(e) => return Err(format!("Could not serialize the database lock: {}", e)),
+        };
+
+        let lock_error = match self.s3_client.put_object(
+            &self.lock_uri,
+            lock_body.to_owned(),
+            &WriteCondition::IfAbsent,
+        ) {
+            Ok(lock_etag) => {
+                self.lock_etag = lock_etag;
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+
+        if !lock_error.is_condition_failed() {
+            return Err(lock_error.to_string());
+        }
+
+        // someone holds the lock, or held it and is gone
+        let lock_object = match self.s3_client.get_object(&self.lock_uri) {
+            Ok(Some(lock_object)) => lock_object,
+            // released in the meantime
+            Ok(None) => return self.acquire_lock_once_more(lock_body),
+            Err(e) => return Err(e.to_string()),
+        };
+
+        let existing_lock = serde_json::from_slice::<DatabaseLock>(&lock_object.body).ok();
+
+        if let Some(existing_lock) = &existing_lock {
+            if existing_lock.expires_at > now {
+                return Err(format!(
+                    "{} is locked by a run on {} since {}, until {}. Delete s3://{}/{} if that run is gone",
+                    self.get_database_uri_text(),
+                    existing_lock.host,
+                    format_timestamp(existing_lock.acquired_at),
+                    format_timestamp(existing_lock.expires_at),
+                    self.lock_uri.bucket,
+                    self.lock_uri.key
+                ));
+            }
+        }
+
+        // only replaced if it is still the expired lock, another run may be taking it over too
+        let lock_etag = match lock_object.etag {
+            Some(lock_etag) => lock_etag,
+            None => {
+                return Err(format!(
+                    "The lock of {} has no etag, it can not be taken over safely",
+                    self.get_database_uri_text()
+                ))
+            }
+        };
+
+        match self.s3_client.put_object(
+            &self.lock_uri,
+            lock_body,
+            &WriteCondition::IfMatch(lock_etag),
+        ) {
+            Ok(new_lock_etag) => {
+                println!(
+                    "Took over the expired lock of {}{}",
+                    self.get_database_uri_text(),
+                    existing_lock
+                        .map(|existing_lock| format!(" from {}", existing_lock.host))
+                        .unwrap_or_default()
+                );
+
+                self.lock_etag = new_lock_etag;
+
+                return Ok(());
+            }
+            Err(e) if e.is_condition_failed() => {
+                return Err(format!(
+                    "Another run took over the lock of {} first",
+                    self.get_database_uri_text()
+                ))
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+    }
+
+    fn acquire_lock_once_more(&mut self, lock_body: Vec<u8>) -> Result<(), String> {
+        return match self
+            .s3_client
+            .put_object(&self.lock_uri, lock_body, &WriteCondition::IfAbsent)
+        {
+            Ok(lock_etag) => {
+                self.lock_etag = lock_etag;
+                Ok(())
+            }
+            Err(e) if e.is_condition_failed() => Err(format!(
+                "Another run locked {} first",
+                self.get_database_uri_text()
+            )),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    // delete the lock object if it is still ours, a lock that was taken over is left alone
+    fn release_lock(&mut self) {
+        let lock_etag = match self.lock_etag.take() {
+            Some(lock_etag) => lock_etag,
+            None => return,
+        };
+
+        if let Err(e) = self
+            .s3_client
+            .delete_object(&self.lock_uri, Some(&lock_etag))
+        {
+            println!(
+                "Could not release the lock of {}: {}",
+                self.get_database_uri_text(),
+                e
+            );
+        }
+    }
+
+    fn get_database_uri_text(&self) -> String {
+        return format!(
+            "s3://{}/{}",
+            self.database_uri.bucket, self.database_uri.key
+        );
+    }
+}
+
+// a run that fails part way does not leave the database locked until the lock expires
+impl Drop for DatabaseSync {
+    fn drop(&mut self) {
+        self.release_lock();
+    }
+}
+
+// a file next to the database, ex. "sqlite.db.download"
+fn get_sibling_path(database_path: &Path, suffix: &str) -> PathBuf {
+    let mut sibling_path = database_path.as_os_str().to_owned();
+    sibling_path.push(suffix);
+
+    return PathBuf::from(sibling_path);
+}
+
+fn get_host_name() -> String {
+    return std::env::var("HOSTNAME")
+        .or(std::env::var("COMPUTERNAME"))
+        .unwrap_or("unknown host".to_string());
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    return match Local.timestamp_opt(timestamp, 0).single() {
+        Some(date_time) => date_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => timestamp.to_string(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DatabaseLock, DatabaseSync};
+    use crate::environment_extractor::get_environment_variables;
+    use crate::s3_client::{S3Client, WriteCondition};
+    use crate::settings_parser::Settings;
+    use std::path::PathBuf;
+
+    // against a real s3 compatible server, ex. MinIO:
+    // MUSIX_TEST_S3_ENDPOINT=http://localhost:9000 MUSIX_TEST_S3_BUCKET=music cargo test -- --ignored
+    fn get_test_settings() -> Option<Settings> {
+        let endpoint_url = std::env::var("MUSIX_TEST_S3_ENDPOINT").ok()?;
+        let bucket = std::env::var("MUSIX_TEST_S3_BUCKET").ok()?;
+
+        // a key of its own, so runs do not see each other's database
+        let database_s3_uri = format!(
+            "s3://{}/musix-test-{}/sqlite.db",
+            bucket,
+            uuid::Uuid::new_v4()
+        );
+
+        return Some(
+            serde_json::from_value(serde_json::json!({
+                "library": { "database_s3_uri": database_s3_uri },
+                "s3": { "endpoint_url": endpoint_url, "force_path_style": true },
+            }))
+            .unwrap(),
+        );
+    }
+
+    fn get_database_path(name: &str) -> PathBuf {
+        let database_path = std::env::temp_dir().join(format!(
+            "musix_database_sync_{}_{}.db",
+            std::process::id(),
+            name
+        ));
+
+        std::fs::write(&database_path, name).unwrap();
+
+        return database_path;
+    }
+
+    #[test]
+    #[ignore]
+    fn test_database_sync() {
+        let settings = match get_test_settings() {
+            Some(settings) => settings,
+            None => {
+                println!("MUSIX_TEST_S3_ENDPOINT and MUSIX_TEST_S3_BUCKET are not set, skipping");
+                return;
+            }
+        };
+
+        let environment_variables = get_environment_variables().unwrap();
+        let new_database_sync = || {
+            DatabaseSync::from_settings(&environment_variables, &settings)
+                .unwrap()
+                .unwrap()
+        };
+
+        let first_path = get_database_path("first");
+        let second_path = get_database_path("second");
+
+        // the first run locks it, the second has to wait for it
+        let mut first_sync = new_database_sync();
+        first_sync.pull(&first_path).unwrap();
+
+        let mut second_sync = new_database_sync();
+        assert!(second_sync
+            .pull(&second_path)
+            .unwrap_err()
+            .contains("is locked by a run on"));
+
+        first_sync.push(&first_path).unwrap();
+
+        second_sync.pull(&second_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&second_path).unwrap(), "first");
+
+        // another run replaced the database in the meantime, ex. after taking over an expired lock
+        let s3_client = S3Client::new(settings.s3()).unwrap();
+        s3_client
+            .put_object(
+                &second_sync.database_uri,
+                b"other".to_vec(),
+                &WriteCondition::Always,
+            )
+            .unwrap();
+
+        std::fs::write(&second_path, "second").unwrap();
+        assert!(second_sync
+            .push(&second_path)
+            .unwrap_err()
+            .contains("was changed by another run"));
+
+        // a lock that expired is taken over
+        let expired_lock = DatabaseLock {
+            owner: "gone".to_string(),
+            host: "gone".to_string(),
+            acquired_at: 0,
+            expires_at: 1,
+        };
+        s3_client
+            .put_object(
+                &second_sync.lock_uri,
+                serde_json::to_vec(&expired_lock).unwrap(),
+                &WriteCondition::Always,
+            )
+            .unwrap();
+
+        let mut third_sync = new_database_sync();
+        third_sync.pull(&first_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&first_path).unwrap(), "other");
+        // an unchanged database is not uploaded again, but the lock is still released
+        third_sync.push(&first_path).unwrap();
+        new_database_sync().pull(&first_path).unwrap();
+
+        // with the conflict copy of the second database
+        for entry in std::fs::read_dir(std::env::temp_dir()).unwrap().flatten() {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+
+            if file_name.starts_with(&format!("musix_database_sync_{}_", std::process::id())) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Class of a failed download, decides how the failure is retried
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DownloadErrorClass {
+    // video or track was removed, made private, or never existed
+    Unavailable,
+    GeoBlocked,
+    // age restricted, members only, or otherwise requires an account
+    LoginRequired,
+    // http 429 or the provider suspects a bot
+    RateLimited,
+    NetworkTransient,
+    // ffmpeg / ffprobe failed converting the audio
+    PostProcessing,
+    // yt-dlp could not parse the site, which usually means it is out of date
+    ExtractorOutdated,
+    Unknown,
+}
+
+/// How a download failing with a class of error is retried
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RetryPolicy {
+    // failure is permanent, do not retry now or in later runs
+    Never,
+    // retry after an exponential backoff starting at the backoff duration
+    Backoff { max_retries: u32, backoff: Duration },
+    // wait once and retry, if it fails again pause the whole source for the rest of the run
+    PauseSource { wait: Duration },
+    // update yt-dlp and retry once
+    UpdateAndRetry,
+}
+
+#[derive(Debug)]
+pub struct DownloadError {
+    pub class: DownloadErrorClass,
+    pub message: String,
+}
+
+// patterns matched against the yt-dlp stderr, in order of precedence
+// rate limiting comes first since youtube's bot check also asks to sign in,
+// and private videos are unavailable even though they mention signing in.
+// unavailable songs are never retried, so it only matches the error yt-dlp gives for the video
+// itself, and not a 404 of a fragment or a page
+const ERROR_CLASS_PATTERNS: [(DownloadErrorClass, &str); 7] = [
+    (
+        DownloadErrorClass::RateLimited,
+        r"(?i)(HTTP Error 429|Too Many Requests|rate.?limit|not a bot|try again later)",
+    ),
+    (
+        DownloadErrorClass::GeoBlocked,
+        r"(?i)(in your country|geo.?restrict|geo.?block|from your location)",
+    ),
+    (
+        DownloadErrorClass::ExtractorOutdated,
+        r"(?i)(unable to extract|nsig extraction failed|signature extraction failed|requested format is not available|update to the latest version|please report this issue)",
+    ),
+    (
+        DownloadErrorClass::Unavailable,
+        r"(?im)^ERROR: \[[^\]]+\] \S+: (Video unavailable|Private video|This video (is private|has been removed|is no longer available|is not available))",
+    ),
+    (
+        DownloadErrorClass::LoginRequired,
+        r"(?i)(confirm your age|age.?restricted|inappropriate for some users|members.?only|join this channel|login required|requires authentication|sign in|log in)",
+    ),
+    (
+        DownloadErrorClass::PostProcessing,
+        r"(?i)(postprocessing|ffmpeg|ffprobe|conversion failed)",
+    ),
+    (
+        DownloadErrorClass::NetworkTransient,
+        r"(?i)(timed out|connection (reset|refused|aborted)|temporary failure in name resolution|network is unreachable|unable to download (webpage|api page)|HTTP Error 5[0-9][0-9]|IncompleteRead|EOF occurred|remote end closed)",
+    ),
+];
+
+lazy_static! {
+    // compiled once, the patterns are constants so a failing regex is a programming error the
+    // classify test catches
+    static ref ERROR_CLASS_REGEXES: Vec<(DownloadErrorClass, Regex)> = ERROR_CLASS_PATTERNS
+        .iter()
+        .map(|(class, pattern)| {
+            (
+                *class,
+                Regex::new(pattern).expect("download error class pattern is a valid regex"),
+            )
+        })
+        .collect();
+}
+
+impl DownloadErrorClass {
+    /// Classify a failed yt-dlp run from its exit code and stderr
+    pub fn classify(exit_code: Option<i32>, stderr: &str) -> DownloadErrorClass {
+        for (class, regex) in ERROR_CLASS_REGEXES.iter() {
+            if regex.is_match(stderr) {
+                return *class;
+            }
+        }
+
+        // killed by a signal, which is most likely the network or the machine going away
+        if exit_code.is_none() {
+            return DownloadErrorClass::NetworkTransient;
+        }
+
+        return DownloadErrorClass::Unknown;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        return match self {
+            DownloadErrorClass::Unavailable => RetryPolicy::Never,
+            DownloadErrorClass::GeoBlocked => RetryPolicy::Never,
+            DownloadErrorClass::LoginRequired => RetryPolicy::Never,
+            DownloadErrorClass::RateLimited => RetryPolicy::PauseSource {
+                wait: Duration::from_secs(60),
+            },
+            DownloadErrorClass::NetworkTransient => RetryPolicy::Backoff {
+                max_retries: 3,
+                backoff: Duration::from_secs(5),
+            },
+            DownloadErrorClass::PostProcessing => RetryPolicy::Backoff {
+                max_retries: 1,
+                backoff: Duration::from_secs(1),
+            },
+            DownloadErrorClass::ExtractorOutdated => RetryPolicy::UpdateAndRetry,
+            // failed songs are retried on the next run
+            DownloadErrorClass::Unknown => RetryPolicy::Backoff {
+                max_retries: 0,
+                backoff: Duration::from_secs(0),
+            },
+        };
+    }
+
+    /// Name of the class as stored in the database
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            DownloadErrorClass::Unavailable => "unavailable",
+            DownloadErrorClass::GeoBlocked => "geo_blocked",
+            DownloadErrorClass::LoginRequired => "login_required",
+            DownloadErrorClass::RateLimited => "rate_limited",
+            DownloadErrorClass::NetworkTransient => "network_transient",
+            DownloadErrorClass::PostProcessing => "post_processing",
+            DownloadErrorClass::ExtractorOutdated => "extractor_outdated",
+            DownloadErrorClass::Unknown => "unknown",
+        };
+    }
+
+    /// Classes of failures that should never be retried, not even in later runs
+    pub fn permanent_classes() -> Vec<DownloadErrorClass> {
+        return [
+            DownloadErrorClass::Unavailable,
+            DownloadErrorClass::GeoBlocked,
+            DownloadErrorClass::LoginRequired,
+            DownloadErrorClass::RateLimited,
+            DownloadErrorClass::NetworkTransient,
+            DownloadErrorClass::PostProcessing,
+            DownloadErrorClass::ExtractorOutdated,
+            DownloadErrorClass::Unknown,
+        ]
+        .into_iter()
+        .filter(|class| class.retry_policy() == RetryPolicy::Never)
+        .collect();
+    }
+}
+
+impl DownloadError {
+    pub fn new(class: DownloadErrorClass, message: String) -> Self {
+        return DownloadError { class, message };
+    }
+}
+
+// errors that are not from yt-dlp itself (spawning, parsing output, io) are unknown
+impl From<String> for DownloadError {
+    fn from(message: String) -> Self {
+        return DownloadError::new(DownloadErrorClass::Unknown, message);
+    }
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "[{}] {}", self.class.as_str(), self.message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DownloadErrorClass;
+
+    #[test]
+    fn test_classify() {
+        let cases = [
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader",
+                DownloadErrorClass::Unavailable,
+            ),
+            (
+                "ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video",
+                DownloadErrorClass::Unavailable,
+            ),
+            (
+                "ERROR: [youtube] abc: The uploader has not made this video available in your country",
+                DownloadErrorClass::GeoBlocked,
+            ),
+            (
+                "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.",
+                DownloadErrorClass::LoginRequired,
+            ),
+            (
+                "ERROR: [youtube] abc: Sign in to confirm you're not a bot",
+                DownloadErrorClass::RateLimited,
+            ),
+            (
+                "ERROR: unable to download video data: HTTP Error 429: Too Many Requests",
+                DownloadErrorClass::RateLimited,
+            ),
+            (
+                "ERROR: [soundcloud] 123: Unable to download API page: <urlopen error [Errno -3] Temporary failure in name resolution>",
+                DownloadErrorClass::NetworkTransient,
+            ),
+            (
+                "ERROR: Postprocessing: audio conversion failed: Error opening output files",
+                DownloadErrorClass::PostProcessing,
+            ),
+            (
+                "ERROR: [youtube] abc: Unable to extract uploader id; please report this issue on https://github.com/yt-dlp/yt-dlp/issues",
+                DownloadErrorClass::ExtractorOutdated,
+            ),
+            (
+                "ERROR: [youtube] abc: Video unavailable. This video is no longer available because the YouTube account associated with this video has been terminated.",
+                DownloadErrorClass::Unavailable,
+            ),
+            (
+                "[download] Got error: HTTP Error 404: Not Found. Retrying fragment 12 (1/10)...\nERROR: fragment 12 not found, unable to continue",
+                DownloadErrorClass::Unknown,
+            ),
+            (
+                "ERROR: unable to download video data: HTTP Error 404: Not Found",
+                DownloadErrorClass::Unknown,
+            ),
+            ("ERROR: something new went wrong", DownloadErrorClass::Unknown),
+        ];
+
+        for (stderr, expected_class) in cases {
+            assert_eq!(
+                DownloadErrorClass::classify(Some(1), stderr),
+                expected_class,
+                "{}",
+                stderr
+            );
+        }
+    }
+}
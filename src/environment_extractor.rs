@@ -1,26 +1,47 @@
+use std::{env, path::PathBuf};
+
 pub struct EnvironmentVariables {
-    //database_s3_uri: String,
+    database_s3_uri: Option<String>,
+    settings_path: Option<PathBuf>,
+    library_root: Option<PathBuf>,
+    database_path: Option<PathBuf>,
 }
 
 pub fn get_environment_variables() -> Result<EnvironmentVariables, String> {
-    // Get the s3 path for the database
-    /*let database_s3_uri = match env::var("DATABASE_S3_URI") {
-        Ok(some) => some,
-        Err(e) => format!(
-            "Could not fetch DATABASE_S3_PATH environment variable: {}",
-            e.to_string()
-        ),
-    };*/
+    // Get the s3 uri the database is kept at, this overrides the settings file
+    let database_s3_uri = env::var("MUSIX_DATABASE_S3_URI").ok();
+
+    // Get the path of the settings file, the default location is used if it is not set
+    let settings_path = env::var("MUSIX_SETTINGS_PATH").ok().map(PathBuf::from);
+
+    // Get the library root and database path, these override the settings file
+    let library_root = env::var("MUSIX_LIBRARY_ROOT").ok().map(PathBuf::from);
+    let database_path = env::var("MUSIX_DB_PATH").ok().map(PathBuf::from);
 
     let environment_variables = EnvironmentVariables {
-        //database_s3_uri: database_s3_uri,
+        database_s3_uri,
+        settings_path,
+        library_root,
+        database_path,
     };
 
     return Ok(environment_variables);
 }
 
 impl EnvironmentVariables {
-    /*pub fn get_database_s3_uri(&self) -> &String {
+    pub fn get_database_s3_uri(&self) -> &Option<String> {
         &self.database_s3_uri
-    }*/
+    }
+
+    pub fn get_settings_path(&self) -> &Option<PathBuf> {
+        &self.settings_path
+    }
+
+    pub fn get_library_root(&self) -> &Option<PathBuf> {
+        &self.library_root
+    }
+
+    pub fn get_database_path(&self) -> &Option<PathBuf> {
+        &self.database_path
+    }
 }
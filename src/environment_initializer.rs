@@ -1,49 +1,197 @@
-use std::{io::Write};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 
 use ureq;
 
-// ensure the yt-dlp binary exists and is downloaded
-pub fn init_yt_dlp_executable() -> Result<(), String> {
+use crate::library_paths::LibraryPaths;
+use crate::settings_parser::YtDlpSettings;
+use crate::tool_locator;
+
+const YT_DLP_RELEASES_URL: &str = "https://github.com/yt-dlp/yt-dlp/releases";
+
+// file listing the sha256 hash of every asset in a yt-dlp release
+const YT_DLP_CHECKSUMS_FILE_NAME: &str = "SHA2-256SUMS";
+
+// ensure the yt-dlp binary exists and is downloaded, at the pinned version if there is one
+pub fn init_yt_dlp_executable(
+    yt_dlp_settings: &YtDlpSettings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let executable_path = get_managed_yt_dlp_path(library_paths);
+
+    // if ytl-dlp.* exists, exit
+    let executable_exists = match std::fs::exists(&executable_path) {
+        Ok(val) => val,
+        Err(e) => {
+            return Err(format!(
+                "Could not check if yt-dlp executable exists: {}",
+                e
+            ))
+        }
+    };
+
+    if executable_exists {
+        let pinned_version = match yt_dlp_settings.version() {
+            Some(version) => version,
+            None => return Ok(()),
+        };
+
+        // reinstall when the pin changed since the executable was downloaded
+        if get_yt_dlp_version(&executable_path).as_deref() == Some(pinned_version.as_str()) {
+            return Ok(());
+        }
+
+        println!(
+            "yt-dlp is not at the pinned version {}, reinstalling",
+            pinned_version
+        );
+    }
+
+    return install_yt_dlp_executable(yt_dlp_settings, &executable_path);
+}
+
+// download yt-dlp again, at the latest version or the pinned version if there is one
+pub fn update_yt_dlp_executable(
+    yt_dlp_settings: &YtDlpSettings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let executable_path = get_managed_yt_dlp_path(library_paths);
+
+    let previous_version = get_yt_dlp_version(&executable_path);
+
+    install_yt_dlp_executable(yt_dlp_settings, &executable_path)?;
+
+    let current_version = get_yt_dlp_version(&executable_path);
+
+    println!(
+        "Updated yt-dlp from {} to {}",
+        previous_version.unwrap_or("none".to_string()),
+        current_version.unwrap_or("unknown".to_string())
+    );
+
+    return Ok(());
+}
+
+/// Path of the managed yt-dlp executable, which is downloaded into the library's tools directory
+pub fn get_managed_yt_dlp_path(library_paths: &LibraryPaths) -> PathBuf {
+    let file_name = match std::env::consts::OS {
+        "windows" => "yt-dlp.exe",
+        _ => "yt-dlp",
+    };
+
+    return library_paths.tools_directory().join(file_name);
+}
+
+// version reported by the yt-dlp executable, None if it could not be run
+fn get_yt_dlp_version(executable_path: &Path) -> Option<String> {
+    return tool_locator::get_tool_version(executable_path, "--version").ok();
+}
+
+// download the yt-dlp release asset for this os into a temporary file, verify it against the
+// release checksums, and only then move it over the executable path
+fn install_yt_dlp_executable(
+    yt_dlp_settings: &YtDlpSettings,
+    executable_path: &Path,
+) -> Result<(), String> {
     let target_os = std::env::consts::OS;
 
-    let yt_dlp_url = match target_os {
-        "windows" => "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp.exe",
-        "macos" => "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp_macos",
+    let asset_name = match target_os {
+        "windows" => "yt-dlp.exe",
+        "macos" => "yt-dlp_macos",
         _ => {
             println!("OS is not windows or mac, assuming a Linux distro");
-            "https://github.com/yt-dlp/yt-dlp/releases/latest/download/yt-dlp"
+            "yt-dlp"
         }
     };
-    let file_name = match target_os {
-        "windows" => "yt-dlp.exe",
-        _ => "yt-dlp",
+
+    let release_download_url = match yt_dlp_settings.version() {
+        Some(version) => format!("{}/download/{}", YT_DLP_RELEASES_URL, version),
+        None => format!("{}/latest/download", YT_DLP_RELEASES_URL),
     };
 
-    // get working directory
-    let working_directory = match std::env::current_dir() {
-        Ok(dir) => dir,
+    let expected_hash = get_release_asset_hash(&release_download_url, asset_name)?;
+
+    // partial downloads are left in the temporary file, never in the executable path
+    let temporary_path = executable_path.with_extension("part");
+
+    let downloaded_hash = download_file(
+        &format!("{}/{}", release_download_url, asset_name),
+        &temporary_path,
+    )?;
+
+    if downloaded_hash != expected_hash {
+        let _ = std::fs::remove_file(&temporary_path);
+
+        return Err(format!(
+            "Downloaded yt-dlp executable has sha256 {} but the release lists {}",
+            downloaded_hash, expected_hash
+        ));
+    }
+
+    match std::fs::File::open(&temporary_path) {
+        Ok(file) => set_file_permissions(&file)?,
         Err(e) => {
-            return Err(format!("Could not get working directory: {}", e));
+            return Err(format!(
+                "Could not open downloaded yt-dlp executable: {}",
+                e
+            ))
         }
     };
-    let executable_path = working_directory.join(file_name);
 
-    // if ytl-dlp.* exists, exit
-    let executable_exists = match std::fs::exists(&executable_path) {
-        Ok(val) => val,
+    match std::fs::rename(&temporary_path, executable_path) {
+        Ok(_) => (),
         Err(e) => {
             return Err(format!(
-                "Could not check if yt-dlp executable exists: {}",
+                "Could not move downloaded yt-dlp executable into place: {}",
                 e
             ))
         }
     };
-    if executable_exists {
-        return Ok(());
+
+    return Ok(());
+}
+
+// get the sha256 hash of the asset from the release checksums file
+fn get_release_asset_hash(release_download_url: &str, asset_name: &str) -> Result<String, String> {
+    let checksums_url = format!("{}/{}", release_download_url, YT_DLP_CHECKSUMS_FILE_NAME);
+
+    let response = match ureq::get(&checksums_url).call() {
+        Ok(resp) => resp,
+        Err(e) => {
+            return Err(format!(
+                "Error making request to download yt-dlp checksums: {}",
+                e
+            ))
+        }
+    };
+
+    let checksums = match response.into_string() {
+        Ok(text) => text,
+        Err(e) => return Err(format!("Could not read yt-dlp checksums: {}", e)),
+    };
+
+    // each line is "<sha256>  <asset name>"
+    for line in checksums.lines() {
+        let mut fields = line.split_whitespace();
+
+        if let (Some(hash), Some(name)) = (fields.next(), fields.next()) {
+            if name == asset_name {
+                return Ok(hash.to_lowercase());
+            }
+        }
     }
 
+    return Err(format!(
+        "Could not find {} in the yt-dlp release checksums",
+        asset_name
+    ));
+}
+
+// download the url into the file, returns the sha256 hash of the downloaded bytes
+fn download_file(url: &str, file_path: &Path) -> Result<String, String> {
     // create executable file
-    let mut file = match std::fs::File::create(&executable_path) {
+    let mut file = match std::fs::File::create(file_path) {
         Ok(file) => file,
         Err(e) => {
             return Err(format!(
@@ -54,7 +202,7 @@ pub fn init_yt_dlp_executable() -> Result<(), String> {
     };
 
     // download file using chuncking
-    let request = ureq::get(yt_dlp_url).set("Transfer-Encoding", "chunked");
+    let request = ureq::get(url).set("Transfer-Encoding", "chunked");
     let response = match request.call() {
         Ok(resp) => resp,
         Err(e) => {
@@ -66,6 +214,8 @@ pub fn init_yt_dlp_executable() -> Result<(), String> {
     };
     let mut response_reader = response.into_reader();
 
+    let mut hasher = Sha256::new();
+
     // read bytes from body reader into file
     let mut buffer = [0u8; 4048];
 
@@ -86,53 +236,49 @@ pub fn init_yt_dlp_executable() -> Result<(), String> {
             break;
         }
 
-        // write but buffer contents to the file until all of them are written
-        // in case there are partial writes
-        let mut total_bytes_written = 0;
-        // prevent infinite loop
-        let mut tries = 0;
-
-        loop {
-            // write buffer to file
-            let bytes_written = match file.write(&buffer[total_bytes_written..bytes_read]) {
-                Ok(n) => n,
-                Err(e) => {
-                    return Err(format!(
-                        "Could not write bytes in buffered write to download yt-dlp executable: {}",
-                        e
-                    ))
-                }
-            };
-
-            total_bytes_written += bytes_written;
-
-            if total_bytes_written == bytes_read {
-                break;
-            }
+        hasher.update(&buffer[..bytes_read]);
 
-            if tries == 10 {
-                return Err("Could not write enough bytes after 10 failed attempts".to_string());
+        match file.write_all(&buffer[..bytes_read]) {
+            Ok(_) => (),
+            Err(e) => {
+                return Err(format!(
+                    "Could not write bytes in buffered write to download yt-dlp executable: {}",
+                    e
+                ))
             }
-
-            tries += 1;
-        }
-
-        // clear buffer
-        buffer.fill(0);
+        };
     }
 
+    // make sure everything is on disk before the file is moved into place
+    match file.sync_all() {
+        Ok(_) => (),
+        Err(e) => {
+            return Err(format!(
+                "Could not flush downloaded yt-dlp executable to disk: {}",
+                e
+            ))
+        }
+    };
 
-    set_file_permissions(&mut file)?;
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
 
-    return Ok(());
+    return Ok(hash);
 }
 
-
 #[cfg(target_os = "windows")]
-fn set_file_permissions(file: &mut std::fs::File) -> Result<(), String> {
+fn set_file_permissions(file: &std::fs::File) -> Result<(), String> {
     let metadata = match file.metadata() {
         Ok(metadata) => metadata,
-        Err(e) => return Err(format!("Could not get metadata from executable file: {}", e))
+        Err(e) => {
+            return Err(format!(
+                "Could not get metadata from executable file: {}",
+                e
+            ))
+        }
     };
     let mut permissions = metadata.permissions();
 
@@ -148,15 +294,15 @@ fn set_file_permissions(file: &mut std::fs::File) -> Result<(), String> {
             ))
         }
     };
-    
+
     return Ok(());
 }
 
 #[cfg(not(target_os = "windows"))]
-fn set_file_permissions(file: &std::fs::File) -> Result<(), String>  {
+fn set_file_permissions(file: &std::fs::File) -> Result<(), String> {
     use std::os::unix::fs::PermissionsExt;
 
-    match file.set_permissions(std::os::fs::Permissions::from_mode(0o755)) {
+    match file.set_permissions(std::fs::Permissions::from_mode(0o755)) {
         Ok(()) => (),
         Err(e) => {
             return Err(format!(
@@ -165,110 +311,74 @@ fn set_file_permissions(file: &std::fs::File) -> Result<(), String>  {
             ))
         }
     };
-    
+
     return Ok(());
 }
 
-// ensure file env exists and move all downloaded songs into the archive folder
-pub fn init_file_env() -> Result<(), String> {
-    // get working directory
-    let working_directory = match std::env::current_dir() {
-        Ok(dir) => dir,
-        Err(e) => {
-            return Err(format!("Could not get working directory: {}", e));
-        }
+// ensure file env exists
+pub fn init_file_env(library_paths: &LibraryPaths) -> Result<(), String> {
+    // the database can live outside of the library, so its directory is created on its own
+    let database_directory = match library_paths.database_path().parent() {
+        Some(directory) => directory.to_path_buf(),
+        None => library_paths.data_directory(),
     };
 
-    // check if the downloaded directory exists, create it if it doesn't
-    let data_directory = working_directory.join("data");
-
-    // ensure that the downloaded directory exists
-    if !data_directory.exists() {
-        if let Err(e) = std::fs::create_dir(&data_directory) {
-            return Err(format!("Could not create data directory: {}", e));
-        }
-    }
-    
-    // check if the downloaded directory exists, create it if it doesn't
-    let database_directory = data_directory.join("database");
-
-    // ensure that the downloaded directory exists
-    if !database_directory.exists() {
-        if let Err(e) = std::fs::create_dir(&database_directory) {
-            return Err(format!("Could not create database directory: {}", e));
-        }
-    }
-
-    // check if the downloaded directory exists, create it if it doesn't
-    let downloaded_directory = working_directory.join("downloaded");
-
-    // ensure that the downloaded directory exists
-    if !downloaded_directory.exists() {
-        if let Err(e) = std::fs::create_dir(&downloaded_directory) {
-            return Err(format!("Could not create downloaded directory: {}", e));
-        }
-    }
-
-    // check if the archive directory exists, create it if it doesn't
-    let archive_directory = working_directory.join("archive");
-
-    // ensure that the downloaded directory exists
-    if !archive_directory.exists() {
-        if let Err(e) = std::fs::create_dir(&archive_directory) {
-            return Err(format!("Could not create archive directory: {}", e));
+    let directories = [
+        ("data", library_paths.data_directory()),
+        ("database", database_directory),
+        ("downloaded", library_paths.downloaded_directory()),
+        ("archive", library_paths.archive_directory()),
+        ("temporary", library_paths.temporary_directory()),
+        ("tools", library_paths.tools_directory()),
+    ];
+
+    // ensure that each directory exists, create it if it doesn't
+    for (directory_name, directory) in directories.iter() {
+        if !directory.exists() {
+            if let Err(e) = std::fs::create_dir_all(directory) {
+                return Err(format!(
+                    "Could not create {} directory {}: {}",
+                    directory_name,
+                    directory.to_string_lossy(),
+                    e
+                ));
+            }
         }
     }
 
-    move_downloaded_songs_to_archive()?;
-
     return Ok(());
 }
 
-pub fn move_downloaded_songs_to_archive() -> Result<(), String> {
-    // get working directory
-    let working_directory = match std::env::current_dir() {
-        Ok(dir) => dir,
-        Err(e) => {
-            return Err(format!("Could not get working directory: {}", e));
-        }
-    };
+/// Remove what is left in the temporary directory by downloads of runs that failed or crashed
+pub fn clear_temporary_directory(library_paths: &LibraryPaths) -> Result<(), String> {
+    let temporary_directory = library_paths.temporary_directory();
 
-    // ensure all songs in to_downloaded are now in the archive folder
-    let downloaded_files = match std::fs::read_dir(working_directory.join("downloaded")) {
-        Ok(files) => files,
+    let directory_entries = match std::fs::read_dir(&temporary_directory) {
+        Ok(entries) => entries,
         Err(e) => {
             return Err(format!(
-                "Could not read files from downloaded directory: {}",
+                "Could not read temporary directory {}: {}",
+                temporary_directory.to_string_lossy(),
                 e
             ))
         }
     };
 
-    // assuming that every file in the downloaded directory is a music file
-    for file_result in downloaded_files.into_iter() {
-        let file = match file_result {
-            Ok(file) => file,
-            Err(e) => {
-                return Err(format!(
-                    "Could not get file from file result in list downloaded files: {}",
-                    e
-                ))
-            }
-        };
+    for entry in directory_entries.flatten() {
+        let path = entry.path();
 
-        // move file into archive directory
-        let from_path = file.path();
-        let to_path = working_directory.join("archive").join(file.file_name());
-        match std::fs::copy(from_path, to_path) {
-            Ok(d) => d,
-            Err(e) => {
-                return Err(format!(
-                    "Could not copy file {} from downloaded folder to archive folder: {}",
-                    file.file_name().to_string_lossy(),
-                    e
-                ))
-            }
+        let remove_result = match path.is_dir() {
+            true => std::fs::remove_dir_all(&path),
+            false => std::fs::remove_file(&path),
         };
+
+        if let Err(e) = remove_result {
+            return Err(format!(
+                "Could not remove {} from the temporary directory: {}",
+                path.to_string_lossy(),
+                e
+            ));
+        }
     }
 
     return Ok(());
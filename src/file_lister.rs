@@ -0,0 +1,50 @@
+use std::path::{Path, PathBuf};
+
+/// The files in the directory and in its folders
+pub fn list_files(directory: &Path) -> Result<Vec<PathBuf>, String> {
+    let directory_entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(format!(
+                "Could not read files from directory {}: {}",
+                directory.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    let mut files = Vec::new();
+
+    for entry_result in directory_entries {
+        let entry = match entry_result {
+            Ok(entry) => entry,
+            Err(e) => {
+                return Err(format!(
+                    "Could not get file from directory {}: {}",
+                    directory.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        let path = entry.path();
+
+        match path.is_dir() {
+            true => files.extend(list_files(&path)?),
+            false => files.push(path),
+        }
+    }
+
+    return Ok(files);
+}
+
+/// The files in the directories, a directory that does not exist yet has none
+pub fn list_files_in(directories: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+
+    for directory in directories.iter().filter(|directory| directory.is_dir()) {
+        files.extend(list_files(directory)?);
+    }
+
+    return Ok(files);
+}
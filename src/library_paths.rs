@@ -0,0 +1,236 @@
+use std::path::{Path, PathBuf};
+
+use crate::environment_extractor::EnvironmentVariables;
+use crate::settings_parser::Settings;
+
+// extensions of the audio files songs are downloaded as
+const SONG_EXTENSIONS: [&str; 9] = [
+    "mp3", "m4a", "mp4", "aac", "opus", "ogg", "oga", "flac", "wav",
+];
+
+/// Locations of everything the library keeps on disk, resolved once at start up
+#[derive(Clone, Debug)]
+pub struct LibraryPaths {
+    root: PathBuf,
+    database_path: PathBuf,
+}
+
+impl LibraryPaths {
+    pub fn new(root: PathBuf, database_path: Option<PathBuf>) -> Self {
+        let database_path = match database_path {
+            Some(database_path) => database_path,
+            None => root.join("data").join("database").join("sqlite.db"),
+        };
+
+        return LibraryPaths {
+            root,
+            database_path,
+        };
+    }
+
+    /// Resolve the library paths, in order of precedence: the command line flags,
+    /// the environment variables, the settings file, and lastly the working directory if it
+    /// already holds a library
+    pub fn resolve(
+        library_root_flag: Option<PathBuf>,
+        database_path_flag: Option<PathBuf>,
+        environment_variables: &EnvironmentVariables,
+        settings: &Settings,
+    ) -> Result<LibraryPaths, String> {
+        let root = match library_root_flag
+            .or(environment_variables.get_library_root().to_owned())
+            .or(settings.library().root().to_owned())
+        {
+            Some(root) => root,
+            None => {
+                let working_directory = match std::env::current_dir() {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        return Err(format!("Could not get working directory: {}", e));
+                    }
+                };
+
+                // running from the wrong directory should not start a second, empty library there
+                if !working_directory.join("data").is_dir() {
+                    return Err(format!(
+                        "No library root is set and the working directory {} is not a library. Set it with --library-root, MUSIX_LIBRARY_ROOT or library.root, which creates the library if it does not exist",
+                        working_directory.to_string_lossy()
+                    ));
+                }
+
+                println!(
+                    "Using the library in the working directory {}",
+                    working_directory.to_string_lossy()
+                );
+
+                working_directory
+            }
+        };
+
+        let database_path = database_path_flag
+            .or(environment_variables.get_database_path().to_owned())
+            .or(settings.library().database_path().to_owned());
+
+        // absolute, as tools are started in other working directories, ex. yt-dlp in the
+        // temporary directory
+        let root = get_absolute_path(&root)?;
+
+        let database_path = match database_path {
+            Some(database_path) => Some(get_absolute_path(&database_path)?),
+            None => None,
+        };
+
+        return Ok(LibraryPaths::new(root, database_path));
+    }
+
+    pub fn root(&self) -> &Path {
+        return &self.root;
+    }
+
+    pub fn data_directory(&self) -> PathBuf {
+        return self.root.join("data");
+    }
+
+    pub fn database_path(&self) -> &Path {
+        return &self.database_path;
+    }
+
+    // where the downloads of the current run end up
+    pub fn downloaded_directory(&self) -> PathBuf {
+        return self.root.join("downloaded");
+    }
+
+    pub fn archive_directory(&self) -> PathBuf {
+        return self.root.join("archive");
+    }
+
+    /// The downloaded folder, or the archive batch, the song is in. The path of the song relative
+    /// to it is the one the file name template gave it
+    pub fn get_library_directory(&self, file_path: &Path) -> Result<PathBuf, String> {
+        let downloaded_directory = self.downloaded_directory();
+
+        if file_path.starts_with(&downloaded_directory) {
+            return Ok(downloaded_directory);
+        }
+
+        let archive_directory = self.archive_directory();
+
+        if let Ok(archived_path) = file_path.strip_prefix(&archive_directory) {
+            if let Some(batch_name) = archived_path.components().next() {
+                return Ok(archive_directory.join(batch_name));
+            }
+        }
+
+        return Err(format!(
+            "{} is not in the downloaded or archive folder",
+            file_path.to_string_lossy()
+        ));
+    }
+
+    // where yt-dlp writes songs while they are downloading and being post processed
+    pub fn temporary_directory(&self) -> PathBuf {
+        return self.data_directory().join("tmp");
+    }
+
+    /// Path as it is stored in the database, relative to the library root and "/" separated,
+    /// so the library can be moved
+    pub fn to_stored_path(&self, path: &Path) -> String {
+        let relative_path = match path.strip_prefix(&self.root) {
+            Ok(relative_path) => relative_path,
+            Err(_) => return path.to_string_lossy().to_string(),
+        };
+
+        return relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("/");
+    }
+
+    pub fn from_stored_path(&self, stored_path: &str) -> PathBuf {
+        return self.root.join(stored_path);
+    }
+
+    // where managed tools like yt-dlp are downloaded to
+    pub fn tools_directory(&self) -> PathBuf {
+        return self.data_directory().join("bin");
+    }
+}
+
+/// The path made absolute from the working directory, without needing it to exist
+pub fn get_absolute_path(path: &Path) -> Result<PathBuf, String> {
+    return match std::path::absolute(path) {
+        Ok(absolute_path) => Ok(absolute_path),
+        Err(e) => Err(format!(
+            "Could not make {} absolute: {}",
+            path.to_string_lossy(),
+            e
+        )),
+    };
+}
+
+/// Whether the file is a song, and not a sidecar, lyrics or a file left by an interrupted download
+pub fn is_song_path(file_path: &Path) -> bool {
+    let file_extension = match file_path.extension() {
+        Some(extension) => extension.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+
+    return SONG_EXTENSIONS.contains(&file_extension.as_str());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LibraryPaths;
+    use crate::environment_extractor;
+    use crate::settings_parser::Settings;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_layout_is_relative_to_root() {
+        let library_paths = LibraryPaths::new(PathBuf::from("/srv/music"), None);
+
+        assert_eq!(
+            library_paths.database_path(),
+            PathBuf::from("/srv/music/data/database/sqlite.db")
+        );
+        assert_eq!(
+            library_paths.downloaded_directory(),
+            PathBuf::from("/srv/music/downloaded")
+        );
+        assert_eq!(
+            library_paths.temporary_directory(),
+            PathBuf::from("/srv/music/data/tmp")
+        );
+
+        // an explicit database path is kept as is
+        let library_paths = LibraryPaths::new(
+            PathBuf::from("/srv/music"),
+            Some(PathBuf::from("/var/lib/musix.db")),
+        );
+        assert_eq!(
+            library_paths.database_path(),
+            PathBuf::from("/var/lib/musix.db")
+        );
+    }
+
+    #[test]
+    fn test_relative_root_is_made_absolute() {
+        let environment_variables = environment_extractor::get_environment_variables().unwrap();
+
+        let library_paths = LibraryPaths::resolve(
+            Some(PathBuf::from("music/library")),
+            None,
+            &environment_variables,
+            &Settings::default(),
+        )
+        .unwrap();
+
+        assert!(library_paths.root().is_absolute());
+        assert_eq!(
+            library_paths.root(),
+            std::env::current_dir().unwrap().join("music/library")
+        );
+        assert!(library_paths.tools_directory().is_absolute());
+    }
+}
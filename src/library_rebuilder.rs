@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime, TimeZone};
+
+use crate::audio_tag_appender::{self, SongTags};
+use crate::database::Database;
+use crate::environment_extractor::EnvironmentVariables;
+use crate::file_lister;
+use crate::library_paths::{self, LibraryPaths};
+use crate::music_sources::StoredSongMetadata;
+use crate::settings_parser::Settings;
+use crate::song_sidecar::{self, SongSidecar};
+
+// a song found in the library, as it is put back into the database
+struct RestoredSong {
+    song_url: String,
+    playlist_url: String,
+    // None for split mixes
+    file_path: Option<PathBuf>,
+    metadata: StoredSongMetadata,
+}
+
+/// Put the songs of the library back into the database, with the playlists they were downloaded
+/// from. Songs are mapped back to their source from their info json sidecar, or else from their
+/// source and playlist url tags, files that can not be mapped are reported
+pub fn rebuild_database(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+    default_playlist_url: Option<&str>,
+) -> Result<(), String> {
+    let library_files = file_lister::list_files_in(&[
+        library_paths.downloaded_directory(),
+        library_paths.archive_directory(),
+    ])?;
+
+    let mut sidecar_paths = library_files
+        .iter()
+        .filter(|file_path| song_sidecar::is_info_json_path(file_path))
+        .cloned()
+        .collect::<Vec<PathBuf>>();
+
+    if let Some(sidecar_directory) = song_sidecar::get_sidecar_directory(settings, library_paths) {
+        sidecar_paths.extend(
+            file_lister::list_files_in(&[sidecar_directory])?
+                .into_iter()
+                .filter(|file_path| song_sidecar::is_info_json_path(file_path)),
+        );
+    }
+
+    // the sidecar folder can be in the library
+    sidecar_paths.sort();
+    sidecar_paths.dedup();
+
+    let library_files_by_name = get_library_files_by_name(&library_files);
+
+    let mut restored_songs = Vec::<RestoredSong>::new();
+
+    // songs and mix folders that have a sidecar
+    let mut mapped_paths = HashSet::<PathBuf>::new();
+
+    for sidecar_path in sidecar_paths.iter() {
+        let song_sidecar = match song_sidecar::read_sidecar(sidecar_path) {
+            Ok(song_sidecar) => song_sidecar,
+            Err(e) => {
+                println!("Skipping {}", e);
+                continue;
+            }
+        };
+
+        let song_directory =
+            song_sidecar::get_song_directory(sidecar_path, settings, library_paths);
+
+        let song_path = match find_song_path(
+            song_directory.as_deref(),
+            &song_sidecar,
+            &library_files_by_name,
+        ) {
+            Some(song_path) => song_path,
+            None => {
+                println!(
+                    "Skipping {}, {} is not in the library",
+                    song_sidecar.song_url, song_sidecar.file_name
+                );
+                continue;
+            }
+        };
+
+        mapped_paths.insert(song_path.to_owned());
+
+        // the tracks of a split mix are not stored with a path, like when they are downloaded
+        restored_songs.push(RestoredSong {
+            song_url: song_sidecar.song_url,
+            playlist_url: song_sidecar.playlist_url,
+            file_path: Some(song_path).filter(|song_path| !song_path.is_dir()),
+            metadata: song_sidecar.metadata,
+        });
+    }
+
+    let sidecar_count = restored_songs.len();
+
+    // files without a sidecar are mapped from their tags, grouped by source url as the tracks of
+    // a split mix share the url of the mix
+    let mut tagged_files = HashMap::<(String, String), Vec<(PathBuf, SongTags)>>::new();
+    let mut unmapped_files = Vec::<(PathBuf, &str)>::new();
+
+    for file_path in library_files.iter() {
+        if !library_paths::is_song_path(file_path)
+            || file_path
+                .ancestors()
+                .any(|ancestor| mapped_paths.contains(ancestor))
+        {
+            continue;
+        }
+
+        let tag_format =
+            match audio_tag_appender::get_tag_format_from_path(file_path, settings.tagging()) {
+                Some(tag_format) => tag_format,
+                None => {
+                    unmapped_files.push((file_path.to_owned(), "its container has no tags"));
+                    continue;
+                }
+            };
+
+        let song_tags = match tag_format.read_tags(file_path) {
+            Ok(song_tags) => song_tags,
+            Err(e) => {
+                println!("{}", e);
+                unmapped_files.push((file_path.to_owned(), "its tags can not be read"));
+                continue;
+            }
+        };
+
+        let source_url = match &song_tags.source_url {
+            Some(source_url) => source_url.to_owned(),
+            None => {
+                unmapped_files.push((file_path.to_owned(), "it has no source url tag"));
+                continue;
+            }
+        };
+
+        let playlist_url = match song_tags.playlist_url.as_deref().or(default_playlist_url) {
+            Some(playlist_url) => playlist_url.to_string(),
+            None => {
+                unmapped_files.push((
+                    file_path.to_owned(),
+                    "it has no playlist url tag, give one with --playlist",
+                ));
+                continue;
+            }
+        };
+
+        tagged_files
+            .entry((source_url, playlist_url))
+            .or_default()
+            .push((file_path.to_owned(), song_tags));
+    }
+
+    let restored_song_urls = restored_songs
+        .iter()
+        .map(|restored_song| restored_song.song_url.to_owned())
+        .collect::<HashSet<String>>();
+
+    for ((song_url, playlist_url), files) in tagged_files {
+        // a song with a sidecar somewhere else in the library
+        if restored_song_urls.contains(&song_url) {
+            continue;
+        }
+
+        let restored_song = match files.as_slice() {
+            [(file_path, song_tags)] => RestoredSong {
+                song_url,
+                playlist_url,
+                file_path: Some(file_path.to_owned()),
+                metadata: get_stored_metadata(song_tags),
+            },
+            // the mix is the album of its tracks
+            _ => RestoredSong {
+                song_url,
+                playlist_url,
+                file_path: None,
+                metadata: StoredSongMetadata {
+                    title: files[0].1.album.to_owned().unwrap_or_default(),
+                    artist: files[0].1.album_artist.to_owned().unwrap_or_default(),
+                    genre: files[0].1.genre.to_owned().unwrap_or_default(),
+                    ..StoredSongMetadata::default()
+                },
+            },
+        };
+
+        restored_songs.push(restored_song);
+    }
+
+    let mut playlist_urls = HashSet::<String>::new();
+
+    for restored_song in restored_songs.iter() {
+        let stored_metadata = match serde_json::to_string(&restored_song.metadata) {
+            Ok(stored_metadata) => stored_metadata,
+            Err(e) => return Err(format!("Could not serialize the song metadata: {}", e)),
+        };
+
+        database_context.put_downloaded_song(
+            restored_song.song_url.to_owned(),
+            restored_song.playlist_url.to_owned(),
+            None,
+            restored_song
+                .file_path
+                .as_ref()
+                .map(|file_path| library_paths.to_stored_path(file_path)),
+            environment_variables,
+        )?;
+
+        database_context.update_song_metadata(
+            &restored_song.song_url,
+            &restored_song.playlist_url,
+            &stored_metadata,
+            environment_variables,
+        )?;
+
+        playlist_urls.insert(restored_song.playlist_url.to_owned());
+    }
+
+    for playlist_url in playlist_urls.iter() {
+        database_context.put_playlist(playlist_url.to_owned(), environment_variables)?;
+    }
+
+    // the batches are indexed again so the retention policy still prunes them
+    for (batch_name, archived_at) in get_archive_batches(library_paths)? {
+        database_context.put_archive_batch(&batch_name, archived_at, environment_variables)?;
+    }
+
+    println!(
+        "Restored {} songs of {} playlists, {} from sidecars and {} from tags",
+        restored_songs.len(),
+        playlist_urls.len(),
+        sidecar_count,
+        restored_songs.len() - sidecar_count
+    );
+
+    if !unmapped_files.is_empty() {
+        println!(
+            "Could not map {} files back to a source url:",
+            unmapped_files.len()
+        );
+
+        for (file_path, reason) in unmapped_files.iter() {
+            println!("  {}, {}", library_paths.to_stored_path(file_path), reason);
+        }
+    }
+
+    return Ok(());
+}
+
+// the stored metadata of a song without a sidecar, as it is tagged
+fn get_stored_metadata(song_tags: &SongTags) -> StoredSongMetadata {
+    return StoredSongMetadata {
+        title: song_tags.title.to_owned().unwrap_or_default(),
+        artist: song_tags.artist.to_owned().unwrap_or_default(),
+        genre: song_tags.genre.to_owned().unwrap_or_default(),
+        // the playlist is the album
+        playlist_name: song_tags.album.to_owned().unwrap_or_default(),
+        album_artist: song_tags.album_artist.to_owned(),
+        track_number: song_tags.track_number,
+        total_tracks: song_tags.total_tracks,
+        release_date: song_tags.release_date.to_owned(),
+        isrc: song_tags.isrc.to_owned(),
+        ..StoredSongMetadata::default()
+    };
+}
+
+// the batches in the archive folder, with when they were archived from their name
+fn get_archive_batches(library_paths: &LibraryPaths) -> Result<Vec<(String, i64)>, String> {
+    let archive_directory = library_paths.archive_directory();
+
+    if !archive_directory.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let directory_entries = match std::fs::read_dir(&archive_directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Err(format!(
+                "Could not read files from directory {}: {}",
+                archive_directory.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    let mut archive_batches = Vec::new();
+
+    for entry in directory_entries.flatten() {
+        if !entry.path().is_dir() {
+            continue;
+        }
+
+        let batch_name = entry.file_name().to_string_lossy().to_string();
+
+        // batches are named "%Y-%m-%d_%H-%M-%S", with a number after it when there are several
+        let archived_at = batch_name
+            .get(0..19)
+            .and_then(|date_time| {
+                NaiveDateTime::parse_from_str(date_time, "%Y-%m-%d_%H-%M-%S").ok()
+            })
+            .and_then(|date_time| Local.from_local_datetime(&date_time).earliest());
+
+        match archived_at {
+            Some(archived_at) => archive_batches.push((batch_name, archived_at.timestamp())),
+            None => println!(
+                "Skipping archive batch {}, its name is not a date",
+                batch_name
+            ),
+        }
+    }
+
+    return Ok(archive_batches);
+}
+
+// the files of the library, and the folders of split mixes, by their name
+fn get_library_files_by_name(library_files: &[PathBuf]) -> HashMap<String, Vec<PathBuf>> {
+    let mut library_files_by_name = HashMap::<String, Vec<PathBuf>>::new();
+
+    for file_path in library_files.iter() {
+        let parent_directory = file_path.parent().map(|parent| parent.to_path_buf());
+
+        for path in [Some(file_path.to_owned()), parent_directory]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(file_name) = path.file_name() {
+                let paths = library_files_by_name
+                    .entry(file_name.to_string_lossy().to_string())
+                    .or_default();
+
+                if !paths.contains(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+    }
+
+    return library_files_by_name;
+}
+// the song in the folder the sidecar belongs to, or else the only song in the library with its file name
+// the song next to the sidecar, or else the only song in the library with its file name
+fn find_song_path(
+    song_directory: Option<&Path>,
+    song_sidecar: &SongSidecar,
+    library_files_by_name: &HashMap<String, Vec<PathBuf>>,
+) -> Option<PathBuf> {
+    if song_sidecar.file_name.is_empty() {
+        return None;
+    }
+
+    if let Some(song_directory) = song_directory {
+        let song_path = song_directory.join(&song_sidecar.file_name);
+
+        if song_path.exists() {
+            return Some(song_path);
+        }
+    }
+
+    return match library_files_by_name.get(&song_sidecar.file_name) {
+        // a name in more than one batch can not tell which one is the song
+        Some(paths) if paths.len() == 1 => Some(paths[0].to_owned()),
+        _ => None,
+    };
+}
@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audio_tag_appender::{self, ReplayGain, SongTags};
+use crate::database::Database;
+use crate::environment_extractor::EnvironmentVariables;
+use crate::file_lister;
+use crate::library_paths::LibraryPaths;
+use crate::settings_parser::{AudioSettings, NormalizationSettings, Settings, TaggingSettings};
+use crate::tool_locator;
+
+// loudness replay gain 2.0 players bring songs to
+const REPLAY_GAIN_REFERENCE_LUFS: f64 = -18.0;
+
+// re-encoding for less than this is not worth the quality loss
+const MIN_APPLIED_GAIN_DB: f64 = 0.5;
+
+/// Integrated loudness and true peak of a song
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LoudnessMeasurement {
+    // LUFS
+    pub loudness: f64,
+    // dBTP
+    pub true_peak: f64,
+}
+
+impl LoudnessMeasurement {
+    pub fn to_replay_gain(&self) -> ReplayGain {
+        return ReplayGain {
+            gain_db: REPLAY_GAIN_REFERENCE_LUFS - self.loudness,
+            peak: 10f64.powf(self.true_peak / 20.0),
+        };
+    }
+
+    pub fn from_replay_gain(replay_gain: &ReplayGain) -> LoudnessMeasurement {
+        return LoudnessMeasurement {
+            loudness: REPLAY_GAIN_REFERENCE_LUFS - replay_gain.gain_db,
+            true_peak: 20.0 * replay_gain.peak.log10(),
+        };
+    }
+}
+
+/// Measure the loudness of a new song, and bring it to the target loudness if gain is applied.
+/// Returns the loudness of the file as it is afterwards
+pub fn normalize_song(
+    file_path: &Path,
+    settings: &Settings,
+) -> Result<LoudnessMeasurement, String> {
+    let ffmpeg = tool_locator::locate_ffmpeg(settings.ffmpeg())?;
+
+    let measurement = measure_loudness(&ffmpeg.path, file_path)?;
+
+    if !*settings.normalization().apply_gain() {
+        return Ok(measurement);
+    }
+
+    let gain_db = get_gain_to_target(&measurement, settings.normalization());
+
+    if gain_db.abs() < MIN_APPLIED_GAIN_DB {
+        return Ok(measurement);
+    }
+
+    apply_gain(&ffmpeg.path, file_path, gain_db, settings.audio())?;
+
+    // a volume change moves the loudness and the peak by the same amount
+    return Ok(LoudnessMeasurement {
+        loudness: measurement.loudness + gain_db,
+        true_peak: measurement.true_peak + gain_db,
+    });
+}
+
+/// Write the album replay gain of a playlist to its songs that are still in the library,
+/// from the loudness of every song of the playlist that was measured
+pub fn update_playlist_album_gain(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    playlist_url: &str,
+    tagging_settings: &TaggingSettings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let song_loudness =
+        database_context.get_playlist_song_loudness(playlist_url, environment_variables)?;
+
+    let songs: Vec<(PathBuf, LoudnessMeasurement)> = song_loudness
+        .iter()
+        .map(|(file_path, loudness, true_peak)| {
+            let measurement = LoudnessMeasurement {
+                loudness: *loudness,
+                true_peak: *true_peak,
+            };
+
+            return (library_paths.from_stored_path(file_path), measurement);
+        })
+        // pruned archive batches take their songs with them
+        .filter(|(file_path, _)| file_path.exists())
+        .collect();
+
+    write_album_replay_gain(&songs, tagging_settings);
+
+    return Ok(());
+}
+
+/// Measure the songs in the archive and downloaded directories and write their replay gain tags.
+/// Songs that already have a track gain are not measured again unless forced
+pub fn normalize_library(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+    force: bool,
+) -> Result<(), String> {
+    let ffmpeg = tool_locator::locate_ffmpeg(settings.ffmpeg())?;
+
+    let files = file_lister::list_files_in(&[
+        library_paths.archive_directory(),
+        library_paths.downloaded_directory(),
+    ])?;
+
+    // songs are grouped into albums by their album tag, which is the name of their playlist
+    let mut albums = HashMap::<String, Vec<(PathBuf, LoudnessMeasurement)>>::new();
+    let mut measured_count = 0;
+
+    for file_path in files {
+        let tag_format =
+            match audio_tag_appender::get_tag_format_from_path(&file_path, settings.tagging()) {
+                Some(tag_format) => tag_format,
+                None => continue,
+            };
+
+        let song_tags = match tag_format.read_tags(&file_path) {
+            Ok(song_tags) => song_tags,
+            Err(e) => {
+                println!("Skipping {}: {}", file_path.to_string_lossy(), e);
+                continue;
+            }
+        };
+
+        let measurement = match (&song_tags.track_replay_gain, force) {
+            (Some(track_replay_gain), false) => {
+                LoudnessMeasurement::from_replay_gain(track_replay_gain)
+            }
+            _ => {
+                let measurement = match measure_loudness(&ffmpeg.path, &file_path) {
+                    Ok(measurement) => measurement,
+                    Err(e) => {
+                        println!("Skipping {}: {}", file_path.to_string_lossy(), e);
+                        continue;
+                    }
+                };
+
+                let track_song_tags = SongTags {
+                    track_replay_gain: Some(measurement.to_replay_gain()),
+                    ..SongTags::default()
+                };
+
+                tag_format.write_tags(&file_path, &track_song_tags)?;
+
+                measured_count += 1;
+
+                measurement
+            }
+        };
+
+        database_context.update_song_loudness(
+            &library_paths.to_stored_path(&file_path),
+            measurement.loudness,
+            measurement.true_peak,
+            environment_variables,
+        )?;
+
+        if let Some(album) = song_tags.album {
+            albums
+                .entry(album)
+                .or_default()
+                .push((file_path, measurement));
+        }
+    }
+
+    for songs in albums.values() {
+        write_album_replay_gain(songs, settings.tagging());
+    }
+
+    println!(
+        "Measured {} songs, wrote album gain for {} albums",
+        measured_count,
+        albums.len()
+    );
+
+    return Ok(());
+}
+
+/// Measure the integrated loudness and true peak of the first audio stream of the file
+pub fn measure_loudness(
+    ffmpeg_path: &Path,
+    file_path: &Path,
+) -> Result<LoudnessMeasurement, String> {
+    // the analysis pass of the loudnorm filter prints its measurements as json
+    let command_output = match Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(file_path)
+        .args(["-map", "0:a:0", "-af", "loudnorm=print_format=json"])
+        .args(["-f", "null", "-"])
+        .output()
+    {
+        Ok(out) => out,
+        Err(e) => return Err(format!("Could not run ffmpeg: {}", e)),
+    };
+
+    let stderr_string = String::from_utf8_lossy(&command_output.stderr);
+
+    if !command_output.status.success() {
+        return Err(format!(
+            "ffmpeg could not measure the loudness of {}: {}",
+            file_path.to_string_lossy(),
+            stderr_string.lines().last().unwrap_or("")
+        ));
+    }
+
+    return parse_loudnorm_output(&stderr_string);
+}
+
+// the json the loudnorm filter prints at the end of the ffmpeg output
+fn parse_loudnorm_output(ffmpeg_output: &str) -> Result<LoudnessMeasurement, String> {
+    let json_text = match (ffmpeg_output.rfind('{'), ffmpeg_output.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &ffmpeg_output[start..=end],
+        _ => return Err("ffmpeg did not print the loudness measurements".to_string()),
+    };
+
+    let measurements: serde_json::Value = match serde_json::from_str(json_text) {
+        Ok(value) => value,
+        Err(e) => return Err(format!("Could not parse the loudness measurements: {}", e)),
+    };
+
+    // the values are strings, silence is measured as "-inf"
+    let get_measurement = |key: &str| -> Result<f64, String> {
+        return match measurements
+            .get(key)
+            .and_then(|value| value.as_str())
+            .and_then(|value| value.parse::<f64>().ok())
+        {
+            Some(value) if value.is_finite() => Ok(value),
+            _ => Err(format!("The song is too quiet to measure its {}", key)),
+        };
+    };
+
+    return Ok(LoudnessMeasurement {
+        loudness: get_measurement("input_i")?,
+        true_peak: get_measurement("input_tp")?,
+    });
+}
+
+// the loudness of the songs played one after another, approximated by averaging their energy
+fn get_album_measurement(measurements: &[LoudnessMeasurement]) -> Option<LoudnessMeasurement> {
+    if measurements.is_empty() {
+        return None;
+    }
+
+    let mean_energy = measurements
+        .iter()
+        .map(|measurement| 10f64.powf(measurement.loudness / 10.0))
+        .sum::<f64>()
+        / measurements.len() as f64;
+
+    let true_peak = measurements
+        .iter()
+        .map(|measurement| measurement.true_peak)
+        .fold(f64::NEG_INFINITY, f64::max);
+
+    return Some(LoudnessMeasurement {
+        loudness: 10.0 * mean_energy.log10(),
+        true_peak,
+    });
+}
+
+// the gain that reaches the target loudness, limited so the true peak stays under the limit
+fn get_gain_to_target(
+    measurement: &LoudnessMeasurement,
+    normalization_settings: &NormalizationSettings,
+) -> f64 {
+    let gain_to_target = normalization_settings.target_lufs() - measurement.loudness;
+    let gain_to_peak_limit = normalization_settings.max_true_peak() - measurement.true_peak;
+
+    return gain_to_target.min(gain_to_peak_limit);
+}
+
+// re-encode the file with its volume changed, the tags are written again afterwards
+fn apply_gain(
+    ffmpeg_path: &Path,
+    file_path: &Path,
+    gain_db: f64,
+    audio_settings: &AudioSettings,
+) -> Result<(), String> {
+    // ffmpeg picks the encoder from the extension, so the temporary file keeps it
+    let temporary_path = match (file_path.file_stem(), file_path.extension()) {
+        (Some(stem), Some(extension)) => file_path.with_file_name(format!(
+            "{}.normalizing.{}",
+            stem.to_string_lossy(),
+            extension.to_string_lossy()
+        )),
+        _ => {
+            return Err(format!(
+                "Can not apply gain to {}, it has no extension",
+                file_path.to_string_lossy()
+            ))
+        }
+    };
+
+    let mut command = Command::new(ffmpeg_path);
+    command
+        .arg("-y")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .args(["-loglevel", "error"])
+        .arg("-i")
+        .arg(file_path)
+        .args(["-map", "0:a:0", "-map_metadata", "-1"])
+        .arg("-af")
+        .arg(format!("volume={:.2}dB", gain_db));
+
+    // keep a fixed bitrate the song was downloaded with, vbr qualities differ between encoders
+    if let Some(bitrate) = audio_settings.bitrate() {
+        if let Some(kilobits) = bitrate.strip_suffix(['K', 'k']) {
+            command.arg("-b:a").arg(format!("{}k", kilobits));
+        }
+    }
+
+    let command_output = match command.arg(&temporary_path).output() {
+        Ok(out) => out,
+        Err(e) => return Err(format!("Could not run ffmpeg: {}", e)),
+    };
+
+    if !command_output.status.success() {
+        let _ = std::fs::remove_file(&temporary_path);
+
+        return Err(format!(
+            "ffmpeg could not apply gain to {}: {}",
+            file_path.to_string_lossy(),
+            String::from_utf8_lossy(&command_output.stderr).trim()
+        ));
+    }
+
+    if let Err(e) = std::fs::rename(&temporary_path, file_path) {
+        return Err(format!(
+            "Could not move {} into place: {}",
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Tag every song with the gain of the album they make up together
+pub fn write_album_replay_gain(
+    songs: &[(PathBuf, LoudnessMeasurement)],
+    tagging_settings: &TaggingSettings,
+) {
+    let measurements: Vec<LoudnessMeasurement> =
+        songs.iter().map(|(_, measurement)| *measurement).collect();
+
+    let album_measurement = match get_album_measurement(&measurements) {
+        Some(album_measurement) => album_measurement,
+        None => return,
+    };
+
+    let song_tags = SongTags {
+        album_replay_gain: Some(album_measurement.to_replay_gain()),
+        ..SongTags::default()
+    };
+
+    for (file_path, _) in songs.iter() {
+        let tag_format =
+            match audio_tag_appender::get_tag_format_from_path(file_path, tagging_settings) {
+                Some(tag_format) => tag_format,
+                None => continue,
+            };
+
+        // one song that can not be tagged should not stop the rest of the album
+        if let Err(e) = tag_format.write_tags(file_path, &song_tags) {
+            println!(
+                "Could not write album gain to {}: {}",
+                file_path.to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_album_measurement, parse_loudnorm_output, LoudnessMeasurement};
+
+    #[test]
+    fn test_loudness_measurements() {
+        let ffmpeg_output = r#"[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-9.85",
+	"input_tp" : "0.42",
+	"input_lra" : "5.60",
+	"input_thresh" : "-20.01",
+	"output_i" : "-24.21",
+	"output_tp" : "-2.00",
+	"output_lra" : "4.50",
+	"output_thresh" : "-34.36",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.21"
+}
+"#;
+
+        let measurement = parse_loudnorm_output(ffmpeg_output).unwrap();
+        assert_eq!(
+            measurement,
+            LoudnessMeasurement {
+                loudness: -9.85,
+                true_peak: 0.42
+            }
+        );
+
+        // loud songs are turned down, the peak is linear
+        let replay_gain = measurement.to_replay_gain();
+        assert!((replay_gain.gain_db - -8.15).abs() < 1e-9);
+        assert!((replay_gain.peak - 1.0495).abs() < 1e-4);
+
+        let silence_output = r#"{ "input_i" : "-inf", "input_tp" : "-inf" }"#;
+        assert!(parse_loudnorm_output(silence_output).is_err());
+
+        // two songs of equal loudness make an album of that loudness, the loudest peak wins
+        let album_measurement = get_album_measurement(&[
+            LoudnessMeasurement {
+                loudness: -14.0,
+                true_peak: -1.0,
+            },
+            LoudnessMeasurement {
+                loudness: -14.0,
+                true_peak: -3.0,
+            },
+        ])
+        .unwrap();
+        assert!((album_measurement.loudness - -14.0).abs() < 1e-9);
+        assert_eq!(album_measurement.true_peak, -1.0);
+    }
+}
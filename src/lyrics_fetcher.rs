@@ -0,0 +1,225 @@
+use regex::Regex;
+
+use crate::settings_parser::LyricsSettings;
+
+pub mod directory_provider;
+pub mod http_provider;
+
+// a timestamp at the start of an lrc line, ex. "[01:02.34]"
+const LRC_TIMESTAMP_REGEX: &str = r"^\[(\d+):(\d{1,2}(?:\.\d{1,3})?)\]";
+
+// a tag of the lrc file, ex. "[ar:Artist]" or "[offset:+200]"
+const LRC_TAG_REGEX: &str = r"^\[[a-z#]+:.*\]$";
+
+/// Lyrics of a song, synchronized when the provider knows when the lines are sung
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics {
+    pub plain: String,
+    // (milliseconds from the start, line), empty if the lyrics are not synchronized
+    pub synced_lines: Vec<(u32, String)>,
+}
+
+/* Common trait of the places lyrics can be fetched from */
+pub trait LyricsProvider {
+    fn name(&self) -> String;
+    // None if the provider has no lyrics for the song
+    fn find_lyrics(
+        &self,
+        artist: &str,
+        title: &str,
+        duration: Option<f64>,
+    ) -> Result<Option<Lyrics>, String>;
+}
+
+impl Lyrics {
+    /// Lyrics of an lrc file, plain if none of its lines have a timestamp
+    pub fn from_lrc(lrc_text: &str) -> Result<Lyrics, String> {
+        let lrc_timestamp_regex = get_regex(LRC_TIMESTAMP_REGEX)?;
+        let lrc_tag_regex = get_regex(LRC_TAG_REGEX)?;
+
+        let mut synced_lines = Vec::new();
+        let mut plain_lines = Vec::new();
+
+        for line in lrc_text.lines() {
+            let line = line.trim();
+
+            if lrc_tag_regex.is_match(line) {
+                continue;
+            }
+
+            // a line can be sung more than once, ex. "[00:12.00][01:30.00]Chorus"
+            let mut timestamps = Vec::new();
+            let mut text_start = 0;
+
+            while let Some(captures) = lrc_timestamp_regex.captures(&line[text_start..]) {
+                let minutes: f64 = captures[1].parse().unwrap_or(0.0);
+                let seconds: f64 = captures[2].parse().unwrap_or(0.0);
+
+                timestamps.push(((minutes * 60.0 + seconds) * 1000.0).round() as u32);
+                text_start += captures[0].len();
+            }
+
+            let text = line[text_start..].trim().to_string();
+
+            match timestamps.is_empty() {
+                true => plain_lines.push(text),
+                false => {
+                    for timestamp in timestamps {
+                        synced_lines.push((timestamp, text.to_owned()));
+                    }
+                }
+            }
+        }
+
+        if synced_lines.is_empty() {
+            return Ok(Lyrics {
+                plain: plain_lines.join("\n").trim().to_string(),
+                synced_lines,
+            });
+        }
+
+        synced_lines.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let plain = synced_lines
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+
+        return Ok(Lyrics {
+            plain: plain.trim().to_string(),
+            synced_lines,
+        });
+    }
+
+    /// The lyrics as an lrc file, with timestamps if they are synchronized
+    pub fn to_lrc(&self) -> String {
+        if self.synced_lines.is_empty() {
+            return self.plain.to_owned();
+        }
+
+        return self
+            .synced_lines
+            .iter()
+            .map(|(timestamp, text)| {
+                format!(
+                    "[{:02}:{:02}.{:02}]{}",
+                    timestamp / 60_000,
+                    timestamp / 1000 % 60,
+                    timestamp % 1000 / 10,
+                    text
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+    }
+}
+
+/// The lyrics providers of the settings, created once for the songs of a playlist
+pub struct LyricsFetcher {
+    providers: Vec<Box<dyn LyricsProvider>>,
+}
+
+impl LyricsFetcher {
+    /// The configured providers, none if lyrics are off. A lyrics folder that can not be read is
+    /// reported and left out, as the songs are downloaded without lyrics then
+    pub fn from_settings(lyrics_settings: &LyricsSettings) -> LyricsFetcher {
+        let mut providers: Vec<Box<dyn LyricsProvider>> = Vec::new();
+
+        if !*lyrics_settings.enabled() {
+            return LyricsFetcher { providers };
+        }
+
+        if let Some(directory) = lyrics_settings.directory() {
+            match directory_provider::DirectoryProvider::new(directory) {
+                Ok(directory_provider) => providers.push(Box::new(directory_provider)),
+                Err(e) => println!("Could not read the lyrics folder: {}", e),
+            }
+        }
+
+        if let Some(provider_url) = lyrics_settings.provider_url() {
+            providers.push(Box::new(http_provider::HttpProvider::new(provider_url)));
+        }
+
+        return LyricsFetcher { providers };
+    }
+
+    /// Lyrics of the song from the first provider that has them, synchronized lyrics are
+    /// preferred over plain lyrics. None if no provider has them
+    pub fn fetch_lyrics(&self, artist: &str, title: &str, duration: Option<f64>) -> Option<Lyrics> {
+        let mut plain_lyrics = None;
+
+        for provider in self.providers.iter() {
+            let lyrics = match provider.find_lyrics(artist, title, duration) {
+                Ok(Some(lyrics)) => lyrics,
+                Ok(None) => continue,
+                Err(e) => {
+                    println!(
+                        "Could not fetch the lyrics of {} - {} from {}: {}",
+                        artist,
+                        title,
+                        provider.name(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            if !lyrics.synced_lines.is_empty() {
+                return Some(lyrics);
+            }
+
+            plain_lyrics = plain_lyrics.or(Some(lyrics));
+        }
+
+        return plain_lyrics;
+    }
+}
+
+fn get_regex(pattern: &str) -> Result<Regex, String> {
+    return match Regex::new(pattern) {
+        Ok(regex) => Ok(regex),
+        Err(e) => Err(format!("Error creating regex {}, {}", pattern, e)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lyrics;
+
+    #[test]
+    fn test_lrc_lyrics() {
+        let lyrics = Lyrics::from_lrc(
+            "[ar:Daft Punk]\n\
+             [ti:One More Time]\n\
+             [00:05.5]One more time\n\
+             [00:12.00][01:30.25]We're gonna celebrate\n\
+             [00:20.120]Oh yeah, all right",
+        )
+        .unwrap();
+
+        assert_eq!(
+            lyrics.synced_lines,
+            vec![
+                (5500, "One more time".to_string()),
+                (12000, "We're gonna celebrate".to_string()),
+                (20120, "Oh yeah, all right".to_string()),
+                (90250, "We're gonna celebrate".to_string()),
+            ]
+        );
+        assert_eq!(
+            lyrics.plain,
+            "One more time\nWe're gonna celebrate\nOh yeah, all right\nWe're gonna celebrate"
+        );
+        assert_eq!(
+            lyrics.to_lrc().lines().last(),
+            Some("[01:30.25]We're gonna celebrate")
+        );
+        assert_eq!(Lyrics::from_lrc(&lyrics.to_lrc()).unwrap(), lyrics);
+
+        // lyrics without timestamps are plain
+        let lyrics = Lyrics::from_lrc("One more time\nWe're gonna celebrate\n").unwrap();
+        assert!(lyrics.synced_lines.is_empty());
+        assert_eq!(lyrics.to_lrc(), "One more time\nWe're gonna celebrate");
+    }
+}
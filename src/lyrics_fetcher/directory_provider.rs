@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{Lyrics, LyricsProvider};
+use crate::file_lister;
+use crate::metadata_enricher::normalize_name;
+
+/// Lyrics from a folder of "{artist} - {title}.lrc" files, the names are compared ignoring case,
+/// accents and punctuation
+pub struct DirectoryProvider {
+    directory: PathBuf,
+    // the lrc files of the folder by their normalized name, read once for every song of a run
+    lyrics_paths: HashMap<String, PathBuf>,
+}
+
+impl DirectoryProvider {
+    pub fn new(directory: &Path) -> Result<Self, String> {
+        let mut files = file_lister::list_files(directory)?;
+        files.sort();
+
+        let mut lyrics_paths = HashMap::new();
+
+        for file_path in files.into_iter().filter(|file_path| {
+            file_path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("lrc"))
+        }) {
+            if let Some(stem) = file_path.file_stem() {
+                lyrics_paths
+                    .entry(normalize_name(&stem.to_string_lossy()))
+                    .or_insert(file_path.to_owned());
+            }
+        }
+
+        return Ok(DirectoryProvider {
+            directory: directory.to_path_buf(),
+            lyrics_paths,
+        });
+    }
+}
+
+impl LyricsProvider for DirectoryProvider {
+    fn name(&self) -> String {
+        return self.directory.to_string_lossy().to_string();
+    }
+
+    fn find_lyrics(
+        &self,
+        artist: &str,
+        title: &str,
+        _duration: Option<f64>,
+    ) -> Result<Option<Lyrics>, String> {
+        let song_name = normalize_name(&format!("{} - {}", artist, title));
+
+        let lyrics_path = match self.lyrics_paths.get(&song_name) {
+            Some(lyrics_path) => lyrics_path,
+            None => return Ok(None),
+        };
+
+        let lrc_text = match std::fs::read_to_string(lyrics_path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read lyrics file {}: {}",
+                    lyrics_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        return Ok(Some(Lyrics::from_lrc(&lrc_text)?));
+    }
+}
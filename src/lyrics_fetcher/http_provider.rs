@@ -0,0 +1,76 @@
+use super::{Lyrics, LyricsProvider};
+
+const USER_AGENT: &str = concat!("Musix2/", env!("CARGO_PKG_VERSION"));
+
+/// Lyrics from a web service with the LRCLIB api, ex. "https://lrclib.net" or one hosted locally
+pub struct HttpProvider {
+    base_url: String,
+}
+
+impl HttpProvider {
+    pub fn new(base_url: &str) -> Self {
+        return HttpProvider {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        };
+    }
+}
+
+impl LyricsProvider for HttpProvider {
+    fn name(&self) -> String {
+        return self.base_url.to_owned();
+    }
+
+    fn find_lyrics(
+        &self,
+        artist: &str,
+        title: &str,
+        duration: Option<f64>,
+    ) -> Result<Option<Lyrics>, String> {
+        let mut request = ureq::get(&format!("{}/api/get", self.base_url))
+            .set("User-Agent", USER_AGENT)
+            .query("artist_name", artist)
+            .query("track_name", title);
+
+        // the length narrows the lookup down to the right recording
+        if let Some(duration) = duration {
+            request = request.query("duration", &format!("{}", duration.round() as u64));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(format!("Error making get lyrics request: {}", e)),
+        };
+
+        let response_body = match response.into_string() {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(format!(
+                    "Error retrieving response body from get lyrics request: {}",
+                    e
+                ))
+            }
+        };
+
+        let response_json: serde_json::Value = match serde_json::from_str(&response_body) {
+            Ok(json) => json,
+            Err(e) => return Err(format!("Could not parse the get lyrics response: {}", e)),
+        };
+
+        let get_lyrics_text = |key: &str| -> Option<&str> {
+            return response_json
+                .get(key)
+                .and_then(|value| value.as_str())
+                .filter(|value| !value.trim().is_empty());
+        };
+
+        return match (
+            get_lyrics_text("syncedLyrics"),
+            get_lyrics_text("plainLyrics"),
+        ) {
+            (Some(lrc_text), _) | (None, Some(lrc_text)) => Ok(Some(Lyrics::from_lrc(lrc_text)?)),
+            // instrumental songs have neither
+            (None, None) => Ok(None),
+        };
+    }
+}
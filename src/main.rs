@@ -1,35 +1,100 @@
+// the codebase prefers explicit returns
+#![allow(clippy::needless_return)]
+
 use database::Database;
+use database_sync::DatabaseSync;
 use environment_extractor::get_environment_variables;
+use library_paths::LibraryPaths;
 
+pub mod archiver;
 pub mod audio_tag_appender;
 pub mod command_line_runtime;
+pub mod cover_art;
 pub mod database;
+pub mod database_sync;
+pub mod download_error;
 pub mod environment_extractor;
 pub mod environment_initializer;
+pub mod file_lister;
+pub mod library_paths;
+pub mod library_rebuilder;
+pub mod loudness_normalizer;
+pub mod lyrics_fetcher;
+pub mod metadata_enricher;
+pub mod mix_splitter;
 pub mod music_sources;
+pub mod path_template;
 pub mod post_processor;
+pub mod progress_reporter;
+pub mod rate_limiter;
+pub mod retagger;
+pub mod s3_client;
 pub mod settings_parser;
+pub mod song_override;
+pub mod song_sidecar;
+pub mod song_storage;
+pub mod song_trimmer;
 pub mod title_extractor;
+pub mod tool_locator;
 pub mod url_enforcer;
 pub mod yt_dlp_caller;
 
-fn main() {
+fn main() -> Result<(), String> {
+    // parse command line arguments first, as they can change where the library lives
+    let args = command_line_runtime::parse_args();
+
     // Get environment variables
     let environment_variables = get_environment_variables().unwrap();
 
+    // parse the settings file, the flag wins over the environment variable
+    let settings_path = args
+        .settings
+        .clone()
+        .or(environment_variables.get_settings_path().to_owned());
+
+    let settings = settings_parser::load_settings(settings_path.as_deref()).unwrap();
+
+    // resolve where the library lives
+    let library_paths = LibraryPaths::resolve(
+        args.library_root.clone(),
+        args.db_path.clone(),
+        &environment_variables,
+        &settings,
+    )
+    .unwrap();
+
     // initialize the file environment
-    environment_initializer::init_file_env().unwrap();
+    environment_initializer::init_file_env(&library_paths).unwrap();
+
+    // the database can live in s3, so the docker image does not have to keep it
+    let database_sync = DatabaseSync::from_settings(&environment_variables, &settings).unwrap();
 
     // create contexts
-    let mut database_context = Database::default();
+    let mut database_context = Database::new(&library_paths, database_sync);
+
+    // execute the command
+    let command_result = command_line_runtime::execute_args(
+        args,
+        &mut database_context,
+        &environment_variables,
+        &settings,
+        &library_paths,
+    );
+
+    // the database is uploaded even if the command failed part way, it has the songs downloaded before that
+    let close_result = database_context.close();
+
+    // the error of the command is reported first, an upload error after it should not hide it
+    return match (command_result, close_result) {
+        (Err(command_error), Err(close_error)) => {
+            eprintln!("Error: {:?}", command_error);
 
-    //parse command line arguments and execute them
-    command_line_runtime::parse_args(&mut database_context, &environment_variables).unwrap();
+            Err(close_error)
+        }
+        (command_result, Ok(())) => command_result,
+        (Ok(()), Err(close_error)) => Err(close_error),
+    };
 }
 
-// TODO have the database file live on s3 for maintainability, as the docker image won't have to reset
-//TODO implement funcationality to create the database file in s3 if it does not already exist yet
-// - however, this exposes the risk of now it can create an object in s3, have to be specific about which object it can create
-// - already have to have the bucket exist
 // TODO migrate to anyhow
 // TODO unit tests for pasing using actual sample data from requests
@@ -0,0 +1,360 @@
+use std::path::Path;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::music_sources::SongInformation;
+use crate::settings_parser::Settings;
+
+pub mod csv_index;
+pub mod musicbrainz_client;
+pub mod sqlite_index;
+
+// weights of the parts of the confidence score
+const TITLE_WEIGHT: f64 = 0.45;
+const ARTIST_WEIGHT: f64 = 0.35;
+const DURATION_WEIGHT: f64 = 0.2;
+
+/// A recording in a metadata index
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub artist: String,
+    pub title: String,
+    pub album: Option<String>,
+    // YYYY-MM-DD, or only the year
+    pub release_date: Option<String>,
+    pub isrc: Option<String>,
+    // seconds
+    pub duration: Option<f64>,
+}
+
+/* Common trait of the places recordings can be looked up in */
+pub trait MetadataIndex {
+    fn name(&self) -> String;
+    // recordings that may be the song, the enricher decides which one is
+    fn find_recordings(&self, artist: &str, title: &str) -> Result<Vec<Recording>, String>;
+}
+
+/// The recording that best matches a song, and how sure we are it is the song, from 0 to 1
+pub struct RecordingMatch {
+    pub recording: Recording,
+    pub confidence: f64,
+    pub index_name: String,
+}
+
+/// Looks songs up in the configured indexes and fills in their canonical metadata
+pub struct MetadataEnricher {
+    indexes: Vec<Box<dyn MetadataIndex>>,
+    min_confidence: f64,
+    duration_tolerance_seconds: f64,
+}
+
+impl MetadataEnricher {
+    /// The enricher of the configured indexes, None if no index is configured
+    pub fn from_settings(settings: &Settings) -> Result<Option<MetadataEnricher>, String> {
+        let enrichment_settings = settings.enrichment();
+
+        let mut indexes: Vec<Box<dyn MetadataIndex>> = Vec::new();
+
+        if let Some(index_path) = enrichment_settings.index_path() {
+            indexes.push(open_index(index_path)?);
+        }
+
+        if let Some(musicbrainz_url) = enrichment_settings.musicbrainz_url() {
+            indexes.push(Box::new(musicbrainz_client::MusicBrainzClient::new(
+                musicbrainz_url,
+                settings.rate_limits().musicbrainz(),
+            )));
+        }
+
+        if indexes.is_empty() {
+            return Ok(None);
+        }
+
+        return Ok(Some(MetadataEnricher::new(
+            indexes,
+            *enrichment_settings.min_confidence(),
+            *enrichment_settings.duration_tolerance_seconds(),
+        )));
+    }
+
+    pub fn new(
+        indexes: Vec<Box<dyn MetadataIndex>>,
+        min_confidence: f64,
+        duration_tolerance_seconds: f64,
+    ) -> MetadataEnricher {
+        return MetadataEnricher {
+            indexes,
+            min_confidence,
+            duration_tolerance_seconds,
+        };
+    }
+
+    /// The song with the metadata of the recording that matches it, unchanged if no recording
+    /// scores at least the minimum confidence
+    pub fn enrich(
+        &self,
+        song_information: SongInformation,
+        duration: Option<f64>,
+    ) -> SongInformation {
+        let recording_match =
+            match self.find_best_match(&song_information.artist, &song_information.title, duration)
+            {
+                Some(recording_match) => recording_match,
+                None => return song_information,
+            };
+
+        if recording_match.confidence < self.min_confidence {
+            println!(
+                "Not enriching {}, the best match in {} has a confidence of {:.2}",
+                song_information.url, recording_match.index_name, recording_match.confidence
+            );
+
+            return song_information;
+        }
+
+        println!(
+            "Enriched {} from {} with a confidence of {:.2}",
+            song_information.url, recording_match.index_name, recording_match.confidence
+        );
+
+        let recording = recording_match.recording;
+
+        return SongInformation {
+            artist: recording.artist,
+            title: recording.title,
+            album: recording.album.or(song_information.album),
+            release_date: recording.release_date.or(song_information.release_date),
+            isrc: recording.isrc.or(song_information.isrc),
+            ..song_information
+        };
+    }
+
+    /// The best scoring recording of all indexes, an index that fails is skipped
+    pub fn find_best_match(
+        &self,
+        artist: &str,
+        title: &str,
+        duration: Option<f64>,
+    ) -> Option<RecordingMatch> {
+        let mut best_match: Option<RecordingMatch> = None;
+
+        for index in self.indexes.iter() {
+            let recordings = match index.find_recordings(artist, title) {
+                Ok(recordings) => recordings,
+                Err(e) => {
+                    println!(
+                        "Could not look up {} - {} in {}: {}",
+                        artist,
+                        title,
+                        index.name(),
+                        e
+                    );
+                    continue;
+                }
+            };
+
+            for recording in recordings {
+                let confidence = score_recording(
+                    artist,
+                    title,
+                    duration,
+                    &recording,
+                    self.duration_tolerance_seconds,
+                );
+
+                let is_better = match &best_match {
+                    Some(best_match) => confidence > best_match.confidence,
+                    None => true,
+                };
+
+                if is_better {
+                    best_match = Some(RecordingMatch {
+                        recording,
+                        confidence,
+                        index_name: index.name(),
+                    });
+                }
+            }
+        }
+
+        return best_match;
+    }
+}
+
+/// How sure we are the recording is the song, from 0 to 1, by the similarity of the title and
+/// artist, and of the duration when both are known
+pub fn score_recording(
+    artist: &str,
+    title: &str,
+    duration: Option<f64>,
+    recording: &Recording,
+    duration_tolerance_seconds: f64,
+) -> f64 {
+    let title_similarity = get_similarity(title, &recording.title);
+    let artist_similarity = get_similarity(artist, &recording.artist);
+
+    let duration_difference = match (duration, recording.duration) {
+        (Some(duration), Some(recording_duration)) => (duration - recording_duration).abs(),
+        // without durations only the names count
+        _ => {
+            return (TITLE_WEIGHT * title_similarity + ARTIST_WEIGHT * artist_similarity)
+                / (TITLE_WEIGHT + ARTIST_WEIGHT)
+        }
+    };
+
+    // a different length is a different recording, however alike the names are
+    if duration_difference > duration_tolerance_seconds {
+        return 0.0;
+    }
+
+    let duration_similarity = match duration_tolerance_seconds > 0.0 {
+        true => 1.0 - duration_difference / duration_tolerance_seconds,
+        false => 1.0,
+    };
+
+    return TITLE_WEIGHT * title_similarity
+        + ARTIST_WEIGHT * artist_similarity
+        + DURATION_WEIGHT * duration_similarity;
+}
+
+/// Lowercase letters and digits separated by single spaces, without accents, for comparing names
+pub fn normalize_name(name: &str) -> String {
+    let name: String = name
+        .nfkd()
+        .filter(|character| !unicode_normalization::char::is_combining_mark(*character))
+        .map(|character| match character.is_alphanumeric() {
+            true => character,
+            false => ' ',
+        })
+        .collect();
+
+    return name
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+}
+
+// similarity of two names from 0 to 1, one minus their edit distance relative to the longer one
+fn get_similarity(name: &str, other_name: &str) -> f64 {
+    let name: Vec<char> = normalize_name(name).chars().collect();
+    let other_name: Vec<char> = normalize_name(other_name).chars().collect();
+
+    let longest_length = name.len().max(other_name.len());
+
+    if longest_length == 0 {
+        return 1.0;
+    }
+
+    // levenshtein distance, one row at a time
+    let mut previous_row: Vec<usize> = (0..=other_name.len()).collect();
+
+    for (name_index, name_character) in name.iter().enumerate() {
+        let mut row = vec![name_index + 1];
+
+        for (other_index, other_character) in other_name.iter().enumerate() {
+            let substitution_cost = match name_character == other_character {
+                true => 0,
+                false => 1,
+            };
+
+            row.push(
+                (previous_row[other_index] + substitution_cost)
+                    .min(previous_row[other_index + 1] + 1)
+                    .min(row[other_index] + 1),
+            );
+        }
+
+        previous_row = row;
+    }
+
+    return 1.0 - previous_row[other_name.len()] as f64 / longest_length as f64;
+}
+
+// a csv file by its extension, a sqlite database otherwise
+fn open_index(index_path: &Path) -> Result<Box<dyn MetadataIndex>, String> {
+    let is_csv = index_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+
+    return match is_csv {
+        true => Ok(Box::new(csv_index::CsvIndex::load(index_path)?)),
+        false => Ok(Box::new(sqlite_index::SqliteIndex::open(index_path)?)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_index::CsvIndex;
+    use super::{normalize_name, score_recording, MetadataEnricher, Recording};
+    use crate::music_sources::SongInformation;
+
+    #[test]
+    fn test_enrich() {
+        assert_eq!(
+            normalize_name("Beyoncé — Halo (Live!)"),
+            "beyonce halo live"
+        );
+
+        let recording = Recording {
+            artist: "Bicep".to_string(),
+            title: "Glue".to_string(),
+            duration: Some(269.0),
+            ..Recording::default()
+        };
+
+        assert_eq!(
+            score_recording("Bicep", "Glue", Some(269.0), &recording, 10.0),
+            1.0
+        );
+        // too far off in length to be the same recording
+        assert_eq!(
+            score_recording("Bicep", "Glue", Some(400.0), &recording, 10.0),
+            0.0
+        );
+        assert!(score_recording("Bicep", "Glue", None, &recording, 10.0) > 0.99);
+        assert!(score_recording("Bicep", "Apricots", None, &recording, 10.0) < 0.5);
+
+        let csv_index = CsvIndex::parse(
+            "artist,title,album,date,isrc,duration\n\
+             Bicep,Glue,Bicep,2017-09-01,GBCFB1700203,269\n\
+             \"Floating Points\",\"Silhouettes (I, II & III)\",Elaenia,2015,,643.5\n",
+        )
+        .unwrap();
+
+        let metadata_enricher = MetadataEnricher::new(vec![Box::new(csv_index)], 0.85, 10.0);
+
+        let song_information = SongInformation {
+            url: "https://www.youtube.com/watch?v=abc".to_string(),
+            title: "glue".to_string(),
+            genre: "Electronic".to_string(),
+            artist: "BICEP".to_string(),
+            playlist_name: "Electronic".to_string(),
+            album: None,
+            album_artist: None,
+            track_number: None,
+            total_tracks: None,
+            release_date: None,
+            artwork_url: None,
+            isrc: None,
+        };
+
+        let enriched_song = metadata_enricher.enrich(song_information.clone(), Some(271.0));
+        assert_eq!(enriched_song.artist, "Bicep");
+        assert_eq!(enriched_song.title, "Glue");
+        assert_eq!(enriched_song.album.as_deref(), Some("Bicep"));
+        assert_eq!(enriched_song.release_date.as_deref(), Some("2017-09-01"));
+        assert_eq!(enriched_song.isrc.as_deref(), Some("GBCFB1700203"));
+
+        // below the minimum confidence nothing changes
+        let song_information = SongInformation {
+            title: "Silhouettes".to_string(),
+            artist: "Floating Points".to_string(),
+            ..song_information
+        };
+
+        let enriched_song = metadata_enricher.enrich(song_information, Some(643.0));
+        assert_eq!(enriched_song.title, "Silhouettes");
+        assert_eq!(enriched_song.album, None);
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{normalize_name, MetadataIndex, Recording};
+
+/// Recordings of a csv file with an "artist,title,album,date,isrc,duration" header,
+/// in any order, of which only artist and title are required
+pub struct CsvIndex {
+    recordings: Vec<Recording>,
+    // normalized words of the titles, to the recordings whose title has them
+    title_words: HashMap<String, Vec<usize>>,
+}
+
+impl CsvIndex {
+    pub fn load(csv_path: &Path) -> Result<CsvIndex, String> {
+        let csv_text = match std::fs::read_to_string(csv_path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read metadata index {}: {}",
+                    csv_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        return CsvIndex::parse(&csv_text);
+    }
+
+    pub fn parse(csv_text: &str) -> Result<CsvIndex, String> {
+        let mut lines = csv_text.lines().filter(|line| !line.trim().is_empty());
+
+        let header = match lines.next() {
+            Some(header) => parse_csv_line(header),
+            None => return Err("Metadata index csv has no header".to_string()),
+        };
+
+        let get_column = |name: &str| -> Option<usize> {
+            return header
+                .iter()
+                .position(|column| column.trim().eq_ignore_ascii_case(name));
+        };
+
+        let (artist_column, title_column) = match (get_column("artist"), get_column("title")) {
+            (Some(artist_column), Some(title_column)) => (artist_column, title_column),
+            _ => {
+                return Err(
+                    "Metadata index csv needs an \"artist\" and a \"title\" column".to_string(),
+                )
+            }
+        };
+
+        let album_column = get_column("album");
+        let date_column = get_column("date");
+        let isrc_column = get_column("isrc");
+        let duration_column = get_column("duration");
+
+        let mut recordings = Vec::new();
+        let mut title_words = HashMap::<String, Vec<usize>>::new();
+
+        for line in lines {
+            let values = parse_csv_line(line);
+
+            // empty values are missing values
+            let get_value = |column: Option<usize>| -> Option<String> {
+                return values
+                    .get(column?)
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty());
+            };
+
+            let recording = Recording {
+                artist: get_value(Some(artist_column)).unwrap_or_default(),
+                title: get_value(Some(title_column)).unwrap_or_default(),
+                album: get_value(album_column),
+                release_date: get_value(date_column),
+                isrc: get_value(isrc_column),
+                duration: get_value(duration_column).and_then(|duration| duration.parse().ok()),
+            };
+
+            for word in normalize_name(&recording.title).split(' ') {
+                title_words
+                    .entry(word.to_string())
+                    .or_default()
+                    .push(recordings.len());
+            }
+
+            recordings.push(recording);
+        }
+
+        return Ok(CsvIndex {
+            recordings,
+            title_words,
+        });
+    }
+}
+
+impl MetadataIndex for CsvIndex {
+    fn name(&self) -> String {
+        return "the csv index".to_string();
+    }
+
+    // the recordings whose title shares a word with the title
+    fn find_recordings(&self, _artist: &str, title: &str) -> Result<Vec<Recording>, String> {
+        let mut recording_indexes: Vec<usize> = normalize_name(title)
+            .split(' ')
+            .filter_map(|word| self.title_words.get(word))
+            .flatten()
+            .copied()
+            .collect();
+
+        recording_indexes.sort_unstable();
+        recording_indexes.dedup();
+
+        return Ok(recording_indexes
+            .into_iter()
+            .map(|recording_index| self.recordings[recording_index].to_owned())
+            .collect());
+    }
+}
+
+// values of a csv line, values in double quotes can hold commas and "" for a quote
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut is_quoted = false;
+    let mut characters = line.chars().peekable();
+
+    while let Some(character) = characters.next() {
+        match (character, is_quoted) {
+            ('"', true) => match characters.peek() {
+                Some('"') => {
+                    value.push('"');
+                    characters.next();
+                }
+                _ => is_quoted = false,
+            },
+            ('"', false) => is_quoted = true,
+            (',', false) => values.push(std::mem::take(&mut value)),
+            _ => value.push(character),
+        }
+    }
+
+    values.push(value);
+
+    return values;
+}
@@ -0,0 +1,206 @@
+use std::cell::RefCell;
+
+use super::{MetadataIndex, Recording};
+use crate::rate_limiter::RateLimiter;
+use crate::settings_parser::RateLimitSettings;
+
+// MusicBrainz asks every client to identify itself
+const USER_AGENT: &str = concat!("Musix2/", env!("CARGO_PKG_VERSION"));
+
+// recordings looked at for one song
+const MAX_CANDIDATES: usize = 10;
+
+/// Recording search of the MusicBrainz web service, or of a mirror or mock at another url
+pub struct MusicBrainzClient {
+    base_url: String,
+    rate_limiter: RefCell<RateLimiter>,
+}
+
+impl MusicBrainzClient {
+    pub fn new(base_url: &str, rate_limit_settings: &RateLimitSettings) -> Self {
+        return MusicBrainzClient {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            rate_limiter: RefCell::new(RateLimiter::new(rate_limit_settings)),
+        };
+    }
+}
+
+impl MetadataIndex for MusicBrainzClient {
+    fn name(&self) -> String {
+        return self.base_url.to_owned();
+    }
+
+    fn find_recordings(&self, artist: &str, title: &str) -> Result<Vec<Recording>, String> {
+        // quotes would end the phrases of the lucene query
+        let get_phrase = |text: &str| -> String {
+            return text.replace(['"', '\\'], " ");
+        };
+
+        let search_query = format!(
+            "recording:\"{}\" AND artist:\"{}\"",
+            get_phrase(title),
+            get_phrase(artist)
+        );
+
+        let request = ureq::get(&format!("{}/ws/2/recording", self.base_url))
+            .set("User-Agent", USER_AGENT)
+            .query("query", &search_query)
+            .query("fmt", "json")
+            .query("limit", &MAX_CANDIDATES.to_string());
+
+        let response = match self.rate_limiter.borrow_mut().call(request) {
+            Ok(response) => response,
+            Err(e) => return Err(format!("Error making recording search request: {}", e)),
+        };
+
+        let response_body = match response.into_string() {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(format!(
+                    "Error retrieving response body from recording search request: {}",
+                    e
+                ))
+            }
+        };
+
+        let response_json: serde_json::Value = match serde_json::from_str(&response_body) {
+            Ok(json) => json,
+            Err(e) => {
+                return Err(format!(
+                    "Could not parse the recording search response: {}",
+                    e
+                ))
+            }
+        };
+
+        return Ok(parse_recordings(&response_json));
+    }
+}
+
+// the recordings of a recording search response, with the first release they appeared on
+fn parse_recordings(response_json: &serde_json::Value) -> Vec<Recording> {
+    let recordings = match response_json
+        .get("recordings")
+        .and_then(|recordings| recordings.as_array())
+    {
+        Some(recordings) => recordings,
+        None => return Vec::new(),
+    };
+
+    let get_string = |value: &serde_json::Value, key: &str| -> Option<String> {
+        return value
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string());
+    };
+
+    return recordings
+        .iter()
+        .filter_map(|recording| {
+            // "A feat. B" is credited as two artists joined by " feat. "
+            let artist = recording
+                .get("artist-credit")?
+                .as_array()?
+                .iter()
+                .map(|credit| {
+                    format!(
+                        "{}{}",
+                        get_string(credit, "name").unwrap_or_default(),
+                        get_string(credit, "joinphrase").unwrap_or_default()
+                    )
+                })
+                .collect::<String>();
+
+            let first_release = recording
+                .get("releases")
+                .and_then(|releases| releases.as_array())
+                .and_then(|releases| releases.first());
+
+            return Some(Recording {
+                artist,
+                title: get_string(recording, "title")?,
+                album: first_release.and_then(|release| get_string(release, "title")),
+                release_date: get_string(recording, "first-release-date")
+                    .or(first_release.and_then(|release| get_string(release, "date"))),
+                isrc: recording
+                    .get("isrcs")
+                    .and_then(|isrcs| isrcs.as_array())
+                    .and_then(|isrcs| isrcs.first())
+                    .and_then(|isrc| isrc.as_str())
+                    .map(|isrc| isrc.to_string()),
+                // length is in milliseconds
+                duration: recording
+                    .get("length")
+                    .and_then(|length| length.as_f64())
+                    .map(|length| length / 1000.0),
+            });
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MusicBrainzClient;
+    use crate::metadata_enricher::MetadataIndex;
+    use crate::settings_parser::RateLimitSettings;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_musicbrainz_client() {
+        let response_body = serde_json::json!({
+            "recordings": [{
+                "title": "Halo",
+                "length": 261000,
+                "first-release-date": "2008-11-12",
+                "artist-credit": [
+                    { "name": "Beyonc\u{e9}", "joinphrase": " feat. " },
+                    { "name": "Someone" }
+                ],
+                "releases": [{ "title": "I Am... Sasha Fierce", "date": "2008-11-12" }],
+                "isrcs": ["USSM10804554"]
+            }]
+        })
+        .to_string();
+
+        // a mock of the web service that answers one request
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut request = [0; 4096];
+            let request_length = stream.read(&mut request).unwrap();
+
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response_body.len(),
+                response_body
+            )
+            .unwrap();
+
+            return String::from_utf8_lossy(&request[..request_length]).to_string();
+        });
+
+        let rate_limit_settings: RateLimitSettings =
+            serde_json::from_str(r#"{"jitter_seconds": 0}"#).unwrap();
+        let musicbrainz_client = MusicBrainzClient::new(&base_url, &rate_limit_settings);
+
+        let recordings = musicbrainz_client
+            .find_recordings("Beyonc\u{e9}", "Halo")
+            .unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("GET /ws/2/recording?query="));
+        assert!(request.contains("User-Agent: Musix2/"));
+
+        assert_eq!(recordings.len(), 1);
+        assert_eq!(recordings[0].artist, "Beyonc\u{e9} feat. Someone");
+        assert_eq!(recordings[0].album.as_deref(), Some("I Am... Sasha Fierce"));
+        assert_eq!(recordings[0].release_date.as_deref(), Some("2008-11-12"));
+        assert_eq!(recordings[0].isrc.as_deref(), Some("USSM10804554"));
+        assert_eq!(recordings[0].duration, Some(261.0));
+    }
+}
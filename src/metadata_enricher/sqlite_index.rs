@@ -0,0 +1,127 @@
+use std::path::Path;
+
+use rusqlite::{params, OpenFlags};
+
+use super::{normalize_name, MetadataIndex, Recording};
+
+// recordings looked at for one song
+const MAX_CANDIDATES: usize = 50;
+
+/// Recordings of a sqlite database with a
+/// "recordings (artist TEXT, title TEXT, album TEXT, date TEXT, isrc TEXT, duration REAL)" table.
+/// An fts5 table "recordings_fts (artist, title)" over it with the same rowids is used when it exists,
+/// otherwise titles have to match exactly, ignoring case
+pub struct SqliteIndex {
+    connection: rusqlite::Connection,
+    has_full_text_search: bool,
+}
+
+impl SqliteIndex {
+    pub fn open(database_path: &Path) -> Result<SqliteIndex, String> {
+        // the index is never written to
+        let connection = match rusqlite::Connection::open_with_flags(
+            database_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        ) {
+            Ok(connection) => connection,
+            Err(e) => {
+                return Err(format!(
+                    "Could not open metadata index {}: {}",
+                    database_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        let query = "SELECT COUNT(*) FROM sqlite_master WHERE name = 'recordings_fts'";
+
+        let has_full_text_search =
+            match connection.query_row(query, params![], |row| row.get::<usize, i64>(0)) {
+                Ok(count) => count > 0,
+                Err(e) => {
+                    return Err(format!(
+                        "Could not execute metadata index query: {}: {}",
+                        query, e
+                    ))
+                }
+            };
+
+        return Ok(SqliteIndex {
+            connection,
+            has_full_text_search,
+        });
+    }
+}
+
+impl MetadataIndex for SqliteIndex {
+    fn name(&self) -> String {
+        return "the sqlite index".to_string();
+    }
+
+    fn find_recordings(&self, _artist: &str, title: &str) -> Result<Vec<Recording>, String> {
+        let (query, search) = match self.has_full_text_search {
+            // any of the words of the title, in the title column
+            true => (
+                "SELECT artist, title, album, date, isrc, duration FROM recordings WHERE rowid IN (SELECT rowid FROM recordings_fts WHERE recordings_fts MATCH ?1 ORDER BY rank LIMIT ?2)",
+                normalize_name(title)
+                    .split(' ')
+                    .filter(|word| !word.is_empty())
+                    .map(|word| format!("title:\"{}\"", word))
+                    .collect::<Vec<String>>()
+                    .join(" OR "),
+            ),
+            false => (
+                "SELECT artist, title, album, date, isrc, duration FROM recordings WHERE title = ?1 COLLATE NOCASE LIMIT ?2",
+                title.to_string(),
+            ),
+        };
+
+        if search.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        //prepare statment
+        let mut statement = match self.connection.prepare(query) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create prepared statement in find recordings: {}: {}",
+                    query, e
+                ));
+            }
+        };
+
+        //execute query, map resulting rows
+        let recording_results = match statement.query_map(params![search, MAX_CANDIDATES], |row| {
+            Ok(Recording {
+                artist: row.get::<usize, Option<String>>(0)?.unwrap_or_default(),
+                title: row.get::<usize, Option<String>>(1)?.unwrap_or_default(),
+                album: row.get(2)?,
+                release_date: row.get(3)?,
+                isrc: row.get(4)?,
+                duration: row.get(5)?,
+            })
+        }) {
+            Ok(some) => some,
+            Err(e) => {
+                return Err(format!("Could not execute prepared statement and collect row information in find recordings: {}: {}", query, e));
+            }
+        };
+
+        let mut recordings = Vec::new();
+
+        for recording_result in recording_results {
+            match recording_result {
+                Ok(recording) => recordings.push(recording),
+                Err(e) => {
+                    return Err(format!(
+                        "Error fetching a row for prepared statement {} in find recordings: {}",
+                        query, e
+                    ));
+                }
+            };
+        }
+
+        return Ok(recordings);
+    }
+}
@@ -0,0 +1,234 @@
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::music_sources::DownloadInformation;
+use crate::settings_parser::Settings;
+use crate::song_trimmer;
+use crate::title_extractor::EmptyTitleExtractor;
+use crate::tool_locator;
+
+// a tracklist line of the description, ex. "00:00 Artist - Title", "[1:02:03] Artist - Title" or "01. 12:34 | Artist - Title"
+const TRACKLIST_LINE_REGEX: &str =
+    r"^\s*(?:\d{1,3}[.)]\s+)?[\[(]?((?:\d{1,2}:)?\d{1,2}:\d{2})[\])]?\s*(?:[-–—|:]\s*)?(\S.*?)\s*$";
+
+/// A track of a mix, times are in seconds of the mix
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixTrack {
+    pub artist: String,
+    pub title: String,
+    pub start_time: f64,
+    // None for the last track when the length of the mix is not known
+    pub end_time: Option<f64>,
+}
+
+/// The tracks of a mix from its chapters, or else from the tracklist in its description.
+/// Tracks without an artist get the artist of the mix, empty if the mix has no tracks
+pub fn get_mix_tracks(
+    download_information: &DownloadInformation,
+    mix_artist: &str,
+) -> Result<Vec<MixTrack>, String> {
+    let chapter_starts = get_chapter_starts(&download_information.info_json);
+
+    let track_starts = match chapter_starts.is_empty() {
+        false => chapter_starts,
+        true => match &download_information.description {
+            Some(description) => parse_tracklist(description)?,
+            None => Vec::new(),
+        },
+    };
+
+    // timestamps that do not count up are not a tracklist
+    if track_starts
+        .windows(2)
+        .any(|starts| starts[0].0 >= starts[1].0)
+    {
+        return Ok(Vec::new());
+    }
+
+    let mut mix_tracks = Vec::new();
+
+    for (track_index, (start_time, track_title)) in track_starts.iter().enumerate() {
+        let end_time = match track_starts.get(track_index + 1) {
+            Some((next_start_time, _)) => Some(*next_start_time),
+            None => download_information.duration,
+        };
+
+        // tracklists often separate the artist with a dash the title extractor does not know
+        let track_title = track_title.replace(['–', '—'], "-");
+
+        let title_extractor =
+            EmptyTitleExtractor::init(track_title, mix_artist.to_string()).extract_from_title()?;
+
+        mix_tracks.push(MixTrack {
+            artist: title_extractor.full_artist(),
+            title: title_extractor.full_title(),
+            start_time: *start_time,
+            end_time,
+        });
+    }
+
+    return Ok(mix_tracks);
+}
+
+/// Cut the tracks out of the mix into files next to it, in the order of the tracks
+pub fn cut_mix(
+    mix_path: &Path,
+    mix_tracks: &[MixTrack],
+    settings: &Settings,
+) -> Result<Vec<PathBuf>, String> {
+    let ffmpeg = tool_locator::locate_ffmpeg(settings.ffmpeg())?;
+
+    let (stem, extension) = match (mix_path.file_stem(), mix_path.extension()) {
+        (Some(stem), Some(extension)) => (stem.to_string_lossy(), extension.to_string_lossy()),
+        _ => {
+            return Err(format!(
+                "Can not split {}, it has no extension",
+                mix_path.to_string_lossy()
+            ))
+        }
+    };
+
+    let mut track_paths = Vec::new();
+
+    for (track_index, mix_track) in mix_tracks.iter().enumerate() {
+        let track_path = mix_path.with_file_name(format!(
+            "{}.track{:03}.{}",
+            stem,
+            track_index + 1,
+            extension
+        ));
+
+        if let Err(e) = song_trimmer::cut_audio(
+            &ffmpeg.path,
+            mix_path,
+            mix_track.start_time,
+            mix_track.end_time,
+            &track_path,
+        ) {
+            // do not leave the tracks cut so far behind
+            for track_path in track_paths.iter() {
+                let _ = std::fs::remove_file(track_path);
+            }
+
+            return Err(e);
+        }
+
+        track_paths.push(track_path);
+    }
+
+    return Ok(track_paths);
+}
+
+// (start time, title) of the chapters yt-dlp found
+fn get_chapter_starts(info_json: &serde_json::Value) -> Vec<(f64, String)> {
+    let chapters = match info_json.get("chapters").and_then(|value| value.as_array()) {
+        Some(chapters) => chapters,
+        None => return Vec::new(),
+    };
+
+    return chapters
+        .iter()
+        .filter_map(|chapter| {
+            let start_time = chapter.get("start_time")?.as_f64()?;
+            let title = chapter.get("title")?.as_str()?.trim().to_string();
+
+            return Some((start_time, title));
+        })
+        .collect();
+}
+
+// (start time, title) of the timestamped lines in the description
+fn parse_tracklist(description: &str) -> Result<Vec<(f64, String)>, String> {
+    let tracklist_line_regex = match Regex::new(TRACKLIST_LINE_REGEX) {
+        Ok(regex) => regex,
+        Err(e) => {
+            return Err(format!(
+                "Error creating regex {}, {}",
+                TRACKLIST_LINE_REGEX, e
+            ))
+        }
+    };
+
+    return Ok(description
+        .lines()
+        .filter_map(|line| {
+            let captures = tracklist_line_regex.captures(line)?;
+
+            let start_time = parse_timestamp(captures.get(1)?.as_str())?;
+            let title = captures.get(2)?.as_str().to_string();
+
+            return Some((start_time, title));
+        })
+        .collect());
+}
+
+// seconds of a "mm:ss" or "h:mm:ss" timestamp
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+
+    for part in timestamp.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+
+    return Some(seconds);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_mix_tracks, MixTrack};
+    use crate::music_sources::DownloadInformation;
+
+    #[test]
+    fn test_get_mix_tracks() {
+        let info_json = serde_json::json!({
+            "duration": 4000.0,
+            "description": "Tracklist:\n\
+                00:00 Intro\n\
+                [03:15] Bicep \u{2013} Glue\n\
+                02. 1:02:03 | Floating Points - Silhouettes\n\
+                Thanks for listening! Follow me at 12:00 on friday",
+        });
+
+        let download_information = DownloadInformation::from_info_json(info_json);
+
+        assert_eq!(
+            get_mix_tracks(&download_information, "DJ Mix").unwrap(),
+            vec![
+                MixTrack {
+                    artist: "DJ Mix".to_string(),
+                    title: "Intro".to_string(),
+                    start_time: 0.0,
+                    end_time: Some(195.0),
+                },
+                MixTrack {
+                    artist: "Bicep".to_string(),
+                    title: "Glue".to_string(),
+                    start_time: 195.0,
+                    end_time: Some(3723.0),
+                },
+                MixTrack {
+                    artist: "Floating Points".to_string(),
+                    title: "Silhouettes".to_string(),
+                    start_time: 3723.0,
+                    end_time: Some(4000.0),
+                },
+            ]
+        );
+
+        // chapters are used before the description
+        let info_json = serde_json::json!({
+            "description": "00:00 Artist - Ignored",
+            "chapters": [
+                { "start_time": 0.0, "end_time": 60.0, "title": "A - One" },
+                { "start_time": 60.0, "end_time": 120.0, "title": "B - Two" },
+            ],
+        });
+
+        let mix_tracks =
+            get_mix_tracks(&DownloadInformation::from_info_json(info_json), "DJ Mix").unwrap();
+        assert_eq!(mix_tracks.len(), 2);
+        assert_eq!(mix_tracks[1].artist, "B");
+        assert_eq!(mix_tracks[1].end_time, None);
+    }
+}
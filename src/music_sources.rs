@@ -1,9 +1,17 @@
 use std::path::PathBuf;
 
+use serde::{Deserialize, Serialize};
+
+use crate::download_error::DownloadError;
+use crate::library_paths::LibraryPaths;
+use crate::settings_parser::Settings;
+use crate::title_extractor::title_rules::TitleRules;
+use crate::yt_dlp_caller::DownloadProgressEvent;
+
 pub mod soundcloud_service;
 pub mod youtube_service;
 
-#[derive(PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum MusicSources {
     SOUNDCLOUD,
     YOUTUBE,
@@ -14,19 +22,198 @@ pub struct DownloadedSong {
     pub title: String,
     pub genre: String,
     pub artist: String,
+    pub playlist_name: String,
+    // album set by an override, the playlist is the album if not set
+    pub album: Option<String>,
+    // owner of the playlist, used as the album artist
+    pub album_artist: Option<String>,
+    // position in the playlist, starting at 1
+    pub track_number: Option<u32>,
+    pub total_tracks: Option<u32>,
+    // YYYY-MM-DD
+    pub release_date: Option<String>,
+    pub artwork_url: Option<String>,
+    pub isrc: Option<String>,
     pub file_location: PathBuf,
+    pub download_information: DownloadInformation,
+}
+
+/// Fields captured from the info json yt-dlp prints after the download
+pub struct DownloadInformation {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    // YYYYMMDD
+    pub upload_date: Option<String>,
+    // seconds
+    pub duration: Option<f64>,
+    pub description: Option<String>,
+    pub thumbnail: Option<String>,
+    pub webpage_url: Option<String>,
+    pub extension: Option<String>,
+    pub file_path: Option<String>,
+    // the complete info json, for fields that are not captured above
+    pub info_json: serde_json::Value,
 }
 
+#[derive(Clone)]
 pub struct SongInformation {
     pub url: String,
     pub title: String,
     pub genre: String,
     pub artist: String,
+    pub playlist_name: String,
+    // album set by an override, the playlist is the album if not set
+    pub album: Option<String>,
+    // owner of the playlist, used as the album artist
+    pub album_artist: Option<String>,
+    // position in the playlist, starting at 1
+    pub track_number: Option<u32>,
+    pub total_tracks: Option<u32>,
+    // YYYY-MM-DD
+    pub release_date: Option<String>,
+    pub artwork_url: Option<String>,
+    pub isrc: Option<String>,
+}
+
+/// Metadata of a downloaded song as it was extracted from its source, kept in the database to retag the song later
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoredSongMetadata {
+    pub title: String,
+    pub genre: String,
+    pub artist: String,
+    pub playlist_name: String,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub total_tracks: Option<u32>,
+    pub release_date: Option<String>,
+    pub artwork_url: Option<String>,
+    pub isrc: Option<String>,
+    // the title as it was uploaded, to extract the title and artist again
+    pub source_title: Option<String>,
+    // fields of the info json used by the file name template
+    pub source_id: Option<String>,
+    pub uploader: Option<String>,
+    pub upload_date: Option<String>,
+    // seconds
+    pub duration: Option<f64>,
+}
+
+impl DownloadedSong {
+    pub fn from_song_information(
+        song_information: &SongInformation,
+        file_location: PathBuf,
+        download_information: DownloadInformation,
+    ) -> DownloadedSong {
+        return DownloadedSong {
+            url: song_information.url.to_owned(),
+            title: song_information.title.to_owned(),
+            genre: song_information.genre.to_owned(),
+            artist: song_information.artist.to_owned(),
+            playlist_name: song_information.playlist_name.to_owned(),
+            album: song_information.album.to_owned(),
+            album_artist: song_information.album_artist.to_owned(),
+            track_number: song_information.track_number,
+            total_tracks: song_information.total_tracks,
+            release_date: song_information.release_date.to_owned(),
+            artwork_url: song_information.artwork_url.to_owned(),
+            isrc: song_information.isrc.to_owned(),
+            file_location,
+            download_information,
+        };
+    }
+}
+
+impl StoredSongMetadata {
+    pub fn new(
+        song_information: &SongInformation,
+        download_information: &DownloadInformation,
+    ) -> StoredSongMetadata {
+        return StoredSongMetadata {
+            title: song_information.title.to_owned(),
+            genre: song_information.genre.to_owned(),
+            artist: song_information.artist.to_owned(),
+            playlist_name: song_information.playlist_name.to_owned(),
+            album: song_information.album.to_owned(),
+            album_artist: song_information.album_artist.to_owned(),
+            track_number: song_information.track_number,
+            total_tracks: song_information.total_tracks,
+            release_date: song_information.release_date.to_owned(),
+            artwork_url: song_information.artwork_url.to_owned(),
+            isrc: song_information.isrc.to_owned(),
+            source_title: download_information.title.to_owned(),
+            source_id: download_information.id.to_owned(),
+            uploader: download_information.uploader.to_owned(),
+            upload_date: download_information.upload_date.to_owned(),
+            duration: download_information.duration,
+        };
+    }
+
+    pub fn to_song_information(&self, url: &str) -> SongInformation {
+        return SongInformation {
+            url: url.to_string(),
+            title: self.title.to_owned(),
+            genre: self.genre.to_owned(),
+            artist: self.artist.to_owned(),
+            playlist_name: self.playlist_name.to_owned(),
+            album: self.album.to_owned(),
+            album_artist: self.album_artist.to_owned(),
+            track_number: self.track_number,
+            total_tracks: self.total_tracks,
+            release_date: self.release_date.to_owned(),
+            artwork_url: self.artwork_url.to_owned(),
+            isrc: self.isrc.to_owned(),
+        };
+    }
+
+    /// The download information with only the stored fields of the info json
+    pub fn to_download_information(&self) -> DownloadInformation {
+        return DownloadInformation::from_info_json(serde_json::json!({
+            "id": self.source_id,
+            "title": self.source_title,
+            "uploader": self.uploader,
+            "upload_date": self.upload_date,
+            "duration": self.duration,
+        }));
+    }
+}
+
+impl DownloadInformation {
+    pub fn from_info_json(info_json: serde_json::Value) -> DownloadInformation {
+        let get_string = |key: &str| -> Option<String> {
+            return info_json
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string());
+        };
+
+        return DownloadInformation {
+            id: get_string("id"),
+            title: get_string("title"),
+            uploader: get_string("uploader"),
+            upload_date: get_string("upload_date"),
+            duration: info_json.get("duration").and_then(|value| value.as_f64()),
+            description: get_string("description"),
+            thumbnail: get_string("thumbnail"),
+            webpage_url: get_string("webpage_url"),
+            extension: get_string("ext"),
+            // filepath is the final path after post processing, _filename is the fallback
+            file_path: get_string("filepath").or(get_string("_filename")),
+            info_json,
+        };
+    }
 }
 
 /* Common trait defining the behavior of a music service */
 pub trait MusicSource {
-    fn download_song(&self, song_information: &SongInformation) -> Result<DownloadedSong, String>;
+    fn download_song(
+        &self,
+        song_information: &SongInformation,
+        settings: &Settings,
+        on_progress: &mut dyn FnMut(&DownloadProgressEvent),
+    ) -> Result<DownloadedSong, DownloadError>;
     fn get_playlist_song_information(&self, url: &str) -> Result<Vec<SongInformation>, String>;
 }
 
@@ -79,10 +266,22 @@ pub fn get_music_source_from_url(url: &str) -> Result<MusicSources, String> {
     return Ok(origin_enum);
 }
 
-pub fn get_music_source_from_enum(music_source: MusicSources) -> Box<dyn MusicSource> {
+pub fn get_music_source_from_enum(
+    music_source: MusicSources,
+    settings: &Settings,
+    title_rules: &TitleRules,
+    library_paths: &LibraryPaths,
+) -> Box<dyn MusicSource> {
     if music_source == MusicSources::SOUNDCLOUD {
-        return Box::new(soundcloud_service::SoundcloudMusicService::new());
+        return Box::new(soundcloud_service::SoundcloudMusicService::new(
+            settings.rate_limits().soundcloud(),
+            library_paths,
+        ));
     } else {
-        return Box::new(youtube_service::YoutubeMusicService::new());
+        return Box::new(youtube_service::YoutubeMusicService::new(
+            settings.rate_limits().youtube(),
+            title_rules,
+            library_paths,
+        ));
     }
 }
@@ -10,17 +10,28 @@
 // donwload songs from playlist
 
 use regex;
+use std::cell::RefCell;
 use ureq;
 
-use crate::yt_dlp_caller;
+use crate::download_error::DownloadError;
+use crate::library_paths::LibraryPaths;
+use crate::rate_limiter::RateLimiter;
+use crate::settings_parser::{RateLimitSettings, Settings};
+use crate::yt_dlp_caller::{self, DownloadProgressEvent};
 
 use super::{MusicSource, SongInformation};
 
-pub struct SoundcloudMusicService {}
+pub struct SoundcloudMusicService {
+    rate_limiter: RefCell<RateLimiter>,
+    library_paths: LibraryPaths,
+}
 
 impl SoundcloudMusicService {
-    pub fn new() -> Self {
-        return SoundcloudMusicService {};
+    pub fn new(rate_limit_settings: &RateLimitSettings, library_paths: &LibraryPaths) -> Self {
+        return SoundcloudMusicService {
+            rate_limiter: RefCell::new(RateLimiter::new(rate_limit_settings)),
+            library_paths: library_paths.clone(),
+        };
     }
 }
 
@@ -29,8 +40,22 @@ impl MusicSource for SoundcloudMusicService {
     fn download_song(
         &self,
         song_information: &SongInformation,
-    ) -> Result<super::DownloadedSong, String> {
-        return yt_dlp_caller::download_song(song_information);
+        settings: &Settings,
+        on_progress: &mut dyn FnMut(&DownloadProgressEvent),
+    ) -> Result<super::DownloadedSong, DownloadError> {
+        let mut rate_limiter = self.rate_limiter.borrow_mut();
+        rate_limiter.acquire();
+
+        let download_result = yt_dlp_caller::download_song(
+            song_information,
+            settings,
+            &self.library_paths,
+            on_progress,
+        );
+
+        rate_limiter.record_download(&download_result);
+
+        return download_result;
     }
 
     fn get_playlist_song_information(
@@ -38,7 +63,7 @@ impl MusicSource for SoundcloudMusicService {
         url: &str,
     ) -> Result<Vec<super::SongInformation>, String> {
         // Fetch the main page of the playlist
-        let response = match ureq::get(url).call() {
+        let response = match self.rate_limiter.borrow_mut().call(ureq::get(url)) {
             Ok(response) => response,
             Err(e) => return Err(format!("Error making get playlist request: {}", e)),
         };
@@ -99,7 +124,7 @@ impl MusicSource for SoundcloudMusicService {
         // for each script
         for script_url in script_urls {
             // fetch script content
-            // Fetch the main page of the playlist
+            // scripts are static assets from the cdn, so they are not rate limited
             let response = match ureq::get(&script_url).call() {
                 Ok(response) => response,
                 Err(e) => {
@@ -219,6 +244,19 @@ impl MusicSource for SoundcloudMusicService {
                     }
                 };
 
+                // name of the playlist, used in the file name templates
+                let playlist_name = hydration_data
+                    .get("title")
+                    .and_then(|title| title.as_str())
+                    .unwrap_or("N/A");
+
+                // owner of the playlist, used as the album artist
+                let playlist_owner = hydration_data
+                    .get("user")
+                    .and_then(|user| user.get("username"))
+                    .and_then(|username| username.as_str())
+                    .map(|username| username.to_string());
+
                 let hydration_track_data = match hydration_data.get("tracks") {
                     Some(data) => data,
                     None => {
@@ -241,7 +279,7 @@ impl MusicSource for SoundcloudMusicService {
                     }
                 };
 
-                for hydration_track in hydration_tracks {
+                for (track_index, hydration_track) in hydration_tracks.iter().enumerate() {
                     let track_id = match hydration_track.get("id") {
                         Some(data) => data,
                         None => {
@@ -250,19 +288,27 @@ impl MusicSource for SoundcloudMusicService {
                     };
 
                     // if the permalink can be fetched
-                    if hydration_track.get("permalink_url").is_some() {
+                    let mut song_information: SongInformation = if hydration_track
+                        .get("permalink_url")
+                        .is_some()
+                    {
                         // get rest of the song information
-                        let song_information: SongInformation =
-                            get_song_information_from_track_information(hydration_track)?;
-
-                        song_informations.push(song_information);
+                        get_song_information_from_track_information(hydration_track, playlist_name)?
                     } else {
                         // permalink cannot be fetched, need to get more track information
-                        let song_information: SongInformation =
-                            get_track_information_from_track_id(&track_id.to_string(), client_id)?;
+                        get_track_information_from_track_id(
+                            &track_id.to_string(),
+                            client_id,
+                            playlist_name,
+                            &self.rate_limiter,
+                        )?
+                    };
 
-                        song_informations.push(song_information);
-                    }
+                    song_information.album_artist = playlist_owner.to_owned();
+                    song_information.track_number = Some(track_index as u32 + 1);
+                    song_information.total_tracks = Some(hydration_tracks.len() as u32);
+
+                    song_informations.push(song_information);
                 }
             }
         }
@@ -292,13 +338,18 @@ impl MusicSource for SoundcloudMusicService {
 fn get_track_information_from_track_id(
     track_id: &str,
     client_id: &str,
+    playlist_name: &str,
+    rate_limiter: &RefCell<RateLimiter>,
 ) -> Result<super::SongInformation, String> {
     // ------------ start get track information
     // create soundcloud track id request
     let get_track_information_url = format!("https://api-v2.soundcloud.com/tracks?ids={track_id}&client_id={client_id}&app_version=1737385876&app_locale=en");
 
     // Fetch the main page of the playlist
-    let track_information_response = match ureq::get(&get_track_information_url).call() {
+    let track_information_response = match rate_limiter
+        .borrow_mut()
+        .call(ureq::get(&get_track_information_url))
+    {
         Ok(response) => response,
         Err(e) => return Err(format!("Error making get playlist request: {}", e)),
     };
@@ -348,13 +399,14 @@ fn get_track_information_from_track_id(
 
     // get track information
     let song_information_result =
-        get_song_information_from_track_information(track_information_track);
+        get_song_information_from_track_information(track_information_track, playlist_name);
 
     return song_information_result;
 }
 
 fn get_song_information_from_track_information(
     information_json: &serde_json::Value,
+    playlist_name: &str,
 ) -> Result<SongInformation, String> {
     // permalink_url: url of the song to use
     let permalink_url = match serde_json_get_str_val_helper(information_json, "permalink_url", None)
@@ -418,11 +470,45 @@ fn get_song_information_from_track_information(
         title: title.to_string(),
         genre: genre.to_string(),
         artist: username.to_string(),
+        playlist_name: playlist_name.to_string(),
+        album: None,
+        // playlist information is filled in by the caller
+        album_artist: None,
+        track_number: None,
+        total_tracks: None,
+        // created_at is an ISO 8601 timestamp, keep the date
+        release_date: information_json
+            .get("created_at")
+            .and_then(|created_at| created_at.as_str())
+            .and_then(|created_at| created_at.get(..10))
+            .map(|date| date.to_string()),
+        artwork_url: get_artwork_url(information_json),
+        // labels fill in the publisher metadata of the tracks they release
+        isrc: information_json
+            .get("publisher_metadata")
+            .and_then(|publisher_metadata| publisher_metadata.get("isrc"))
+            .and_then(|isrc| isrc.as_str())
+            .filter(|isrc| !isrc.is_empty())
+            .map(|isrc| isrc.to_string()),
     };
 
     return Ok(song_information);
 }
 
+// url of the largest version of the track artwork, falling back to the uploader's avatar
+fn get_artwork_url(information_json: &serde_json::Value) -> Option<String> {
+    let artwork_url = information_json
+        .get("artwork_url")
+        .and_then(|artwork_url| artwork_url.as_str())
+        .or(information_json
+            .get("user")
+            .and_then(|user| user.get("avatar_url"))
+            .and_then(|avatar_url| avatar_url.as_str()))?;
+
+    // the urls point to the small "large" version by default
+    return Some(artwork_url.replace("-large.", "-t500x500."));
+}
+
 fn serde_json_get_str_val_helper(
     val: &serde_json::Value,
     key: &str,
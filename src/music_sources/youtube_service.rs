@@ -1,14 +1,25 @@
+use std::cell::RefCell;
 use ureq;
 
 use super::{MusicSource, SongInformation};
+use crate::download_error::DownloadError;
+use crate::library_paths::LibraryPaths;
+use crate::rate_limiter::RateLimiter;
+use crate::settings_parser::{RateLimitSettings, Settings};
+use crate::title_extractor::title_rules::TitleRules;
 use crate::title_extractor::{
     EmptyTitleExtractor, FinishedTitleExtractor, InitializedTitleExtractor,
 };
-use crate::yt_dlp_caller;
+use crate::yt_dlp_caller::{self, DownloadProgressEvent};
 
 const GOOGLE_API_KEY: &str = include_str!("../../resources/api_key.txt");
 
-pub struct YoutubeMusicService {}
+pub struct YoutubeMusicService {
+    rate_limiter: RefCell<RateLimiter>,
+    // applied to video titles before the default extraction
+    title_rules: TitleRules,
+    library_paths: LibraryPaths,
+}
 
 pub struct Video {
     // Define Video struct fields her
@@ -19,8 +30,16 @@ pub struct Video {
 }
 
 impl YoutubeMusicService {
-    pub fn new() -> Self {
-        return YoutubeMusicService {};
+    pub fn new(
+        rate_limit_settings: &RateLimitSettings,
+        title_rules: &TitleRules,
+        library_paths: &LibraryPaths,
+    ) -> Self {
+        return YoutubeMusicService {
+            rate_limiter: RefCell::new(RateLimiter::new(rate_limit_settings)),
+            title_rules: title_rules.clone(),
+            library_paths: library_paths.clone(),
+        };
     }
 }
 
@@ -28,14 +47,31 @@ impl MusicSource for YoutubeMusicService {
     fn download_song(
         &self,
         song_information: &SongInformation,
-    ) -> Result<super::DownloadedSong, String> {
-        return yt_dlp_caller::download_song(song_information);
+        settings: &Settings,
+        on_progress: &mut dyn FnMut(&DownloadProgressEvent),
+    ) -> Result<super::DownloadedSong, DownloadError> {
+        let mut rate_limiter = self.rate_limiter.borrow_mut();
+        rate_limiter.acquire();
+
+        let download_result = yt_dlp_caller::download_song(
+            song_information,
+            settings,
+            &self.library_paths,
+            on_progress,
+        );
+
+        rate_limiter.record_download(&download_result);
+
+        return download_result;
     }
 
     fn get_playlist_song_information(
         &self,
         url: &str,
     ) -> Result<Vec<super::SongInformation>, String> {
+        // title rules are scoped by the url the playlist was added with
+        let added_playlist_url = url;
+
         // get playlist id from url
         let url_regex =
             match regex::Regex::new(r"https://www\.youtube\.com/playlist\?list=([A-z0-9]+)") {
@@ -48,9 +84,9 @@ impl MusicSource for YoutubeMusicService {
             .ok_or("Could not extract playlist id from url")?
             .as_str();
 
-        // get playlist information from https://www.googleapis.com/youtube/v3/playlists?part=snippet%2Clocalizations&id=" + playlistId + "&fields=items(localizations%2Csnippet%2Flocalized%2Ftitle)&key=" + KEY;
-        let url = format!("https://www.googleapis.com/youtube/v3/playlists?part=snippet%2Clocalizations&id={}&fields=items(localizations%2Csnippet%2Flocalized%2Ftitle)&key={}", playlist_url, GOOGLE_API_KEY);
-        let response = match ureq::get(&url).call() {
+        // get playlist information from https://www.googleapis.com/youtube/v3/playlists?part=snippet%2Clocalizations&id=" + playlistId + "&fields=items(localizations%2Csnippet%2Flocalized%2Ftitle%2Csnippet%2FchannelTitle)&key=" + KEY;
+        let url = format!("https://www.googleapis.com/youtube/v3/playlists?part=snippet%2Clocalizations&id={}&fields=items(localizations%2Csnippet%2Flocalized%2Ftitle%2Csnippet%2FchannelTitle)&key={}", playlist_url, GOOGLE_API_KEY);
+        let response = match self.rate_limiter.borrow_mut().call(ureq::get(&url)) {
             Ok(response) => response,
             Err(err) => {
                 return Err(format!(
@@ -115,6 +151,12 @@ impl MusicSource for YoutubeMusicService {
             .as_str()
             .ok_or("Could not convert title information to string")?;
 
+        // owner of the playlist, used as the album artist
+        let playlist_channel_title = snippet_information
+            .get("channelTitle")
+            .and_then(|channel_title| channel_title.as_str())
+            .map(|channel_title| channel_title.to_string());
+
         let mut playlist_videos = Vec::new();
         let base_url = format!("https://www.googleapis.com/youtube/v3/playlistItems?part=snippet&maxResults=25&playlistId={}&key={}&page_token=", playlist_url, GOOGLE_API_KEY);
 
@@ -124,7 +166,11 @@ impl MusicSource for YoutubeMusicService {
         // for each page in the pagnated result
         loop {
             // get the next page
-            let response = match ureq::get(&format!("{}{}", base_url, page_token)).call() {
+            let response = match self
+                .rate_limiter
+                .borrow_mut()
+                .call(ureq::get(&format!("{}{}", base_url, page_token)))
+            {
                 Ok(some) => some,
                 Err(e) => {
                     return Err(format!("Could not make request to google api: {}", e));
@@ -219,7 +265,7 @@ impl MusicSource for YoutubeMusicService {
                     }
                 };
 
-                let _published_at = match video_snippet.get("publishedAt") {
+                let published_at = match video_snippet.get("publishedAt") {
                     Some(value) => value.as_str().unwrap_or(""),
                     None => {
                         return Err(
@@ -232,16 +278,29 @@ impl MusicSource for YoutubeMusicService {
                 // get title from youtube video title
                 let title_extractor: InitializedTitleExtractor =
                     EmptyTitleExtractor::init(title.to_string(), channel_title.to_string());
-                let title_extractor: FinishedTitleExtractor =
-                    title_extractor.extract_from_title()?;
+                let title_extractor: FinishedTitleExtractor = title_extractor
+                    .extract_with_rules(&self.title_rules, Some(added_playlist_url))?;
 
                 // create Video instance with extracted data
                 let song_information = super::SongInformation {
                     url: song_url.to_owned(),
-                    title: title_extractor.name().to_owned(),
+                    title: title_extractor.full_title(),
                     // genre is the title of the playlist
                     genre: playlist_title.to_owned(),
-                    artist: title_extractor.artist().to_owned(),
+                    artist: title_extractor.full_artist(),
+                    playlist_name: playlist_title.to_owned(),
+                    album: None,
+                    album_artist: playlist_channel_title.to_owned(),
+                    // position is zero based
+                    track_number: video_snippet
+                        .get("position")
+                        .and_then(|position| position.as_u64())
+                        .map(|position| position as u32 + 1),
+                    total_tracks: None,
+                    // publishedAt is an ISO 8601 timestamp, keep the date
+                    release_date: published_at.get(..10).map(|date| date.to_string()),
+                    artwork_url: get_best_thumbnail_url(video_snippet),
+                    isrc: None,
                 };
 
                 playlist_videos.push(song_information);
@@ -264,6 +323,31 @@ impl MusicSource for YoutubeMusicService {
             };
         }
 
+        // the size of the playlist is only known once every page is fetched
+        let total_tracks = playlist_videos.len() as u32;
+
+        for song_information in playlist_videos.iter_mut() {
+            song_information.total_tracks = Some(total_tracks);
+        }
+
         return Ok(playlist_videos);
     }
 }
+
+// url of the largest thumbnail of the video
+fn get_best_thumbnail_url(video_snippet: &serde_json::Value) -> Option<String> {
+    let thumbnails = video_snippet.get("thumbnails")?;
+
+    for thumbnail_size in ["maxres", "standard", "high", "medium", "default"] {
+        let thumbnail_url = thumbnails
+            .get(thumbnail_size)
+            .and_then(|thumbnail| thumbnail.get("url"))
+            .and_then(|url| url.as_str());
+
+        if let Some(thumbnail_url) = thumbnail_url {
+            return Some(thumbnail_url.to_string());
+        }
+    }
+
+    return None;
+}
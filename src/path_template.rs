@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::settings_parser::{CollisionPolicy, OutputSettings, TargetFilesystem};
+
+// characters that are not allowed in file names on windows, they are replaced on every file system
+// so the library can be copied between them
+const INVALID_FILENAME_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+// device names windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// both limits are 255, in bytes on unix and in utf-16 code units on windows
+const DEFAULT_MAX_NAME_LENGTH: usize = 255;
+
+// used in place of names that end up empty
+const EMPTY_NAME_REPLACEMENT: &str = "_";
+
+/// Rules the folder and file names are made to follow
+pub struct NameRules {
+    filesystem: TargetFilesystem,
+    max_name_length: usize,
+}
+
+impl NameRules {
+    pub fn from_settings(output_settings: &OutputSettings) -> Self {
+        let filesystem = match output_settings.filesystem() {
+            Some(filesystem) => *filesystem,
+            None => match std::env::consts::OS {
+                "windows" => TargetFilesystem::Windows,
+                _ => TargetFilesystem::Unix,
+            },
+        };
+
+        return NameRules {
+            filesystem,
+            max_name_length: output_settings
+                .max_name_length()
+                .unwrap_or(DEFAULT_MAX_NAME_LENGTH),
+        };
+    }
+
+    // length of a name as the file system counts it
+    fn name_length(&self, name: &str) -> usize {
+        return match self.filesystem {
+            TargetFilesystem::Unix => name.len(),
+            TargetFilesystem::Windows => name.encode_utf16().count(),
+        };
+    }
+}
+
+/// Render a template like "{genre}/{artist}/{title}.{ext}" into a relative path,
+/// every folder and file name is made valid for the target file system
+pub fn render_path_template(
+    template: &str,
+    values: &HashMap<&str, String>,
+    name_rules: &NameRules,
+) -> Result<PathBuf, String> {
+    let mut path = PathBuf::new();
+
+    let components: Vec<&str> = template.split('/').collect();
+
+    for (component_index, component_template) in components.iter().enumerate() {
+        let rendered_component = render_component(component_template, values)?;
+
+        // the last component is the file name, which keeps its extension when truncated
+        let is_file_name = component_index == components.len() - 1;
+
+        path.push(sanitize_name(&rendered_component, is_file_name, name_rules));
+    }
+
+    return Ok(path);
+}
+
+/// Decide where a song goes when its path may already exist, None if it should be skipped
+pub fn resolve_collision(
+    path: &Path,
+    collision_policy: CollisionPolicy,
+    name_rules: &NameRules,
+) -> Option<PathBuf> {
+    if !path.exists() {
+        return Some(path.to_path_buf());
+    }
+
+    match collision_policy {
+        CollisionPolicy::Overwrite => return Some(path.to_path_buf()),
+        CollisionPolicy::Skip => return None,
+        CollisionPolicy::Suffix => (),
+    };
+
+    let file_name = match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().to_string(),
+        None => return Some(path.to_path_buf()),
+    };
+
+    let (stem, extension) = split_extension(&file_name);
+
+    let mut suffix_number: u32 = 1;
+
+    loop {
+        let suffix = format!(" ({})", suffix_number);
+        let candidate = path.with_file_name(fit_name(stem, &suffix, extension, name_rules));
+
+        if !candidate.exists() {
+            return Some(candidate);
+        }
+
+        suffix_number += 1;
+    }
+}
+
+// replace the {name} placeholders in one folder or file name
+fn render_component(
+    component_template: &str,
+    values: &HashMap<&str, String>,
+) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut remaining = component_template;
+
+    while let Some(placeholder_start) = remaining.find('{') {
+        rendered.push_str(&remaining[..placeholder_start]);
+
+        let placeholder_end = match remaining[placeholder_start..].find('}') {
+            Some(i) => placeholder_start + i,
+            None => {
+                return Err(format!(
+                    "Unclosed placeholder in file name template: {}",
+                    component_template
+                ))
+            }
+        };
+
+        let name = &remaining[placeholder_start + 1..placeholder_end];
+
+        let value = match values.get(name) {
+            Some(value) => value,
+            None => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in file name template, expected one of: {}",
+                    name,
+                    get_sorted_names(values)
+                ))
+            }
+        };
+
+        // values can not create folders of their own
+        rendered.push_str(&value.replace(['/', '\\'], " "));
+
+        remaining = &remaining[placeholder_end + 1..];
+    }
+
+    rendered.push_str(remaining);
+
+    return Ok(rendered);
+}
+
+fn get_sorted_names(values: &HashMap<&str, String>) -> String {
+    let mut names: Vec<&str> = values.keys().copied().collect();
+    names.sort();
+
+    return names.join(", ");
+}
+
+// make one folder or file name valid for the target file system
+fn sanitize_name(name: &str, is_file_name: bool, name_rules: &NameRules) -> String {
+    // the same title can be written in composed or decomposed form, always use the composed one
+    let mut sanitized: String = name
+        .nfc()
+        .map(|character| {
+            if character.is_control() || INVALID_FILENAME_CHARS.contains(&character) {
+                return ' ';
+            }
+
+            return character;
+        })
+        .collect();
+
+    sanitized = trim_name(&sanitized, name_rules).to_string();
+
+    if sanitized.is_empty() || sanitized == "." || sanitized == ".." {
+        sanitized = EMPTY_NAME_REPLACEMENT.to_string();
+    }
+
+    let (stem, extension) = match is_file_name {
+        true => split_extension(&sanitized),
+        false => (sanitized.as_str(), ""),
+    };
+
+    let mut stem = stem.to_string();
+
+    // windows does not allow device names, even with an extension, ex. CON.mp3
+    if name_rules.filesystem == TargetFilesystem::Windows {
+        let device_name = stem.split('.').next().unwrap_or("");
+
+        if WINDOWS_RESERVED_NAMES.contains(&device_name.trim_end().to_uppercase().as_str()) {
+            stem.insert(device_name.len(), '_');
+        }
+    }
+
+    return fit_name(&stem, "", extension, name_rules);
+}
+
+// truncate the stem so the stem, suffix and extension fit in the name length limit
+fn fit_name(stem: &str, suffix: &str, extension: &str, name_rules: &NameRules) -> String {
+    let fixed_length = name_rules.name_length(suffix) + name_rules.name_length(extension);
+    let max_stem_length = name_rules.max_name_length.saturating_sub(fixed_length);
+
+    let mut truncated_stem = String::new();
+
+    for character in stem.chars() {
+        let mut candidate = truncated_stem.clone();
+        candidate.push(character);
+
+        if name_rules.name_length(&candidate) > max_stem_length {
+            break;
+        }
+
+        truncated_stem = candidate;
+    }
+
+    // truncating can leave a trailing space or dot behind
+    let mut truncated_stem = trim_name(&truncated_stem, name_rules).to_string();
+
+    if truncated_stem.is_empty() {
+        truncated_stem = EMPTY_NAME_REPLACEMENT.to_string();
+    }
+
+    return format!("{}{}{}", truncated_stem, suffix, extension);
+}
+
+// windows drops trailing dots and spaces from names, so they are removed on windows targets
+fn trim_name<'a>(name: &'a str, name_rules: &NameRules) -> &'a str {
+    let name = name.trim();
+
+    return match name_rules.filesystem {
+        TargetFilesystem::Windows => name.trim_end_matches(['.', ' ']),
+        TargetFilesystem::Unix => name,
+    };
+}
+
+// split "name.ext" into ("name", ".ext"), names without an extension have an empty one
+fn split_extension(file_name: &str) -> (&str, &str) {
+    return match file_name.rfind('.') {
+        Some(i) if i > 0 => file_name.split_at(i),
+        _ => (file_name, ""),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_path_template, NameRules};
+    use crate::settings_parser::TargetFilesystem;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    fn get_values() -> HashMap<&'static str, String> {
+        return HashMap::from([
+            ("genre", "Drum & Bass".to_string()),
+            ("artist", "AC/DC".to_string()),
+            ("title", "Cafe\u{301} del Mar...".to_string()),
+            ("source_id", "abc123".to_string()),
+            ("ext", "mp3".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_render_path_template() {
+        let windows_rules = NameRules {
+            filesystem: TargetFilesystem::Windows,
+            max_name_length: 255,
+        };
+
+        let path = render_path_template(
+            "{genre}/{artist}/{title} [{source_id}].{ext}",
+            &get_values(),
+            &windows_rules,
+        )
+        .unwrap();
+
+        // values do not create folders, and the title is in composed form
+        assert_eq!(
+            path,
+            PathBuf::from("Drum & Bass/AC DC/Caf\u{e9} del Mar... [abc123].mp3")
+        );
+
+        // trailing dots and reserved names on windows
+        let path = render_path_template("con/{title}", &get_values(), &windows_rules).unwrap();
+        assert_eq!(path, PathBuf::from("con_/Caf\u{e9} del Mar"));
+
+        // names are truncated, keeping the extension
+        let short_rules = NameRules {
+            filesystem: TargetFilesystem::Unix,
+            max_name_length: 10,
+        };
+
+        let path = render_path_template("{title}.{ext}", &get_values(), &short_rules).unwrap();
+        assert_eq!(path, PathBuf::from("Caf\u{e9}.mp3"));
+
+        assert!(render_path_template("{album}.{ext}", &get_values(), &short_rules).is_err());
+    }
+}
@@ -1,48 +1,702 @@
-use std::{path::PathBuf, str::FromStr};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use crate::{audio_tag_appender, music_sources::DownloadedSong};
+use crate::loudness_normalizer::{self, LoudnessMeasurement};
+use crate::lyrics_fetcher::{Lyrics, LyricsFetcher};
+use crate::mix_splitter::{self, MixTrack};
+use crate::path_template::{self, NameRules};
+use crate::settings_parser::Settings;
+use crate::song_sidecar::{self, SongSidecar};
+use crate::song_storage::SongStorage;
+use crate::song_trimmer::{self, AppliedTrim};
+use crate::{audio_tag_appender, library_paths::LibraryPaths, music_sources::DownloadedSong};
+
+// value of placeholders whose information is not known
+const UNKNOWN_VALUE: &str = "Unknown";
+
+// extension of the lyrics files written next to songs
+const LRC_EXTENSION: &str = "lrc";
+
+// file name of the tracks of a split mix, in the folder of the mix
+const MIX_TRACK_TEMPLATE: &str = "{track_number} {artist} - {title}.{ext}";
+
+/// A song after post processing
+pub struct ProcessedSong {
+    // None if the song was skipped because another song already has its path
+    pub file_path: Option<PathBuf>,
+    // None if the song was not measured
+    pub loudness: Option<LoudnessMeasurement>,
+    // parts cut from the start and end of the song
+    pub trims: Vec<AppliedTrim>,
+    // the tracks the song was split into, the file path is then the folder of the tracks
+    pub split_tracks: Vec<SplitTrack>,
+}
+
+/// A track of a mix after post processing
+pub struct SplitTrack {
+    // position in the mix, starting at 1
+    pub track_number: u32,
+    pub file_path: PathBuf,
+    pub mix_track: MixTrack,
+}
 
 // manage downloaded song, return new location
 // returns the path to the new song
-pub fn post_process_downloaded_song(downloaded_song: DownloadedSong) -> Result<PathBuf, String> {
+// the optional steps (trimming, loudness, cover art, lyrics and sidecars) are best effort, their
+// errors are printed and the song is kept without them, as a song without them beats no song
+pub fn post_process_downloaded_song(
+    downloaded_song: DownloadedSong,
+    song_sidecar: &SongSidecar,
+    song_storage: Option<&SongStorage>,
+    lyrics_fetcher: &LyricsFetcher,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<ProcessedSong, String> {
+    if *settings.split().enabled() {
+        let mix_tracks = mix_splitter::get_mix_tracks(
+            &downloaded_song.download_information,
+            &downloaded_song.artist,
+        )?;
+
+        if mix_tracks.len() >= *settings.split().min_tracks() {
+            return post_process_mix(
+                downloaded_song,
+                &mix_tracks,
+                song_sidecar,
+                song_storage,
+                lyrics_fetcher,
+                settings,
+                library_paths,
+            );
+        }
+    }
+
+    // trim before measuring, silence and intros change the loudness
+    let trims = match song_trimmer::trim_song(
+        &downloaded_song.file_location,
+        &downloaded_song.download_information,
+        settings,
+    ) {
+        Ok(trims) => trims,
+        Err(e) => {
+            println!("Could not trim {}: {}", downloaded_song.url, e);
+
+            Vec::new()
+        }
+    };
+
+    let loudness = measure_song(
+        &downloaded_song.file_location,
+        &downloaded_song.url,
+        settings,
+    );
+
+    let track_replay_gain = match settings.normalization().replay_gain() {
+        true => loudness.map(|loudness| loudness.to_replay_gain()),
+        false => None,
+    };
+
     // add metadata to song file
-    audio_tag_appender::append_metadata(&downloaded_song)?;
+    audio_tag_appender::append_metadata(
+        &downloaded_song,
+        &song_sidecar.playlist_url,
+        settings.tagging(),
+        track_replay_gain,
+    )?;
+
+    let lyrics = embed_lyrics(
+        &downloaded_song.file_location,
+        &downloaded_song.artist,
+        &downloaded_song.title,
+        downloaded_song.download_information.duration,
+        lyrics_fetcher,
+        settings,
+    );
+
+    // keep the extension of the downloaded file, as it depends on the audio format
+    let file_extension = get_file_extension(&downloaded_song.file_location)?;
+
+    // get the path of the song from the file name template
+    let templated_file_path = library_paths
+        .downloaded_directory()
+        .join(get_templated_path(
+            &downloaded_song,
+            &file_extension,
+            settings,
+        )?);
+
+    let renamed_file_path = match place_in_library(
+        &downloaded_song.file_location,
+        &templated_file_path,
+        settings,
+    )? {
+        Some(path) => path,
+        None => {
+            // the file at the path is another song, so this one is kept without a file, and the
+            // loudness measured and the trims were of the dropped download
+            return Ok(ProcessedSong {
+                file_path: None,
+                loudness: None,
+                trims: Vec::new(),
+                split_tracks: Vec::new(),
+            });
+        }
+    };
+
+    if let Some(lyrics) = lyrics {
+        write_lrc_file(&renamed_file_path, &lyrics, settings);
+    }
+
+    write_sidecars(&renamed_file_path, song_sidecar, settings, library_paths);
+
+    if let Some(song_storage) = song_storage {
+        song_storage.try_store_song(&renamed_file_path, library_paths);
+    }
+
+    return Ok(ProcessedSong {
+        file_path: Some(renamed_file_path),
+        loudness,
+        trims,
+        split_tracks: Vec::new(),
+    });
+}
+
+// split a mix into tracks, tagged as an album of their own, in a folder named after the mix
+fn post_process_mix(
+    downloaded_song: DownloadedSong,
+    mix_tracks: &[MixTrack],
+    song_sidecar: &SongSidecar,
+    song_storage: Option<&SongStorage>,
+    lyrics_fetcher: &LyricsFetcher,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<ProcessedSong, String> {
+    let file_extension = get_file_extension(&downloaded_song.file_location)?;
+
+    let track_paths = mix_splitter::cut_mix(&downloaded_song.file_location, mix_tracks, settings)?;
+
+    // the templated path of the mix, without its extension, is the folder of the tracks
+    let output_settings = settings.output();
+    let name_rules = NameRules::from_settings(output_settings);
+
+    let mix_relative_path = get_templated_path(&downloaded_song, &file_extension, settings)?;
+
+    let mix_directory = library_paths
+        .downloaded_directory()
+        .join(mix_relative_path.with_extension(""));
+
+    // every track shares the source, date and cover art of the mix
+    let mix_song_tags = audio_tag_appender::SongTags {
+        playlist_url: Some(song_sidecar.playlist_url.to_owned()),
+        ..audio_tag_appender::get_song_tags(&downloaded_song, settings.tagging(), None)
+    };
+
+    let mut split_tracks = Vec::new();
+    let mut track_loudness = Vec::new();
+
+    for (track_index, (mix_track, track_path)) in mix_tracks.iter().zip(track_paths).enumerate() {
+        let track_number = track_index as u32 + 1;
+
+        let loudness = measure_song(&track_path, &downloaded_song.url, settings);
+
+        let track_replay_gain = match settings.normalization().replay_gain() {
+            true => loudness.map(|loudness| loudness.to_replay_gain()),
+            false => None,
+        };
+
+        let song_tags = audio_tag_appender::SongTags {
+            title: Some(mix_track.title.to_owned()),
+            artist: Some(mix_track.artist.to_owned()),
+            // the mix is the album
+            album: Some(downloaded_song.title.to_owned()),
+            album_artist: Some(downloaded_song.artist.to_owned()),
+            track_number: Some(track_number),
+            total_tracks: Some(mix_tracks.len() as u32),
+            track_replay_gain,
+            ..mix_song_tags.clone()
+        };
+
+        if let Err(e) =
+            audio_tag_appender::write_song_tags(&track_path, &song_tags, settings.tagging())
+        {
+            println!(
+                "Could not tag track {} of {}: {}",
+                track_number, downloaded_song.url, e
+            );
+        }
+
+        let lyrics = embed_lyrics(
+            &track_path,
+            &mix_track.artist,
+            &mix_track.title,
+            mix_track
+                .end_time
+                .map(|end_time| end_time - mix_track.start_time),
+            lyrics_fetcher,
+            settings,
+        );
+
+        let track_values = HashMap::from([
+            ("track_number", format!("{:02}", track_number)),
+            ("artist", mix_track.artist.to_owned()),
+            ("title", mix_track.title.to_owned()),
+            ("ext", file_extension.to_owned()),
+        ]);
+
+        let templated_track_path = mix_directory.join(path_template::render_path_template(
+            MIX_TRACK_TEMPLATE,
+            &track_values,
+            &name_rules,
+        )?);
+
+        let track_file_path = match place_in_library(&track_path, &templated_track_path, settings)?
+        {
+            Some(path) => path,
+            None => continue,
+        };
+
+        if let Some(lyrics) = lyrics {
+            write_lrc_file(&track_file_path, &lyrics, settings);
+        }
+
+        if let Some(loudness) = loudness {
+            track_loudness.push((track_file_path.to_owned(), loudness));
+        }
+
+        split_tracks.push(SplitTrack {
+            track_number,
+            file_path: track_file_path,
+            mix_track: mix_track.to_owned(),
+        });
+    }
+
+    if *settings.normalization().replay_gain() {
+        loudness_normalizer::write_album_replay_gain(&track_loudness, settings.tagging());
+    }
+
+    // uploaded once the album replay gain is in their tags
+    if let Some(song_storage) = song_storage {
+        for split_track in split_tracks.iter() {
+            song_storage.try_store_song(&split_track.file_path, library_paths);
+        }
+    }
+
+    // the tracks hold all of the mix
+    if let Err(e) = std::fs::remove_file(&downloaded_song.file_location) {
+        return Err(format!(
+            "Could not remove split mix {}: {}",
+            downloaded_song.file_location.to_string_lossy(),
+            e
+        ));
+    }
+
+    // the mix has one sidecar, next to the folder of its tracks
+    if mix_directory.is_dir() {
+        write_sidecars(&mix_directory, song_sidecar, settings, library_paths);
+    }
+
+    println!(
+        "Split {} into {} tracks",
+        downloaded_song.url,
+        split_tracks.len()
+    );
+
+    return Ok(ProcessedSong {
+        file_path: Some(mix_directory),
+        loudness: None,
+        trims: Vec::new(),
+        split_tracks,
+    });
+}
+
+/// Move a song in the library to the path the file name template gives it now, songs in an
+/// archive batch stay in their batch. Returns the path of the song, which is unchanged if it is not moved
+pub fn rename_song(
+    downloaded_song: &DownloadedSong,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<PathBuf, String> {
+    let file_path = &downloaded_song.file_location;
+
+    let file_extension = get_file_extension(file_path)?;
+
+    let templated_file_path =
+        library_paths
+            .get_library_directory(file_path)?
+            .join(get_templated_path(
+                downloaded_song,
+                &file_extension,
+                settings,
+            )?);
+
+    if has_templated_name(file_path, &templated_file_path) {
+        return Ok(file_path.to_owned());
+    }
+
+    let output_settings = settings.output();
+
+    let renamed_file_path = match path_template::resolve_collision(
+        &templated_file_path,
+        *output_settings.on_collision(),
+        &NameRules::from_settings(output_settings),
+    ) {
+        Some(path) => path,
+        None => {
+            println!(
+                "{} already exists, not renaming {}",
+                templated_file_path.to_string_lossy(),
+                file_path.to_string_lossy()
+            );
+
+            return Ok(file_path.to_owned());
+        }
+    };
+
+    move_into_library(file_path, &renamed_file_path)?;
+
+    // the lrc file of the song follows it
+    let lrc_file_path = file_path.with_extension(LRC_EXTENSION);
+
+    if lrc_file_path.is_file() {
+        move_into_library(
+            &lrc_file_path,
+            &renamed_file_path.with_extension(LRC_EXTENSION),
+        )?;
+    }
+
+    song_sidecar::move_sidecars(file_path, &renamed_file_path, settings, library_paths)?;
+
+    return Ok(renamed_file_path);
+}
+
+// the path the file name template gives the song, relative to the folder of the library it is in
+fn get_templated_path(
+    downloaded_song: &DownloadedSong,
+    file_extension: &str,
+    settings: &Settings,
+) -> Result<PathBuf, String> {
+    let output_settings = settings.output();
+
+    return path_template::render_path_template(
+        output_settings.template(),
+        &get_template_values(downloaded_song, file_extension),
+        &NameRules::from_settings(output_settings),
+    );
+}
+
+// true if the song is at its templated path, or was given a suffix there because the path was taken
+fn has_templated_name(file_path: &Path, templated_file_path: &Path) -> bool {
+    if file_path == templated_file_path {
+        return true;
+    }
+
+    if file_path.parent() != templated_file_path.parent()
+        || file_path.extension() != templated_file_path.extension()
+    {
+        return false;
+    }
+
+    return match (file_path.file_stem(), templated_file_path.file_stem()) {
+        (Some(stem), Some(templated_stem)) => {
+            let stem = stem.to_string_lossy();
+
+            stem.starts_with(&format!("{} (", templated_stem.to_string_lossy()))
+                && stem.ends_with(')')
+        }
+        _ => false,
+    };
+}
 
-    // rename file to include the artist and name of the song
-    let renamed_file_path = match downloaded_song.file_location.parent() {
-        Some(path_buf) => PathBuf::from(path_buf),
-        None => match PathBuf::from_str("") {
-            Ok(path) => path,
-            Err(e) => return Err(format!("Could not create empty path: {}", e)),
-        },
+// look up the lyrics of the song and add them to its tags, None if lyrics are off or not found
+fn embed_lyrics(
+    file_path: &Path,
+    artist: &str,
+    title: &str,
+    duration: Option<f64>,
+    lyrics_fetcher: &LyricsFetcher,
+    settings: &Settings,
+) -> Option<Lyrics> {
+    let lyrics = lyrics_fetcher.fetch_lyrics(artist, title, duration)?;
+
+    let song_tags = audio_tag_appender::SongTags {
+        lyrics: Some(lyrics.clone()),
+        ..Default::default()
     };
 
-    // get file name
-    let mut final_file_name = format!("{} - {}", downloaded_song.artist, downloaded_song.title);
+    if let Err(e) = audio_tag_appender::write_song_tags(file_path, &song_tags, settings.tagging()) {
+        println!(
+            "Could not add lyrics to {}: {}",
+            file_path.to_string_lossy(),
+            e
+        );
+    }
 
-    // replaces characters that are not allowed in file names with spaces, for both windows and linux (as they should be the same on both)
-    const INVALID_WINDOWS_FILENAME_CHARS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+    return Some(lyrics);
+}
 
-    for invalid_char in INVALID_WINDOWS_FILENAME_CHARS {
-        final_file_name = final_file_name.replace(invalid_char, " ");
+// write the lyrics next to the song, for players that read lrc files instead of tags
+fn write_lrc_file(file_path: &Path, lyrics: &Lyrics, settings: &Settings) {
+    if !*settings.lyrics().write_lrc_files() {
+        return;
     }
 
-    let renamed_file_path = renamed_file_path.join(format!(
-        "downloaded/{}.mp3",
-        final_file_name
-    ));
+    let lrc_file_path = file_path.with_extension(LRC_EXTENSION);
 
-    match std::fs::rename(&downloaded_song.file_location, &renamed_file_path) {
-        Ok(_) => (),
+    if let Err(e) = std::fs::write(&lrc_file_path, lyrics.to_lrc()) {
+        println!(
+            "Could not write lyrics file {}: {}",
+            lrc_file_path.to_string_lossy(),
+            e
+        );
+    }
+}
+
+// write the sidecars of the song
+fn write_sidecars(
+    song_path: &Path,
+    song_sidecar: &SongSidecar,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) {
+    if let Err(e) = song_sidecar::write_sidecars(song_path, song_sidecar, settings, library_paths) {
+        println!(
+            "Could not write the sidecars of {}: {}",
+            song_sidecar.song_url, e
+        );
+    }
+}
+
+// measure the loudness before tagging, as applying gain re-encodes the file
+fn measure_song(
+    file_path: &Path,
+    song_url: &str,
+    settings: &Settings,
+) -> Option<LoudnessMeasurement> {
+    let normalization_settings = settings.normalization();
+
+    if !*normalization_settings.replay_gain() && !*normalization_settings.apply_gain() {
+        return None;
+    }
+
+    return match loudness_normalizer::normalize_song(file_path, settings) {
+        Ok(loudness) => Some(loudness),
         Err(e) => {
+            println!("Could not normalize the loudness of {}: {}", song_url, e);
+
+            None
+        }
+    };
+}
+
+// the extension of the downloaded file, as it depends on the audio format
+fn get_file_extension(file_path: &Path) -> Result<String, String> {
+    return match file_path.extension() {
+        Some(extension) => Ok(extension.to_string_lossy().to_string()),
+        None => Err(format!(
+            "Downloaded song {} has no file extension",
+            file_path.to_string_lossy()
+        )),
+    };
+}
+
+// move the song to its templated path, or next to it when the path is taken and the collision
+// policy adds a suffix. Returns None if the policy skips songs whose path is taken, the song is
+// then removed and the song at the path is left as it is
+fn place_in_library(
+    song_path: &Path,
+    templated_file_path: &Path,
+    settings: &Settings,
+) -> Result<Option<PathBuf>, String> {
+    let output_settings = settings.output();
+
+    let file_path = match path_template::resolve_collision(
+        templated_file_path,
+        *output_settings.on_collision(),
+        &NameRules::from_settings(output_settings),
+    ) {
+        Some(path) => path,
+        None => {
+            println!(
+                "{} already exists, skipping",
+                templated_file_path.to_string_lossy()
+            );
+
+            if let Err(e) = std::fs::remove_file(song_path) {
+                return Err(format!(
+                    "Could not remove skipped download {}: {}",
+                    song_path.to_string_lossy(),
+                    e
+                ));
+            }
+
+            return Ok(None);
+        }
+    };
+
+    move_into_library(song_path, &file_path)?;
+
+    return Ok(Some(file_path));
+}
+
+fn move_into_library(from_path: &Path, to_path: &Path) -> Result<(), String> {
+    if let Some(parent_directory) = to_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent_directory) {
             return Err(format!(
-                "Could not rename {} to {}: {}",
-                downloaded_song.file_location.to_string_lossy(),
-                renamed_file_path.to_string_lossy(),
+                "Could not create directory {}: {}",
+                parent_directory.to_string_lossy(),
                 e
-            ))
+            ));
         }
+    }
+
+    if let Err(e) = std::fs::rename(from_path, to_path) {
+        return Err(format!(
+            "Could not rename {} to {}: {}",
+            from_path.to_string_lossy(),
+            to_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+// values of the placeholders that can be used in the file name template
+fn get_template_values<'a>(
+    downloaded_song: &DownloadedSong,
+    file_extension: &str,
+) -> HashMap<&'a str, String> {
+    let download_information = &downloaded_song.download_information;
+
+    let get_value = |value: &Option<String>| -> String {
+        return value.to_owned().unwrap_or(UNKNOWN_VALUE.to_string());
     };
 
-    return Ok(renamed_file_path);
+    return HashMap::from([
+        ("artist", downloaded_song.artist.to_owned()),
+        ("title", downloaded_song.title.to_owned()),
+        ("genre", downloaded_song.genre.to_owned()),
+        ("playlist_name", downloaded_song.playlist_name.to_owned()),
+        (
+            "track_number",
+            get_value(
+                &downloaded_song
+                    .track_number
+                    .map(|track_number| format!("{:02}", track_number)),
+            ),
+        ),
+        ("source_id", get_value(&download_information.id)),
+        ("uploader", get_value(&download_information.uploader)),
+        ("upload_date", get_value(&download_information.upload_date)),
+        (
+            "year",
+            get_value(
+                &download_information
+                    .upload_date
+                    .as_ref()
+                    .map(|upload_date| upload_date.chars().take(4).collect()),
+            ),
+        ),
+        ("ext", file_extension.to_string()),
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::post_process_downloaded_song;
+    use crate::library_paths::LibraryPaths;
+    use crate::lyrics_fetcher::LyricsFetcher;
+    use crate::music_sources::{DownloadInformation, DownloadedSong, SongInformation};
+    use crate::settings_parser::Settings;
+    use crate::song_sidecar::SongSidecar;
+    use std::path::{Path, PathBuf};
+
+    // a download of the silent mp3 fixture, as another song with the same artist and title
+    fn get_downloaded_song(library_paths: &LibraryPaths, source_id: &str) -> DownloadedSong {
+        let file_location = library_paths
+            .temporary_directory()
+            .join(format!("{}.mp3", source_id));
+
+        std::fs::create_dir_all(library_paths.temporary_directory()).unwrap();
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tagging/silence.mp3"),
+            &file_location,
+        )
+        .unwrap();
+
+        let song_information = SongInformation {
+            url: format!("https://www.youtube.com/watch?v={}", source_id),
+            title: "Title".to_string(),
+            genre: "Genre".to_string(),
+            artist: "Artist".to_string(),
+            playlist_name: "Playlist".to_string(),
+            album: None,
+            album_artist: None,
+            track_number: None,
+            total_tracks: None,
+            release_date: None,
+            artwork_url: None,
+            isrc: None,
+        };
+
+        return DownloadedSong::from_song_information(
+            &song_information,
+            file_location,
+            DownloadInformation::from_info_json(serde_json::json!({ "id": source_id })),
+        );
+    }
+
+    #[test]
+    fn test_skipped_song_has_no_file() {
+        let library_root =
+            std::env::temp_dir().join(format!("musix_post_processor_{}", std::process::id()));
+        let library_paths = LibraryPaths::new(library_root.to_owned(), None);
+
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "output": { "template": "{artist} - {title}.{ext}", "on_collision": "skip" },
+                "normalization": { "replay_gain": false },
+                "tagging": { "embed_cover_art": false },
+                "sidecars": { "info_json": false }
+            }"#,
+        )
+        .unwrap();
+
+        let lyrics_fetcher = LyricsFetcher::from_settings(settings.lyrics());
+
+        let first_song = post_process_downloaded_song(
+            get_downloaded_song(&library_paths, "first"),
+            &SongSidecar::default(),
+            None,
+            &lyrics_fetcher,
+            &settings,
+            &library_paths,
+        )
+        .unwrap();
+
+        let second_download = get_downloaded_song(&library_paths, "second");
+        let second_download_path = second_download.file_location.to_owned();
+
+        let second_song = post_process_downloaded_song(
+            second_download,
+            &SongSidecar::default(),
+            None,
+            &lyrics_fetcher,
+            &settings,
+            &library_paths,
+        )
+        .unwrap();
+
+        let song_path = library_paths
+            .downloaded_directory()
+            .join("Artist - Title.mp3");
+
+        // the path is the first song's, the second song is dropped without taking it
+        assert_eq!(first_song.file_path, Some(song_path.to_owned()));
+        assert_eq!(second_song.file_path, None::<PathBuf>);
+        assert!(song_path.is_file());
+        assert!(!second_download_path.exists());
+
+        std::fs::remove_dir_all(library_root).unwrap();
+    }
 }
@@ -0,0 +1,147 @@
+use std::io::{IsTerminal, Write};
+
+use crate::yt_dlp_caller::DownloadProgressEvent;
+
+// ansi escape codes used to redraw the progress lines in place
+const CLEAR_LINE: &str = "\x1b[2K";
+
+/// Renders a live progress line per download worker in the terminal. When the output is not a
+/// terminal, ex. a log file, it prints plain lines as the downloads change phase instead
+pub struct ProgressReporter {
+    worker_lines: Vec<String>,
+    // number of lines drawn by the last render, which are redrawn in place
+    rendered_line_count: usize,
+    is_terminal: bool,
+    // whether the worker already printed a plain downloading line for its current download
+    workers_downloading: Vec<bool>,
+}
+
+impl ProgressReporter {
+    pub fn new(worker_count: usize) -> Self {
+        return ProgressReporter {
+            worker_lines: vec![String::new(); worker_count],
+            rendered_line_count: 0,
+            is_terminal: std::io::stdout().is_terminal(),
+            workers_downloading: vec![false; worker_count],
+        };
+    }
+
+    /// Update the progress line of the worker and redraw the progress lines. A finished download
+    /// ends the block, so the output that follows is not drawn over
+    pub fn report(&mut self, worker_id: usize, event: &DownloadProgressEvent) {
+        let line = format!("[worker {}] {}", worker_id + 1, format_event(event));
+
+        match self.worker_lines.get_mut(worker_id) {
+            Some(worker_line) => *worker_line = line.to_owned(),
+            None => return,
+        }
+
+        if !self.is_terminal {
+            self.print_plain(worker_id, event, &line);
+            return;
+        }
+
+        self.render();
+
+        if matches!(event, DownloadProgressEvent::Finished) {
+            self.end_block();
+        }
+    }
+
+    /// Leave the drawn lines in the terminal, so the next report starts a new block below
+    /// any other output. Has to be called before anything else is printed
+    pub fn end_block(&mut self) {
+        self.rendered_line_count = 0;
+        self.workers_downloading.fill(false);
+    }
+
+    // one line when the download starts, then one per post processing step and when it is done
+    fn print_plain(&mut self, worker_id: usize, event: &DownloadProgressEvent, line: &str) {
+        let is_downloading = matches!(event, DownloadProgressEvent::Downloading { .. });
+
+        if let Some(worker_downloading) = self.workers_downloading.get_mut(worker_id) {
+            if is_downloading && *worker_downloading {
+                return;
+            }
+
+            *worker_downloading = is_downloading;
+        }
+
+        println!("{}", line);
+    }
+
+    fn render(&mut self) {
+        let mut output = String::new();
+
+        // move the cursor back up to the start of the previously drawn lines
+        if self.rendered_line_count > 0 {
+            output.push_str(&format!("\x1b[{}A", self.rendered_line_count));
+        }
+
+        for line in self.worker_lines.iter() {
+            output.push_str(CLEAR_LINE);
+            output.push_str(line);
+            output.push('\n');
+        }
+
+        self.rendered_line_count = self.worker_lines.len();
+
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(output.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+fn format_event(event: &DownloadProgressEvent) -> String {
+    return match event {
+        DownloadProgressEvent::Downloading {
+            downloaded_bytes,
+            total_bytes,
+            speed,
+            eta,
+        } => {
+            let percent = match (downloaded_bytes, total_bytes) {
+                (Some(downloaded), Some(total)) if *total > 0 => {
+                    format!("{:5.1}%", *downloaded as f64 / *total as f64 * 100.0)
+                }
+                _ => "  ?  %".to_string(),
+            };
+            let total = match total_bytes {
+                Some(total) => format_bytes(*total as f64),
+                None => "?".to_string(),
+            };
+            let speed = match speed {
+                Some(speed) => format!("{}/s", format_bytes(*speed)),
+                None => "?/s".to_string(),
+            };
+            let eta = match eta {
+                Some(eta) => format!("{}:{:02}", eta / 60, eta % 60),
+                None => "?".to_string(),
+            };
+
+            format!(
+                "downloading {} of {} at {} ETA {}",
+                percent, total, speed, eta
+            )
+        }
+        DownloadProgressEvent::PostProcessing {
+            postprocessor,
+            status,
+        } => format!("post processing: {} ({})", postprocessor, status),
+        DownloadProgressEvent::Finished => "done".to_string(),
+    };
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut value = bytes;
+    let mut unit_index = 0;
+
+    while value >= 1024.0 && unit_index < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit_index += 1;
+    }
+
+    return format!("{:.2}{}", value, UNITS[unit_index]);
+}
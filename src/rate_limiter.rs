@@ -0,0 +1,159 @@
+use rand::Rng;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::download_error::{DownloadError, DownloadErrorClass};
+use crate::music_sources::DownloadedSong;
+use crate::settings_parser::RateLimitSettings;
+
+// times a throttled listing request is retried before giving up
+const MAX_THROTTLED_RETRIES: u32 = 3;
+
+/// Token bucket rate limiter for one music source, with jitter,
+/// that slows down when the source throttles us and recovers gradually after successes
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    tokens: f64,
+    last_refill: Instant,
+    // multiplier of the wait between requests, 1.0 when we are not being throttled
+    slowdown: f64,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        return RateLimiter {
+            settings: settings.clone(),
+            tokens: *settings.burst(),
+            last_refill: Instant::now(),
+            slowdown: 1.0,
+        };
+    }
+
+    /// Block until a request can be made
+    pub fn acquire(&mut self) {
+        self.refill();
+
+        if self.tokens < 1.0 {
+            let wait_seconds = (1.0 - self.tokens) / self.tokens_per_second();
+            thread::sleep(Duration::from_secs_f64(wait_seconds));
+
+            self.refill();
+        }
+
+        self.tokens = (self.tokens - 1.0).max(0.0);
+
+        // random wait so we don't give the music provider sneaky suspicions *__*
+        let jitter_seconds = *self.settings.jitter_seconds();
+
+        if jitter_seconds > 0.0 {
+            let jitter = rand::rng().random_range(0.0..jitter_seconds);
+            thread::sleep(Duration::from_secs_f64(jitter));
+        }
+    }
+
+    /// The source throttled us, back off exponentially
+    pub fn on_throttled(&mut self) {
+        self.slowdown =
+            (self.slowdown * self.settings.backoff_multiplier()).min(*self.settings.max_slowdown());
+        self.tokens = 0.0;
+    }
+
+    /// A request succeeded, move the slowdown back towards normal by averaging it
+    /// over the recovery window
+    pub fn on_success(&mut self) {
+        let recovery_window = (*self.settings.recovery_window()).max(1) as f64;
+
+        self.slowdown = (self.slowdown * (recovery_window - 1.0) + 1.0) / recovery_window;
+    }
+
+    pub fn slowdown(&self) -> f64 {
+        return self.slowdown;
+    }
+
+    /// Record the outcome of a download, rate limited downloads slow the source down
+    pub fn record_download(&mut self, download_result: &Result<DownloadedSong, DownloadError>) {
+        match download_result {
+            Ok(_) => self.on_success(),
+            Err(e) if e.class == DownloadErrorClass::RateLimited => self.on_throttled(),
+            // other failures say nothing about how fast we are going
+            Err(_) => (),
+        }
+    }
+
+    /// Make a rate limited request, retrying with a slowdown if the source throttles it
+    pub fn call(&mut self, request: ureq::Request) -> Result<ureq::Response, Box<ureq::Error>> {
+        let mut retries = 0;
+
+        loop {
+            self.acquire();
+
+            match request.clone().call() {
+                Ok(response) => {
+                    self.on_success();
+
+                    return Ok(response);
+                }
+                // too many requests or service unavailable
+                Err(ureq::Error::Status(status, response)) if status == 429 || status == 503 => {
+                    self.on_throttled();
+
+                    if retries >= MAX_THROTTLED_RETRIES {
+                        return Err(Box::new(ureq::Error::Status(status, response)));
+                    }
+
+                    println!(
+                        "Request to {} was throttled, slowing down by {}x",
+                        request.url(),
+                        self.slowdown
+                    );
+
+                    retries += 1;
+                }
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+    }
+
+    fn tokens_per_second(&self) -> f64 {
+        return self.settings.requests_per_minute() / 60.0 / self.slowdown;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed_seconds = now.duration_since(self.last_refill).as_secs_f64();
+
+        self.tokens =
+            (self.tokens + elapsed_seconds * self.tokens_per_second()).min(*self.settings.burst());
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use crate::settings_parser::RateLimitSettings;
+
+    #[test]
+    fn test_slowdown_backs_off_and_recovers() {
+        let mut rate_limiter = RateLimiter::new(&RateLimitSettings::default());
+
+        rate_limiter.on_throttled();
+        rate_limiter.on_throttled();
+        assert_eq!(rate_limiter.slowdown(), 4.0);
+
+        // capped at the max slowdown
+        for _ in 0..10 {
+            rate_limiter.on_throttled();
+        }
+        assert_eq!(rate_limiter.slowdown(), 32.0);
+
+        // recovers gradually, never dropping below normal speed
+        rate_limiter.on_success();
+        assert!(rate_limiter.slowdown() < 32.0 && rate_limiter.slowdown() > 1.0);
+
+        for _ in 0..100 {
+            rate_limiter.on_success();
+        }
+        assert!(rate_limiter.slowdown() >= 1.0 && rate_limiter.slowdown() < 1.01);
+    }
+}
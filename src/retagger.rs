@@ -0,0 +1,248 @@
+use crate::database::{Database, LibrarySong};
+use crate::environment_extractor::EnvironmentVariables;
+use crate::library_paths::LibraryPaths;
+use crate::metadata_enricher::MetadataEnricher;
+use crate::music_sources::{
+    get_music_source_from_url, DownloadedSong, MusicSources, SongInformation, StoredSongMetadata,
+};
+use crate::settings_parser::Settings;
+use crate::song_storage::SongStorage;
+use crate::title_extractor::title_rules::{self, TitleRules};
+use crate::title_extractor::EmptyTitleExtractor;
+use crate::{audio_tag_appender, post_processor};
+
+/// Write the tags of the songs in the library again from their stored metadata and overrides,
+/// of one playlist or of every playlist, and move them to their templated path if renaming.
+/// Retagged songs are stored again in the storage of the settings. The tracks of split mixes are
+/// left as they are and reported
+pub fn retag_library(
+    database_context: &mut Database,
+    environment_variables: &EnvironmentVariables,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+    song_storage: Option<&SongStorage>,
+    playlist_url: Option<&str>,
+    rename: bool,
+) -> Result<(), String> {
+    let title_rules = title_rules::load_title_rules(settings.titles().rules_path().as_deref())?;
+
+    let metadata_enricher = MetadataEnricher::from_settings(settings)?;
+
+    let library_songs = database_context.get_library_songs(playlist_url, environment_variables)?;
+
+    let mut retagged_count = 0;
+    let mut renamed_count = 0;
+
+    for library_song in library_songs.iter() {
+        let file_path = library_paths.from_stored_path(&library_song.file_path);
+
+        if !file_path.exists() {
+            println!(
+                "Skipping {}, {} no longer exists",
+                library_song.song_url,
+                file_path.to_string_lossy()
+            );
+            continue;
+        }
+
+        let stored_metadata = match &library_song.metadata {
+            Some(metadata) => match serde_json::from_str::<StoredSongMetadata>(metadata) {
+                Ok(stored_metadata) => stored_metadata,
+                Err(e) => {
+                    println!(
+                        "Skipping {}, its stored metadata can not be read: {}",
+                        library_song.song_url, e
+                    );
+                    continue;
+                }
+            },
+            None => {
+                println!(
+                    "Skipping {}, it was downloaded before its metadata was stored",
+                    library_song.song_url
+                );
+                continue;
+            }
+        };
+
+        // settings with the playlist's overrides applied
+        let playlist_settings = settings.for_playlist(&library_song.playlist_url)?;
+
+        let song_information = get_song_information(library_song, &stored_metadata, &title_rules);
+
+        let song_information = match (&metadata_enricher, playlist_settings.enrichment().enabled())
+        {
+            (Some(metadata_enricher), true) => {
+                metadata_enricher.enrich(song_information, stored_metadata.duration)
+            }
+            _ => song_information,
+        };
+
+        // metadata set by hand wins over the metadata extracted from the source
+        let song_information = match database_context
+            .get_song_override(&library_song.song_url, environment_variables)?
+        {
+            Some(song_override) => song_override.apply_to_song_information(song_information),
+            None => song_information,
+        };
+
+        let downloaded_song = DownloadedSong::from_song_information(
+            &song_information,
+            file_path,
+            stored_metadata.to_download_information(),
+        );
+
+        // replay gain is left as it is in the file
+        let song_tags = audio_tag_appender::SongTags {
+            playlist_url: Some(library_song.playlist_url.to_owned()),
+            ..audio_tag_appender::get_song_tags(&downloaded_song, playlist_settings.tagging(), None)
+        };
+
+        if let Err(e) = audio_tag_appender::write_song_tags(
+            &downloaded_song.file_location,
+            &song_tags,
+            playlist_settings.tagging(),
+        ) {
+            println!("Could not retag {}: {}", library_song.song_url, e);
+            continue;
+        }
+
+        retagged_count += 1;
+
+        let renamed_file_path = match rename {
+            true => {
+                post_processor::rename_song(&downloaded_song, &playlist_settings, library_paths)?
+            }
+            false => downloaded_song.file_location.to_owned(),
+        };
+
+        if renamed_file_path != downloaded_song.file_location {
+            database_context.update_song_file_path(
+                &library_song.file_path,
+                &library_paths.to_stored_path(&renamed_file_path),
+                environment_variables,
+            )?;
+
+            renamed_count += 1;
+        }
+
+        // the new tags change the file, and renaming it changes its key
+        if let Some(song_storage) = song_storage {
+            song_storage.try_move_song(
+                &downloaded_song.file_location,
+                &renamed_file_path,
+                library_paths,
+            );
+        }
+    }
+
+    println!(
+        "Retagged {} of {} songs, renamed {}",
+        retagged_count,
+        library_songs.len(),
+        renamed_count
+    );
+
+    // the tracks of a split mix are tagged from the chapters of the mix, which are not stored
+    let split_mix_urls =
+        database_context.get_split_mix_urls(playlist_url, environment_variables)?;
+
+    if !split_mix_urls.is_empty() {
+        println!(
+            "Skipped {} split mixes, the tags of their tracks can not be written again:",
+            split_mix_urls.len()
+        );
+
+        for split_mix_url in split_mix_urls.iter() {
+            println!("  {}", split_mix_url);
+        }
+    }
+
+    return Ok(());
+}
+
+// the stored metadata, with the title and artist extracted again for sources whose titles are extracted
+fn get_song_information(
+    library_song: &LibrarySong,
+    stored_metadata: &StoredSongMetadata,
+    title_rules: &TitleRules,
+) -> SongInformation {
+    let song_information = stored_metadata.to_song_information(&library_song.song_url);
+
+    let is_extracted = matches!(
+        get_music_source_from_url(&library_song.playlist_url),
+        Ok(MusicSources::YOUTUBE)
+    );
+
+    let source_title = match (&stored_metadata.source_title, is_extracted) {
+        (Some(source_title), true) => source_title,
+        _ => return song_information,
+    };
+
+    let channel = stored_metadata.uploader.to_owned().unwrap_or_default();
+
+    return match EmptyTitleExtractor::init(source_title.to_owned(), channel)
+        .extract_with_rules(title_rules, Some(&library_song.playlist_url))
+    {
+        Ok(title_extractor) => SongInformation {
+            title: title_extractor.full_title(),
+            artist: title_extractor.full_artist(),
+            ..song_information
+        },
+        Err(e) => {
+            println!(
+                "Could not extract the title of {} again: {}",
+                library_song.song_url, e
+            );
+
+            song_information
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_song_information;
+    use crate::database::LibrarySong;
+    use crate::music_sources::StoredSongMetadata;
+    use crate::title_extractor::title_rules::TitleRules;
+
+    #[test]
+    fn test_get_song_information() {
+        let stored_metadata = StoredSongMetadata {
+            title: "Glue [Official Video]".to_string(),
+            artist: "Bicep".to_string(),
+            genre: "Electronic".to_string(),
+            source_title: Some("Bicep - Glue (Official Video)".to_string()),
+            uploader: Some("FeelMyBicep".to_string()),
+            ..StoredSongMetadata::default()
+        };
+
+        let library_song = |playlist_url: &str| -> LibrarySong {
+            return LibrarySong {
+                song_url: "https://www.youtube.com/watch?v=abc".to_string(),
+                playlist_url: playlist_url.to_string(),
+                file_path: "downloaded/Bicep - Glue.mp3".to_string(),
+                metadata: None,
+            };
+        };
+
+        // titles of youtube songs are extracted again
+        let song_information = get_song_information(
+            &library_song("https://www.youtube.com/playlist?list=abc"),
+            &stored_metadata,
+            &TitleRules::default(),
+        );
+        assert_eq!(song_information.title, "Glue");
+        assert_eq!(song_information.artist, "Bicep");
+        assert_eq!(song_information.genre, "Electronic");
+
+        // soundcloud titles are kept as they were stored
+        let song_information = get_song_information(
+            &library_song("https://soundcloud.com/user/sets/abc"),
+            &stored_metadata,
+            &TitleRules::default(),
+        );
+        assert_eq!(song_information.title, "Glue [Official Video]");
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::BehaviorVersion;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::primitives::ByteStream;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::settings_parser::S3Settings;
+
+// region used when none is configured, s3 compatible servers like MinIO accept any
+const DEFAULT_REGION: &str = "us-east-1";
+
+// the object changed, or exists, since the condition was taken
+const PRECONDITION_FAILED_STATUS: u16 = 412;
+// another conditional write to the object is in progress
+const CONDITIONAL_CONFLICT_STATUS: u16 = 409;
+const NOT_FOUND_STATUS: u16 = 404;
+
+// characters kept as they are in a copy source, the "/" of folders included
+const COPY_SOURCE_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Bucket and key of an "s3://bucket/key" uri
+#[derive(Clone, Debug, PartialEq)]
+pub struct S3Uri {
+    pub bucket: String,
+    pub key: String,
+}
+
+/// An object read from a bucket
+pub struct S3Object {
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+}
+
+/// The etag and user metadata of an object, without its body
+pub struct S3ObjectHead {
+    pub etag: Option<String>,
+    // the "x-amz-meta-" headers, without the prefix
+    pub metadata: HashMap<String, String>,
+}
+
+/// When a write is allowed to replace what is in the bucket
+pub enum WriteCondition {
+    Always,
+    // only if there is no object with the key yet
+    IfAbsent,
+    // only if the object still has this etag
+    IfMatch(String),
+}
+
+/// An error of an s3 request, with the http status when the server answered
+#[derive(Debug)]
+pub struct S3Error {
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl std::fmt::Display for S3Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.message);
+    }
+}
+
+impl S3Error {
+    /// Whether a conditional write lost against another write
+    pub fn is_condition_failed(&self) -> bool {
+        return matches!(
+            self.status,
+            Some(PRECONDITION_FAILED_STATUS) | Some(CONDITIONAL_CONFLICT_STATUS)
+        );
+    }
+}
+
+impl S3Uri {
+    pub fn parse(uri: &str) -> Result<S3Uri, String> {
+        let path = match uri.strip_prefix("s3://") {
+            Some(path) => path,
+            None => return Err(format!("{} is not an s3://bucket/key uri", uri)),
+        };
+
+        return match path.split_once('/') {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(S3Uri {
+                bucket: bucket.to_string(),
+                key: key.to_string(),
+            }),
+            _ => Err(format!("{} is not an s3://bucket/key uri", uri)),
+        };
+    }
+}
+
+/// Blocking client of an s3 compatible object storage, credentials come from the usual AWS
+/// environment variables and profiles
+pub struct S3Client {
+    client: aws_sdk_s3::Client,
+    // the sdk is async, requests are run to completion one at a time
+    runtime: tokio::runtime::Runtime,
+}
+
+impl S3Client {
+    pub fn new(s3_settings: &S3Settings) -> Result<S3Client, String> {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => return Err(format!("Could not start the s3 client runtime: {}", e)),
+        };
+
+        let region_provider = RegionProviderChain::first_try(
+            s3_settings
+                .region()
+                .to_owned()
+                .map(aws_sdk_s3::config::Region::new),
+        )
+        .or_default_provider()
+        .or_else(DEFAULT_REGION);
+
+        let sdk_config = runtime.block_on(
+            aws_config::defaults(BehaviorVersion::latest())
+                .region(region_provider)
+                .load(),
+        );
+
+        let mut config_builder = aws_sdk_s3::config::Builder::from(&sdk_config)
+            .force_path_style(*s3_settings.force_path_style());
+
+        if let Some(endpoint_url) = s3_settings.endpoint_url() {
+            config_builder = config_builder.endpoint_url(endpoint_url);
+        }
+
+        return Ok(S3Client {
+            client: aws_sdk_s3::Client::from_conf(config_builder.build()),
+            runtime,
+        });
+    }
+
+    /// The object, None if there is no object with the key
+    pub fn get_object(&self, s3_uri: &S3Uri) -> Result<Option<S3Object>, S3Error> {
+        let request = self
+            .client
+            .get_object()
+            .bucket(&s3_uri.bucket)
+            .key(&s3_uri.key);
+
+        return self.runtime.block_on(async {
+            let output = match request.send().await {
+                Ok(output) => output,
+                Err(e) => {
+                    let s3_error = get_s3_error(e, "get", s3_uri);
+
+                    return match s3_error.status {
+                        Some(NOT_FOUND_STATUS) => Ok(None),
+                        _ => Err(s3_error),
+                    };
+                }
+            };
+
+            let etag = output.e_tag().map(|etag| etag.to_string());
+
+            let body = match output.body.collect().await {
+                Ok(body) => body.into_bytes().to_vec(),
+                Err(e) => {
+                    return Err(S3Error {
+                        status: None,
+                        message: format!(
+                            "Could not read s3://{}/{}: {}",
+                            s3_uri.bucket, s3_uri.key, e
+                        ),
+                    })
+                }
+            };
+
+            return Ok(Some(S3Object { body, etag }));
+        });
+    }
+
+    /// The etag and user metadata of the object, None if there is no object with the key
+    pub fn head_object(&self, s3_uri: &S3Uri) -> Result<Option<S3ObjectHead>, S3Error> {
+        let request = self
+            .client
+            .head_object()
+            .bucket(&s3_uri.bucket)
+            .key(&s3_uri.key);
+
+        return self.runtime.block_on(async {
+            return match request.send().await {
+                Ok(output) => Ok(Some(S3ObjectHead {
+                    etag: output.e_tag().map(|etag| etag.to_string()),
+                    metadata: output.metadata().cloned().unwrap_or_default(),
+                })),
+                Err(e) => {
+                    let s3_error = get_s3_error(e, "head", s3_uri);
+
+                    match s3_error.status {
+                        Some(NOT_FOUND_STATUS) => Ok(None),
+                        _ => Err(s3_error),
+                    }
+                }
+            };
+        });
+    }
+
+    /// Write the object if the condition holds, returns the etag of the new object
+    pub fn put_object(
+        &self,
+        s3_uri: &S3Uri,
+        body: Vec<u8>,
+        write_condition: &WriteCondition,
+    ) -> Result<Option<String>, S3Error> {
+        let mut request = self
+            .client
+            .put_object()
+            .bucket(&s3_uri.bucket)
+            .key(&s3_uri.key)
+            .body(ByteStream::from(body));
+
+        request = match write_condition {
+            WriteCondition::Always => request,
+            WriteCondition::IfAbsent => request.if_none_match("*"),
+            WriteCondition::IfMatch(etag) => request.if_match(etag),
+        };
+
+        return self.runtime.block_on(async {
+            return match request.send().await {
+                Ok(output) => Ok(output.e_tag().map(|etag| etag.to_string())),
+                Err(e) => Err(get_s3_error(e, "put", s3_uri)),
+            };
+        });
+    }
+
+    /// Upload the file as the object, streamed from disk, with the user metadata. Returns the etag
+    /// of the new object
+    pub fn put_file(
+        &self,
+        s3_uri: &S3Uri,
+        file_path: &Path,
+        metadata: &HashMap<String, String>,
+    ) -> Result<Option<String>, S3Error> {
+        return self.runtime.block_on(async {
+            let body = match ByteStream::from_path(file_path).await {
+                Ok(body) => body,
+                Err(e) => {
+                    return Err(S3Error {
+                        status: None,
+                        message: format!(
+                            "Could not read {} to upload it: {}",
+                            file_path.to_string_lossy(),
+                            e
+                        ),
+                    })
+                }
+            };
+
+            let request = self
+                .client
+                .put_object()
+                .bucket(&s3_uri.bucket)
+                .key(&s3_uri.key)
+                .set_metadata(Some(metadata.to_owned()))
+                .body(body);
+
+            return match request.send().await {
+                Ok(output) => Ok(output.e_tag().map(|etag| etag.to_string())),
+                Err(e) => Err(get_s3_error(e, "put", s3_uri)),
+            };
+        });
+    }
+
+    /// Copy the object to another key of the same bucket, with its user metadata, without
+    /// downloading it
+    pub fn copy_object(&self, from_s3_uri: &S3Uri, to_s3_uri: &S3Uri) -> Result<(), S3Error> {
+        // the copy source is url encoded, keys of songs have spaces and non ascii titles
+        let copy_source = format!(
+            "{}/{}",
+            from_s3_uri.bucket,
+            utf8_percent_encode(&from_s3_uri.key, COPY_SOURCE_ENCODE_SET)
+        );
+
+        let request = self
+            .client
+            .copy_object()
+            .bucket(&to_s3_uri.bucket)
+            .key(&to_s3_uri.key)
+            .copy_source(copy_source);
+
+        return self.runtime.block_on(async {
+            return match request.send().await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(get_s3_error(e, "copy to", to_s3_uri)),
+            };
+        });
+    }
+
+    /// Delete the object if it still has the etag, or whatever it is if no etag is given
+    pub fn delete_object(&self, s3_uri: &S3Uri, etag: Option<&str>) -> Result<(), S3Error> {
+        let mut request = self
+            .client
+            .delete_object()
+            .bucket(&s3_uri.bucket)
+            .key(&s3_uri.key);
+
+        if let Some(etag) = etag {
+            request = request.if_match(etag);
+        }
+
+        return self.runtime.block_on(async {
+            return match request.send().await {
+                Ok(_) => Ok(()),
+                Err(e) => Err(get_s3_error(e, "delete", s3_uri)),
+            };
+        });
+    }
+}
+
+fn get_s3_error<E>(error: SdkError<E, HttpResponse>, operation: &str, s3_uri: &S3Uri) -> S3Error
+where
+    E: std::error::Error + 'static,
+{
+    let status = error
+        .raw_response()
+        .map(|response| response.status().as_u16());
+
+    return S3Error {
+        status,
+        message: format!(
+            "Could not {} s3://{}/{}: {}",
+            operation,
+            s3_uri.bucket,
+            s3_uri.key,
+            aws_sdk_s3::error::DisplayErrorContext(&error)
+        ),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::S3Uri;
+
+    #[test]
+    fn test_s3_uri() {
+        assert_eq!(
+            S3Uri::parse("s3://music/library/sqlite.db").unwrap(),
+            S3Uri {
+                bucket: "music".to_string(),
+                key: "library/sqlite.db".to_string(),
+            }
+        );
+        assert!(S3Uri::parse("s3://music").is_err());
+        assert!(S3Uri::parse("s3:///sqlite.db").is_err());
+        assert!(S3Uri::parse("https://music/sqlite.db").is_err());
+    }
+}
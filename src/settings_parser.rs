@@ -1,23 +1,603 @@
 use getset::Getters;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
+use std::path::{Path, PathBuf};
 
-#[derive(Serialize, Deserialize, Getters)]
+// settings file used when neither --settings nor MUSIX_SETTINGS_PATH is given, relative to the working directory
+const DEFAULT_SETTINGS_PATH: &str = "settings/settings.json";
+
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
 pub struct Settings {
     #[getset(get = "pub")]
     soundcloud_username: String,
     #[getset(get = "pub")]
     soundcloud_password: String,
+    #[getset(get = "pub")]
+    audio: AudioSettings,
+    #[getset(get = "pub")]
+    rate_limits: RateLimitsSettings,
+    #[getset(get = "pub")]
+    yt_dlp: YtDlpSettings,
+    #[getset(get = "pub")]
+    ffmpeg: FfmpegSettings,
+    #[getset(get = "pub")]
+    library: LibrarySettings,
+    #[getset(get = "pub")]
+    s3: S3Settings,
+    #[getset(get = "pub")]
+    output: OutputSettings,
+    #[getset(get = "pub")]
+    archive: ArchiveSettings,
+    #[getset(get = "pub")]
+    tagging: TaggingSettings,
+    #[getset(get = "pub")]
+    normalization: NormalizationSettings,
+    #[getset(get = "pub")]
+    trimming: TrimmingSettings,
+    #[getset(get = "pub")]
+    split: SplitSettings,
+    #[getset(get = "pub")]
+    titles: TitleSettings,
+    #[getset(get = "pub")]
+    enrichment: EnrichmentSettings,
+    #[getset(get = "pub")]
+    lyrics: LyricsSettings,
+    #[getset(get = "pub")]
+    sidecars: SidecarSettings,
+    #[getset(get = "pub")]
+    storage: StorageSettings,
+    // per playlist overrides, keyed by playlist url
+    // each value has the same shape as the settings file and is merged over the global settings
+    #[getset(get = "pub")]
+    playlists: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct AudioSettings {
+    #[getset(get = "pub")]
+    format: AudioFormat,
+    // passed to yt-dlp as --audio-quality, ex. "320K" or a vbr quality from "0" (best) to "10" (worst)
+    #[getset(get = "pub")]
+    bitrate: Option<String>,
+    // keep the best audio stream in its original container instead of re-encoding it
+    #[getset(get = "pub")]
+    keep_original_container: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    Mp3,
+    M4a,
+    Aac,
+    Opus,
+    Vorbis,
+    Flac,
+    Wav,
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct YtDlpSettings {
+    // explicit path to the yt-dlp executable, otherwise it is looked up on the PATH
+    #[getset(get = "pub")]
+    path: Option<PathBuf>,
+    // opt in to downloading and updating yt-dlp ourselves when it is not configured or on the PATH
+    #[getset(get = "pub")]
+    managed: bool,
+    // release tag to pin yt-dlp to, ex. "2024.12.13", the latest release is used if not set
+    #[getset(get = "pub")]
+    version: Option<String>,
+    // update yt-dlp and retry when downloads fail because its extractors are out of date
+    #[getset(get = "pub")]
+    auto_update: bool,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct FfmpegSettings {
+    // explicit paths to the executables, otherwise they are looked up on the PATH
+    #[getset(get = "pub")]
+    ffmpeg_path: Option<PathBuf>,
+    #[getset(get = "pub")]
+    ffprobe_path: Option<PathBuf>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct LibrarySettings {
+    // directory the downloaded, archive and data folders live in, the working directory if not set
+    #[getset(get = "pub")]
+    root: Option<PathBuf>,
+    // path of the sqlite database, data/database/sqlite.db in the library root if not set
+    #[getset(get = "pub")]
+    database_path: Option<PathBuf>,
+    // "s3://bucket/key" the database is downloaded from before a command and uploaded to after it
+    #[getset(get = "pub")]
+    database_s3_uri: Option<String>,
+    // a lock on the database older than this, in minutes, is from a run that is gone
+    #[getset(get = "pub")]
+    database_lock_ttl_minutes: u64,
+}
+
+impl Default for LibrarySettings {
+    fn default() -> Self {
+        return LibrarySettings {
+            root: None,
+            database_path: None,
+            database_s3_uri: None,
+            database_lock_ttl_minutes: 360,
+        };
+    }
+}
+
+// s3 compatible object storage, credentials come from the usual AWS environment variables and profiles
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct S3Settings {
+    // ex. "http://localhost:9000" for MinIO, AWS if not set
+    #[getset(get = "pub")]
+    endpoint_url: Option<String>,
+    // the AWS region, from the environment or us-east-1 if not set
+    #[getset(get = "pub")]
+    region: Option<String>,
+    // bucket in the path instead of the host name, most s3 compatible servers need it
+    #[getset(get = "pub")]
+    force_path_style: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct TaggingSettings {
+    // download the thumbnail or artwork and embed it as the cover
+    #[getset(get = "pub")]
+    embed_cover_art: bool,
+    // width and height in pixels of the square cover art
+    #[getset(get = "pub")]
+    cover_art_size: u32,
+    // version of the id3 tags written to mp3 and wav files, some older players only read 2.3
+    #[getset(get = "pub")]
+    id3_version: Id3Version,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Id3Version {
+    #[serde(rename = "2.3")]
+    V23,
+    #[serde(rename = "2.4")]
+    V24,
+}
+
+impl Default for TaggingSettings {
+    fn default() -> Self {
+        return TaggingSettings {
+            embed_cover_art: true,
+            cover_art_size: 600,
+            id3_version: Id3Version::V24,
+        };
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct NormalizationSettings {
+    // measure the loudness of every song and write replay gain tags
+    #[getset(get = "pub")]
+    replay_gain: bool,
+    // re-encode new songs so they play at the target loudness, even in players without replay gain
+    #[getset(get = "pub")]
+    apply_gain: bool,
+    // integrated loudness the applied gain aims for, in LUFS
+    #[getset(get = "pub")]
+    target_lufs: f64,
+    // the applied gain never pushes the true peak above this, in dBTP
+    #[getset(get = "pub")]
+    max_true_peak: f64,
+}
+
+impl Default for NormalizationSettings {
+    fn default() -> Self {
+        return NormalizationSettings {
+            replay_gain: true,
+            apply_gain: false,
+            target_lufs: -18.0,
+            max_true_peak: -1.0,
+        };
+    }
+}
+
+// cutting of silence, intros and outros from the start and end of new songs
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct TrimmingSettings {
+    // cut leading and trailing silence
+    #[getset(get = "pub")]
+    trim_silence: bool,
+    // audio quieter than this is silence, in dB
+    #[getset(get = "pub")]
+    silence_threshold_db: f64,
+    // shorter silences are kept, in seconds
+    #[getset(get = "pub")]
+    min_silence_seconds: f64,
+    // chapters at the start or end whose title contains one of these are cut, ignoring case
+    // ex. "intro", "outro", "end screen"
+    #[getset(get = "pub")]
+    cut_chapters: Vec<String>,
+    // SponsorBlock segments of these categories at the start or end are cut
+    // ex. "intro", "outro", "music_offtopic", "selfpromo"
+    #[getset(get = "pub")]
+    sponsorblock_categories: Vec<String>,
+}
+
+impl Default for TrimmingSettings {
+    fn default() -> Self {
+        return TrimmingSettings {
+            trim_silence: false,
+            silence_threshold_db: -50.0,
+            min_silence_seconds: 1.0,
+            cut_chapters: Vec::new(),
+            sponsorblock_categories: Vec::new(),
+        };
+    }
+}
+
+impl TrimmingSettings {
+    /// Whether any kind of trimming is enabled
+    pub fn is_enabled(&self) -> bool {
+        return self.trim_silence
+            || !self.cut_chapters.is_empty()
+            || !self.sponsorblock_categories.is_empty();
+    }
+}
+
+// splitting of mixes into their tracks, usually turned on for the playlists of mixes
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct SplitSettings {
+    // split songs with chapters or a tracklist in their description into tracks
+    #[getset(get = "pub")]
+    enabled: bool,
+    // songs with fewer tracks are kept whole
+    #[getset(get = "pub")]
+    min_tracks: usize,
+}
+
+impl Default for SplitSettings {
+    fn default() -> Self {
+        return SplitSettings {
+            enabled: false,
+            min_tracks: 3,
+        };
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct TitleSettings {
+    // json file of the rules applied to titles before the default extraction, no rules if not set
+    #[getset(get = "pub")]
+    rules_path: Option<PathBuf>,
+}
+
+// looking songs up in a metadata index for their canonical artist, title, album, date and isrc
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct EnrichmentSettings {
+    #[getset(get = "pub")]
+    enabled: bool,
+    // sqlite database or csv file of recordings, ex. an import of a MusicBrainz dump
+    #[getset(get = "pub")]
+    index_path: Option<PathBuf>,
+    // MusicBrainz web service, ex. "https://musicbrainz.org" or a local mirror
+    #[getset(get = "pub")]
+    musicbrainz_url: Option<String>,
+    // matches scoring below this, from 0 to 1, change nothing
+    #[getset(get = "pub")]
+    min_confidence: f64,
+    // recordings whose length is further off than this, in seconds, are not the song
+    #[getset(get = "pub")]
+    duration_tolerance_seconds: f64,
+}
+
+impl Default for EnrichmentSettings {
+    fn default() -> Self {
+        return EnrichmentSettings {
+            enabled: false,
+            index_path: None,
+            musicbrainz_url: None,
+            min_confidence: 0.85,
+            duration_tolerance_seconds: 10.0,
+        };
+    }
+}
+
+// lyrics embedded in the tags of new songs, the providers are tried in the order below
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct LyricsSettings {
+    #[getset(get = "pub")]
+    enabled: bool,
+    // folder of "{artist} - {title}.lrc" files
+    #[getset(get = "pub")]
+    directory: Option<PathBuf>,
+    // web service with the LRCLIB api, ex. "https://lrclib.net"
+    #[getset(get = "pub")]
+    provider_url: Option<String>,
+    // also write the lyrics to a .lrc file next to the song
+    #[getset(get = "pub")]
+    write_lrc_files: bool,
+}
+
+// files written next to every new song with the metadata of its source
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct SidecarSettings {
+    // "{name}.info.json" with the full info json of the source, the database can be rebuilt from these
+    #[getset(get = "pub")]
+    info_json: bool,
+    // "{name}.nfo" for media servers
+    #[getset(get = "pub")]
+    nfo: bool,
+    // folder the sidecars are written to instead of next to the songs, relative to the library root,
+    // at the path the song has in the library
+    #[getset(get = "pub")]
+    directory: Option<PathBuf>,
+}
+
+impl Default for SidecarSettings {
+    fn default() -> Self {
+        return SidecarSettings {
+            info_json: true,
+            nfo: false,
+            directory: None,
+        };
+    }
+}
+
+// where finished songs are uploaded to, so other machines and phones can pull them
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct StorageSettings {
+    // "s3://bucket/prefix", or a local folder, songs are not uploaded if not set
+    #[getset(get = "pub")]
+    uri: Option<String>,
+    // key of a song under the uri, from its path in the downloaded folder or archive batch and the
+    // name of its batch. "{batch}", "{path}", "{directory}", "{file_name}", "{stem}" and "{ext}"
+    #[getset(get = "pub")]
+    key_template: String,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        return StorageSettings {
+            uri: None,
+            key_template: "{batch}/{path}".to_string(),
+        };
+    }
+}
+
+// retention of the archive batches, batches are kept forever if neither is set
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    // number of most recent batches to keep
+    #[getset(get = "pub")]
+    keep_batches: Option<usize>,
+    // batches older than this are pruned
+    #[getset(get = "pub")]
+    max_age_days: Option<u64>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct OutputSettings {
+    // path of a downloaded song relative to the downloaded directory, "/" separates folders
+    // ex. "{genre}/{artist}/{playlist_name}/{title} [{source_id}].{ext}"
+    #[getset(get = "pub")]
+    template: String,
+    // what to do when a song is written to a path that already exists
+    #[getset(get = "pub")]
+    on_collision: CollisionPolicy,
+    // file system the names have to be valid on, the one we are running on if not set
+    #[getset(get = "pub")]
+    filesystem: Option<TargetFilesystem>,
+    // longest folder or file name allowed, the limit of the file system if not set
+    #[getset(get = "pub")]
+    max_name_length: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionPolicy {
+    // add " (1)", " (2)", ... to the file name
+    Suffix,
+    // replace the existing file, the song it was stored for no longer has one
+    Overwrite,
+    // keep the existing file and drop the new download
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetFilesystem {
+    Unix,
+    Windows,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        return OutputSettings {
+            template: "{artist} - {title}.{ext}".to_string(),
+            on_collision: CollisionPolicy::Suffix,
+            filesystem: None,
+            max_name_length: None,
+        };
+    }
+}
+
+impl Default for YtDlpSettings {
+    fn default() -> Self {
+        return YtDlpSettings {
+            path: None,
+            managed: false,
+            version: None,
+            auto_update: true,
+        };
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct RateLimitsSettings {
+    #[getset(get = "pub")]
+    youtube: RateLimitSettings,
+    #[getset(get = "pub")]
+    soundcloud: RateLimitSettings,
+    #[getset(get = "pub")]
+    musicbrainz: RateLimitSettings,
+}
+
+// limits for the requests and downloads to one music source
+#[derive(Clone, Serialize, Deserialize, Getters)]
+#[serde(default)]
+pub struct RateLimitSettings {
+    #[getset(get = "pub")]
+    requests_per_minute: f64,
+    // number of requests that can be made back to back before being limited
+    #[getset(get = "pub")]
+    burst: f64,
+    // up to this many seconds of random wait are added to every request
+    #[getset(get = "pub")]
+    jitter_seconds: f64,
+    // the wait between requests is multiplied by this every time we are throttled
+    #[getset(get = "pub")]
+    backoff_multiplier: f64,
+    // the most the wait between requests can be multiplied by
+    #[getset(get = "pub")]
+    max_slowdown: f64,
+    // number of successful requests the slowdown is averaged over when recovering
+    #[getset(get = "pub")]
+    recovery_window: u32,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        return RateLimitSettings {
+            requests_per_minute: 15.0,
+            burst: 3.0,
+            jitter_seconds: 3.0,
+            backoff_multiplier: 2.0,
+            max_slowdown: 32.0,
+            recovery_window: 5,
+        };
+    }
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        return AudioSettings {
+            format: AudioFormat::Mp3,
+            bitrate: None,
+            keep_original_container: false,
+        };
+    }
+}
+
+impl AudioFormat {
+    // name of the format as yt-dlp expects it in --audio-format
+    pub fn as_yt_dlp_format(&self) -> &'static str {
+        return match self {
+            AudioFormat::Mp3 => "mp3",
+            AudioFormat::M4a => "m4a",
+            AudioFormat::Aac => "aac",
+            AudioFormat::Opus => "opus",
+            AudioFormat::Vorbis => "vorbis",
+            AudioFormat::Flac => "flac",
+            AudioFormat::Wav => "wav",
+        };
+    }
+}
+
+impl Settings {
+    /// Get the settings for a playlist, which are the global settings with
+    /// the playlist's overrides (if any) merged over them
+    pub fn for_playlist(&self, playlist_url: &str) -> Result<Settings, String> {
+        let playlist_overrides = match self.playlists.get(playlist_url) {
+            Some(overrides) => overrides,
+            None => return Ok(self.clone()),
+        };
+
+        let mut merged_settings = match serde_json::to_value(self) {
+            Ok(value) => value,
+            Err(e) => return Err(format!("Could not serialize global settings: {}", e)),
+        };
+
+        merge_json_values(&mut merged_settings, playlist_overrides);
+
+        return match serde_json::from_value(merged_settings) {
+            Ok(settings) => Ok(settings),
+            Err(e) => Err(format!(
+                "Error parsing settings overrides for playlist {}: {}",
+                playlist_url, e
+            )),
+        };
+    }
+}
+
+// recursively merge the override object into the base object, override values win
+fn merge_json_values(base: &mut serde_json::Value, overrides: &serde_json::Value) {
+    let (base_object, override_object) = match (base.as_object_mut(), overrides.as_object()) {
+        (Some(base_object), Some(override_object)) => (base_object, override_object),
+        _ => {
+            *base = overrides.clone();
+            return;
+        }
+    };
+
+    for (key, override_value) in override_object {
+        match base_object.get_mut(key) {
+            Some(base_value) => merge_json_values(base_value, override_value),
+            None => {
+                base_object.insert(key.to_owned(), override_value.clone());
+            }
+        }
+    }
+}
+
+/// Load the settings from the requested file, which has to exist. When no file is requested the
+/// default location is used, and the default settings if there is no file there
+pub fn load_settings(requested_path: Option<&Path>) -> Result<Settings, String> {
+    if let Some(settings_path) = requested_path {
+        return parse_settings(settings_path);
+    }
+
+    let default_path = Path::new(DEFAULT_SETTINGS_PATH);
+
+    if !default_path.exists() {
+        println!(
+            "No settings file at {}, using the default settings",
+            default_path.to_string_lossy()
+        );
+
+        return Ok(Settings::default());
+    }
+
+    return parse_settings(default_path);
 }
 
-pub fn parse_settings() -> Result<Settings, String> {
+/// Parse the settings file
+pub fn parse_settings(settings_path: &Path) -> Result<Settings, String> {
     //open settings file
-    let mut file = match File::open("../settings/settings.json") {
+    let mut file = match File::open(settings_path) {
         Ok(some) => some,
         Err(e) => {
             return Err(format!(
-                "Error opening file \"../settings/settings.json\": {}",
+                "Error opening file \"{}\": {}",
+                settings_path.to_string_lossy(),
                 e
             ))
         }
@@ -0,0 +1,72 @@
+use crate::music_sources::SongInformation;
+
+/// Metadata set by hand for a song, used instead of the metadata extracted from its source
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SongOverride {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub album: Option<String>,
+}
+
+impl SongOverride {
+    /// True if the override does not set any field
+    pub fn is_empty(&self) -> bool {
+        return self.title.is_none()
+            && self.artist.is_none()
+            && self.genre.is_none()
+            && self.album.is_none();
+    }
+
+    /// The override with the fields the other override sets replaced
+    pub fn merge(self, other: SongOverride) -> SongOverride {
+        return SongOverride {
+            title: other.title.or(self.title),
+            artist: other.artist.or(self.artist),
+            genre: other.genre.or(self.genre),
+            album: other.album.or(self.album),
+        };
+    }
+
+    /// Replace the extracted metadata of a song that is downloaded or retagged
+    pub fn apply_to_song_information(&self, song_information: SongInformation) -> SongInformation {
+        return SongInformation {
+            title: self.title.to_owned().unwrap_or(song_information.title),
+            artist: self.artist.to_owned().unwrap_or(song_information.artist),
+            genre: self.genre.to_owned().unwrap_or(song_information.genre),
+            album: self.album.to_owned().or(song_information.album),
+            ..song_information
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SongOverride;
+
+    #[test]
+    fn test_song_override_merge() {
+        let song_override = SongOverride {
+            title: Some("Glue".to_string()),
+            artist: Some("Bicep".to_string()),
+            ..SongOverride::default()
+        };
+
+        let merged_override = song_override.merge(SongOverride {
+            artist: Some("Bicep & Hammer".to_string()),
+            album: Some("Isles".to_string()),
+            ..SongOverride::default()
+        });
+
+        assert_eq!(
+            merged_override,
+            SongOverride {
+                title: Some("Glue".to_string()),
+                artist: Some("Bicep & Hammer".to_string()),
+                genre: None,
+                album: Some("Isles".to_string()),
+            }
+        );
+        assert!(SongOverride::default().is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::library_paths::LibraryPaths;
+use crate::music_sources::{DownloadInformation, StoredSongMetadata};
+use crate::settings_parser::Settings;
+
+// extensions of the sidecar files, after the name of the song
+const INFO_JSON_EXTENSION: &str = "info.json";
+const NFO_EXTENSION: &str = "nfo";
+
+/// Everything known about a downloaded song from its source, written next to the song so the
+/// database can be rebuilt from the library
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SongSidecar {
+    pub song_url: String,
+    pub playlist_url: String,
+    // name of the song file, or of the folder of the tracks of a split mix
+    pub file_name: String,
+    // the metadata extracted from the source, as it is kept in the database
+    pub metadata: StoredSongMetadata,
+    // the complete info json of the source, with the description, uploader and upload date
+    pub source: serde_json::Value,
+}
+
+impl SongSidecar {
+    pub fn new(
+        song_url: &str,
+        playlist_url: &str,
+        metadata: &StoredSongMetadata,
+        download_information: &DownloadInformation,
+    ) -> SongSidecar {
+        return SongSidecar {
+            song_url: song_url.to_string(),
+            playlist_url: playlist_url.to_string(),
+            // known once the song has its place in the library
+            file_name: String::new(),
+            metadata: metadata.to_owned(),
+            source: download_information.info_json.to_owned(),
+        };
+    }
+
+    /// The sidecar as an nfo file, the xml media servers read next to media files
+    pub fn to_nfo(&self) -> String {
+        let get_source_text = |key: &str| -> Option<String> {
+            return self
+                .source
+                .get(key)
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string());
+        };
+
+        let metadata = &self.metadata;
+
+        let year = metadata
+            .release_date
+            .as_ref()
+            .and_then(|release_date| release_date.get(0..4))
+            .map(|year| year.to_string());
+
+        let elements = [
+            ("title", Some(metadata.title.to_owned())),
+            ("artist", Some(metadata.artist.to_owned())),
+            (
+                "album",
+                Some(
+                    metadata
+                        .album
+                        .to_owned()
+                        .unwrap_or(metadata.playlist_name.to_owned()),
+                ),
+            ),
+            ("albumartist", metadata.album_artist.to_owned()),
+            ("genre", Some(metadata.genre.to_owned())),
+            (
+                "track",
+                metadata.track_number.map(|track| track.to_string()),
+            ),
+            ("year", year),
+            ("releasedate", metadata.release_date.to_owned()),
+            ("isrc", metadata.isrc.to_owned()),
+            (
+                "runtime",
+                metadata
+                    .duration
+                    .map(|duration| format!("{}", duration.round() as u64)),
+            ),
+            ("plot", get_source_text("description")),
+            ("uploader", metadata.uploader.to_owned()),
+            ("uploaddate", metadata.upload_date.to_owned()),
+            ("sourcetitle", metadata.source_title.to_owned()),
+            ("source", Some(self.song_url.to_owned())),
+            ("playlist", Some(metadata.playlist_name.to_owned())),
+            ("playlisturl", Some(self.playlist_url.to_owned())),
+        ];
+
+        let mut nfo =
+            String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<song>\n");
+
+        for (element, value) in elements {
+            if let Some(value) = value {
+                nfo.push_str(&format!(
+                    "  <{}>{}</{}>\n",
+                    element,
+                    escape_xml(&value),
+                    element
+                ));
+            }
+        }
+
+        nfo.push_str("</song>\n");
+
+        return nfo;
+    }
+}
+
+/// Write the sidecars of a song the settings ask for, next to it or in the sidecar folder
+pub fn write_sidecars(
+    song_path: &Path,
+    song_sidecar: &SongSidecar,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    let sidecar_settings = settings.sidecars();
+
+    let song_sidecar = SongSidecar {
+        file_name: get_file_name(song_path)?,
+        ..song_sidecar.clone()
+    };
+
+    if *sidecar_settings.info_json() {
+        let info_json = match serde_json::to_string_pretty(&song_sidecar) {
+            Ok(info_json) => info_json,
+            Err(e) => return Err(format!("Could not serialize the song sidecar: {}", e)),
+        };
+
+        write_sidecar_file(
+            &get_sidecar_path(song_path, INFO_JSON_EXTENSION, settings, library_paths)?,
+            &info_json,
+        )?;
+    }
+
+    if *sidecar_settings.nfo() {
+        write_sidecar_file(
+            &get_sidecar_path(song_path, NFO_EXTENSION, settings, library_paths)?,
+            &song_sidecar.to_nfo(),
+        )?;
+    }
+
+    return Ok(());
+}
+
+/// Move the sidecars of a song to follow it to its new path, the info json gets the new file name
+pub fn move_sidecars(
+    from_song_path: &Path,
+    to_song_path: &Path,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<(), String> {
+    for extension in [INFO_JSON_EXTENSION, NFO_EXTENSION] {
+        let from_sidecar_path =
+            get_sidecar_path(from_song_path, extension, settings, library_paths)?;
+
+        if !from_sidecar_path.is_file() {
+            continue;
+        }
+
+        let to_sidecar_path = get_sidecar_path(to_song_path, extension, settings, library_paths)?;
+
+        if let Err(e) = std::fs::rename(&from_sidecar_path, &to_sidecar_path) {
+            return Err(format!(
+                "Could not rename {} to {}: {}",
+                from_sidecar_path.to_string_lossy(),
+                to_sidecar_path.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    let info_json_path =
+        get_sidecar_path(to_song_path, INFO_JSON_EXTENSION, settings, library_paths)?;
+
+    if info_json_path.is_file() {
+        let song_sidecar = read_sidecar(&info_json_path)?;
+
+        let song_sidecar = SongSidecar {
+            file_name: get_file_name(to_song_path)?,
+            ..song_sidecar
+        };
+
+        let info_json = match serde_json::to_string_pretty(&song_sidecar) {
+            Ok(info_json) => info_json,
+            Err(e) => return Err(format!("Could not serialize the song sidecar: {}", e)),
+        };
+
+        write_sidecar_file(&info_json_path, &info_json)?;
+    }
+
+    return Ok(());
+}
+
+/// Read an info json sidecar
+pub fn read_sidecar(sidecar_path: &Path) -> Result<SongSidecar, String> {
+    let sidecar_text = match std::fs::read_to_string(sidecar_path) {
+        Ok(text) => text,
+        Err(e) => {
+            return Err(format!(
+                "Could not read sidecar {}: {}",
+                sidecar_path.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    return match serde_json::from_str::<SongSidecar>(&sidecar_text) {
+        Ok(song_sidecar) => Ok(song_sidecar),
+        Err(e) => Err(format!(
+            "Could not parse sidecar {}: {}",
+            sidecar_path.to_string_lossy(),
+            e
+        )),
+    };
+}
+
+/// Whether the file is an info json sidecar
+pub fn is_info_json_path(file_path: &Path) -> bool {
+    return file_path.file_name().is_some_and(|file_name| {
+        file_name
+            .to_string_lossy()
+            .ends_with(&format!(".{}", INFO_JSON_EXTENSION))
+    });
+}
+
+/// The sidecar folder of the settings, relative to the library root, None if sidecars are
+/// written next to the songs
+pub fn get_sidecar_directory(settings: &Settings, library_paths: &LibraryPaths) -> Option<PathBuf> {
+    return settings
+        .sidecars()
+        .directory()
+        .as_ref()
+        .map(|directory| library_paths.root().join(directory));
+}
+
+/// The folder the song of the sidecar is in, the sidecar folder mirrors the folders of the library
+pub fn get_song_directory(
+    sidecar_path: &Path,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Option<PathBuf> {
+    let sidecar_parent = sidecar_path.parent()?;
+
+    return match get_sidecar_directory(settings, library_paths)
+        .and_then(|directory| sidecar_parent.strip_prefix(directory).ok())
+    {
+        Some(relative_directory) => Some(library_paths.root().join(relative_directory)),
+        None => Some(sidecar_parent.to_path_buf()),
+    };
+}
+
+// the sidecar of the song with the extension, next to it, or at the same path in the sidecar
+// folder if one is set so songs with the same name in different folders keep their own
+fn get_sidecar_path(
+    song_path: &Path,
+    extension: &str,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+) -> Result<PathBuf, String> {
+    // the folder of a split mix keeps its whole name, as there is no extension to replace
+    let sidecar_name = match song_path.is_dir() {
+        true => song_path.file_name(),
+        false => song_path.file_stem(),
+    };
+
+    let sidecar_name = match sidecar_name {
+        Some(sidecar_name) => sidecar_name.to_string_lossy().to_string(),
+        None => {
+            return Err(format!(
+                "Song {} has no file name",
+                song_path.to_string_lossy()
+            ))
+        }
+    };
+
+    let song_directory = song_path
+        .parent()
+        .map(|parent| parent.to_path_buf())
+        .unwrap_or_default();
+
+    let sidecar_directory = match get_sidecar_directory(settings, library_paths) {
+        Some(directory) => match song_directory.strip_prefix(library_paths.root()) {
+            Ok(relative_directory) => directory.join(relative_directory),
+            Err(e) => {
+                return Err(format!(
+                    "Song {} is not in the library {}: {}",
+                    song_path.to_string_lossy(),
+                    library_paths.root().to_string_lossy(),
+                    e
+                ))
+            }
+        },
+        None => song_directory,
+    };
+
+    return Ok(sidecar_directory.join(format!("{}.{}", sidecar_name, extension)));
+}
+
+fn get_file_name(song_path: &Path) -> Result<String, String> {
+    return match song_path.file_name() {
+        Some(file_name) => Ok(file_name.to_string_lossy().to_string()),
+        None => Err(format!(
+            "Song {} has no file name",
+            song_path.to_string_lossy()
+        )),
+    };
+}
+
+fn write_sidecar_file(sidecar_path: &Path, contents: &str) -> Result<(), String> {
+    if let Some(parent_directory) = sidecar_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent_directory) {
+            return Err(format!(
+                "Could not create directory {}: {}",
+                parent_directory.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    if let Err(e) = std::fs::write(sidecar_path, contents) {
+        return Err(format!(
+            "Could not write sidecar {}: {}",
+            sidecar_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+fn escape_xml(text: &str) -> String {
+    return text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_sidecar_path, get_song_directory, SongSidecar, INFO_JSON_EXTENSION};
+    use crate::library_paths::LibraryPaths;
+    use crate::music_sources::StoredSongMetadata;
+    use crate::settings_parser::Settings;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_song_sidecar() {
+        let song_sidecar = SongSidecar {
+            song_url: "https://www.youtube.com/watch?v=abc".to_string(),
+            playlist_url: "https://www.youtube.com/playlist?list=xyz".to_string(),
+            file_name: "Artist - Title [abc].mp3".to_string(),
+            metadata: StoredSongMetadata {
+                title: "Title".to_string(),
+                artist: "Artist & Friends".to_string(),
+                playlist_name: "Liked <3".to_string(),
+                release_date: Some("2024-05-01".to_string()),
+                ..Default::default()
+            },
+            source: serde_json::json!({ "id": "abc", "description": "Out now" }),
+        };
+
+        let info_json = serde_json::to_string(&song_sidecar).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SongSidecar>(&info_json).unwrap(),
+            song_sidecar
+        );
+
+        let nfo = song_sidecar.to_nfo();
+        assert!(nfo.contains("<artist>Artist &amp; Friends</artist>"));
+        assert!(nfo.contains("<album>Liked &lt;3</album>"));
+        assert!(nfo.contains("<year>2024</year>"));
+        assert!(nfo.contains("<plot>Out now</plot>"));
+        assert!(!nfo.contains("<isrc>"));
+    }
+
+    #[test]
+    fn test_sidecar_directory_mirrors_library() {
+        let library_paths = LibraryPaths::new(PathBuf::from("/srv/music"), None);
+        let settings = serde_json::from_value::<Settings>(
+            serde_json::json!({ "sidecars": { "directory": "sidecars" } }),
+        )
+        .unwrap();
+
+        // songs with the same name in different folders keep their own sidecar
+        let rock_sidecar_path = get_sidecar_path(
+            Path::new("/srv/music/downloaded/Rock/A/Intro.mp3"),
+            INFO_JSON_EXTENSION,
+            &settings,
+            &library_paths,
+        )
+        .unwrap();
+        let pop_sidecar_path = get_sidecar_path(
+            Path::new("/srv/music/archive/2026-10-19_12-00-00/Pop/B/Intro.mp3"),
+            INFO_JSON_EXTENSION,
+            &settings,
+            &library_paths,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rock_sidecar_path,
+            PathBuf::from("/srv/music/sidecars/downloaded/Rock/A/Intro.info.json")
+        );
+        assert_eq!(
+            pop_sidecar_path,
+            PathBuf::from("/srv/music/sidecars/archive/2026-10-19_12-00-00/Pop/B/Intro.info.json")
+        );
+
+        assert_eq!(
+            get_song_directory(&pop_sidecar_path, &settings, &library_paths),
+            Some(PathBuf::from(
+                "/srv/music/archive/2026-10-19_12-00-00/Pop/B"
+            ))
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::file_lister;
+use crate::library_paths::{self, LibraryPaths};
+use crate::settings_parser::Settings;
+
+pub mod local_storage;
+pub mod s3_storage;
+
+/* Common trait of the places finished songs are uploaded to */
+pub trait StorageBackend {
+    fn name(&self) -> String;
+    // sha256 of the file stored with the key, None if there is none
+    fn get_stored_hash(&self, key: &str) -> Result<Option<String>, String>;
+    // store the file with the key, replacing what is stored with it
+    fn store_file(&self, file_path: &Path, key: &str, hash: &str) -> Result<(), String>;
+    // move what is stored with a key to another, replacing what the other has
+    fn move_file(&self, from_key: &str, to_key: &str) -> Result<(), String>;
+    // delete what is stored with the key, nothing if there is none
+    fn delete_file(&self, key: &str) -> Result<(), String>;
+}
+
+/// Uploads finished songs to the storage of the settings, under the key the key template gives them
+pub struct SongStorage {
+    backend: Box<dyn StorageBackend>,
+    key_template: String,
+}
+
+/// Songs counted by what syncing them did
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub uploaded: usize,
+    // already stored with the same hash
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl SongStorage {
+    /// The storage of the uri in the settings, None if songs are not uploaded
+    pub fn from_settings(settings: &Settings) -> Result<Option<SongStorage>, String> {
+        let storage_settings = settings.storage();
+
+        let uri = match storage_settings.uri() {
+            Some(uri) => uri,
+            None => return Ok(None),
+        };
+
+        let backend: Box<dyn StorageBackend> = match uri.starts_with("s3://") {
+            true => Box::new(s3_storage::S3Storage::new(uri, settings.s3())?),
+            false => Box::new(local_storage::LocalStorage::new(Path::new(uri))),
+        };
+
+        // a broken template should fail before anything is downloaded
+        render_key_template(
+            storage_settings.key_template(),
+            &get_key_values(Path::new("song.mp3"), ""),
+        )?;
+
+        return Ok(Some(SongStorage {
+            backend,
+            key_template: storage_settings.key_template().to_owned(),
+        }));
+    }
+
+    /// Upload the song unless the storage has it with the same hash already, returns whether it
+    /// was uploaded
+    pub fn store_song(
+        &self,
+        file_path: &Path,
+        library_paths: &LibraryPaths,
+    ) -> Result<bool, String> {
+        let key = self.get_song_key(file_path, library_paths)?;
+
+        let hash = get_file_hash(file_path)?;
+
+        if self.backend.get_stored_hash(&key)?.as_deref() == Some(hash.as_str()) {
+            return Ok(false);
+        }
+
+        self.backend.store_file(file_path, &key, &hash)?;
+
+        return Ok(true);
+    }
+
+    /// Upload the song, reporting instead of failing when it can not be, it is uploaded again by
+    /// the next sync
+    pub fn try_store_song(&self, file_path: &Path, library_paths: &LibraryPaths) {
+        match self.store_song(file_path, library_paths) {
+            Ok(true) => println!(
+                "Uploaded {} to {}",
+                library_paths.to_stored_path(file_path),
+                self.backend.name()
+            ),
+            Ok(false) => (),
+            Err(e) => println!(
+                "Could not upload {} to {}: {}",
+                library_paths.to_stored_path(file_path),
+                self.backend.name(),
+                e
+            ),
+        }
+    }
+
+    /// Store the song that was moved, archived or renamed, from the key of its old path to the key
+    /// of its new one, so the storage does not keep it under both. It is moved in the storage if
+    /// the storage has it unchanged, uploaded again otherwise
+    pub fn move_song(
+        &self,
+        old_file_path: &Path,
+        file_path: &Path,
+        library_paths: &LibraryPaths,
+    ) -> Result<bool, String> {
+        let old_key = self.get_song_key(old_file_path, library_paths)?;
+        let key = self.get_song_key(file_path, library_paths)?;
+
+        if old_key == key {
+            return self.store_song(file_path, library_paths);
+        }
+
+        let hash = get_file_hash(file_path)?;
+
+        if self.backend.get_stored_hash(&old_key)?.as_deref() == Some(hash.as_str()) {
+            self.backend.move_file(&old_key, &key)?;
+            return Ok(true);
+        }
+
+        let uploaded = self.store_song(file_path, library_paths)?;
+
+        // what is left under the old key is an older version of the song
+        self.backend.delete_file(&old_key)?;
+
+        return Ok(uploaded);
+    }
+
+    /// Move the song in the storage, reporting instead of failing when it can not be
+    pub fn try_move_song(
+        &self,
+        old_file_path: &Path,
+        file_path: &Path,
+        library_paths: &LibraryPaths,
+    ) {
+        match self.move_song(old_file_path, file_path, library_paths) {
+            Ok(true) => println!(
+                "Stored {} in {}",
+                library_paths.to_stored_path(file_path),
+                self.backend.name()
+            ),
+            Ok(false) => (),
+            Err(e) => println!(
+                "Could not store {} in {}: {}",
+                library_paths.to_stored_path(file_path),
+                self.backend.name(),
+                e
+            ),
+        }
+    }
+
+    /// Upload the songs in the downloaded and archive folders the storage does not have yet
+    pub fn sync_library(&self, library_paths: &LibraryPaths) -> Result<SyncSummary, String> {
+        let mut library_files = file_lister::list_files_in(&[
+            library_paths.downloaded_directory(),
+            library_paths.archive_directory(),
+        ])?;
+
+        library_files.sort();
+
+        let mut sync_summary = SyncSummary::default();
+
+        for file_path in library_files
+            .iter()
+            .filter(|file_path| library_paths::is_song_path(file_path))
+        {
+            match self.store_song(file_path, library_paths) {
+                Ok(true) => {
+                    println!("Uploaded {}", library_paths.to_stored_path(file_path));
+                    sync_summary.uploaded += 1;
+                }
+                Ok(false) => sync_summary.skipped += 1,
+                Err(e) => {
+                    println!(
+                        "Could not upload {}: {}",
+                        library_paths.to_stored_path(file_path),
+                        e
+                    );
+                    sync_summary.failed += 1;
+                }
+            }
+        }
+
+        return Ok(sync_summary);
+    }
+
+    pub fn name(&self) -> String {
+        return self.backend.name();
+    }
+
+    // key of the song from its path in the downloaded folder or archive batch
+    fn get_song_key(
+        &self,
+        file_path: &Path,
+        library_paths: &LibraryPaths,
+    ) -> Result<String, String> {
+        let library_directory = library_paths.get_library_directory(file_path)?;
+
+        let relative_path = match file_path.strip_prefix(&library_directory) {
+            Ok(relative_path) => relative_path,
+            Err(e) => {
+                return Err(format!(
+                    "{} is not in {}: {}",
+                    file_path.to_string_lossy(),
+                    library_directory.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        // songs that are not archived yet have no batch
+        let batch_name = match library_directory.starts_with(library_paths.archive_directory()) {
+            true => library_directory
+                .file_name()
+                .map(|batch_name| batch_name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            false => String::new(),
+        };
+
+        return render_key_template(
+            &self.key_template,
+            &get_key_values(relative_path, &batch_name),
+        );
+    }
+}
+
+// values of the key template placeholders, from the path of the song relative to its library folder
+// and the name of its archive batch
+fn get_key_values(relative_path: &Path, batch_name: &str) -> HashMap<&'static str, String> {
+    let to_key = |path: &Path| -> String {
+        return path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("/");
+    };
+
+    let get_text = |text: Option<&std::ffi::OsStr>| -> String {
+        return text
+            .map(|text| text.to_string_lossy().to_string())
+            .unwrap_or_default();
+    };
+
+    return HashMap::from([
+        ("batch", batch_name.to_string()),
+        ("path", to_key(relative_path)),
+        (
+            "directory",
+            relative_path.parent().map(to_key).unwrap_or_default(),
+        ),
+        ("file_name", get_text(relative_path.file_name())),
+        ("stem", get_text(relative_path.file_stem())),
+        ("ext", get_text(relative_path.extension())),
+    ]);
+}
+
+// fill the placeholders of the key template, values keep their "/" as keys have folders too
+fn render_key_template(
+    key_template: &str,
+    values: &HashMap<&'static str, String>,
+) -> Result<String, String> {
+    let mut key = String::new();
+    let mut remaining = key_template;
+
+    while let Some(placeholder_start) = remaining.find('{') {
+        key.push_str(&remaining[..placeholder_start]);
+
+        let placeholder_end = match remaining[placeholder_start..].find('}') {
+            Some(i) => placeholder_start + i,
+            None => {
+                return Err(format!(
+                    "Unclosed placeholder in storage key template: {}",
+                    key_template
+                ))
+            }
+        };
+
+        let name = &remaining[placeholder_start + 1..placeholder_end];
+
+        match values.get(name) {
+            Some(value) => key.push_str(value),
+            None => {
+                let mut names = values.keys().copied().collect::<Vec<&str>>();
+                names.sort();
+
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in storage key template, expected one of: {}",
+                    name,
+                    names.join(", ")
+                ));
+            }
+        }
+
+        remaining = &remaining[placeholder_end + 1..];
+    }
+
+    key.push_str(remaining);
+
+    // an empty folder, ex. "{directory}/{file_name}" for a song at the top, is not part of the key
+    return Ok(key
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("/"));
+}
+
+/// The sha256 of the file, as lowercase hex
+pub fn get_file_hash(file_path: &Path) -> Result<String, String> {
+    let mut file = match std::fs::File::open(file_path) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(format!(
+                "Could not open {}: {}",
+                file_path.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 65536];
+
+    loop {
+        let bytes_read = match file.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) => {
+                return Err(format!(
+                    "Could not read {}: {}",
+                    file_path.to_string_lossy(),
+                    e
+                ))
+            }
+        };
+
+        if bytes_read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    return Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_key_values, render_key_template};
+    use std::path::Path;
+
+    #[test]
+    fn test_render_key_template() {
+        let values = get_key_values(Path::new("Artist/Album/01 Title.mp3"), "");
+
+        assert_eq!(
+            render_key_template("{path}", &values).unwrap(),
+            "Artist/Album/01 Title.mp3"
+        );
+        assert_eq!(
+            render_key_template("music/{directory}/{stem}.{ext}", &values).unwrap(),
+            "music/Artist/Album/01 Title.mp3"
+        );
+
+        // a song at the top of the library has no folder
+        let values = get_key_values(Path::new("Title.opus"), "");
+        assert_eq!(
+            render_key_template("{directory}/{file_name}", &values).unwrap(),
+            "Title.opus"
+        );
+
+        // an archived song is under its batch, a song not archived yet is not
+        let values = get_key_values(Path::new("Artist/Title.opus"), "2026-10-19_12-00-00");
+        assert_eq!(
+            render_key_template("{batch}/{path}", &values).unwrap(),
+            "2026-10-19_12-00-00/Artist/Title.opus"
+        );
+        let values = get_key_values(Path::new("Artist/Title.opus"), "");
+        assert_eq!(
+            render_key_template("{batch}/{path}", &values).unwrap(),
+            "Artist/Title.opus"
+        );
+
+        assert!(render_key_template("{album}/{file_name}", &values).is_err());
+        assert!(render_key_template("{path", &values).is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::{Path, PathBuf};
+
+use super::{get_file_hash, StorageBackend};
+
+/// Songs copied into a folder, ex. a mounted network share or a folder synced to other devices
+pub struct LocalStorage {
+    directory: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(directory: &Path) -> Self {
+        return LocalStorage {
+            directory: directory.to_path_buf(),
+        };
+    }
+
+    fn get_stored_path(&self, key: &str) -> PathBuf {
+        return key
+            .split('/')
+            .fold(self.directory.to_owned(), |path, part| path.join(part));
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn name(&self) -> String {
+        return self.directory.to_string_lossy().to_string();
+    }
+
+    fn get_stored_hash(&self, key: &str) -> Result<Option<String>, String> {
+        let stored_path = self.get_stored_path(key);
+
+        if !stored_path.is_file() {
+            return Ok(None);
+        }
+
+        return get_file_hash(&stored_path).map(Some);
+    }
+
+    fn store_file(&self, file_path: &Path, key: &str, _hash: &str) -> Result<(), String> {
+        let stored_path = self.get_stored_path(key);
+
+        create_parent_directory(&stored_path)?;
+
+        // copied next to it first, so devices syncing the folder never see a partial song
+        let mut temporary_file_name = stored_path.as_os_str().to_owned();
+        temporary_file_name.push(".part");
+        let temporary_path = PathBuf::from(temporary_file_name);
+
+        if let Err(e) = std::fs::copy(file_path, &temporary_path) {
+            let _ = std::fs::remove_file(&temporary_path);
+
+            return Err(format!(
+                "Could not copy {} to {}: {}",
+                file_path.to_string_lossy(),
+                temporary_path.to_string_lossy(),
+                e
+            ));
+        }
+
+        if let Err(e) = std::fs::rename(&temporary_path, &stored_path) {
+            return Err(format!(
+                "Could not move {} into place: {}",
+                temporary_path.to_string_lossy(),
+                e
+            ));
+        }
+
+        return Ok(());
+    }
+
+    fn move_file(&self, from_key: &str, to_key: &str) -> Result<(), String> {
+        let from_path = self.get_stored_path(from_key);
+        let to_path = self.get_stored_path(to_key);
+
+        create_parent_directory(&to_path)?;
+
+        return match std::fs::rename(&from_path, &to_path) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!(
+                "Could not move {} to {}: {}",
+                from_path.to_string_lossy(),
+                to_path.to_string_lossy(),
+                e
+            )),
+        };
+    }
+
+    fn delete_file(&self, key: &str) -> Result<(), String> {
+        let stored_path = self.get_stored_path(key);
+
+        return match std::fs::remove_file(&stored_path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!(
+                "Could not delete {}: {}",
+                stored_path.to_string_lossy(),
+                e
+            )),
+        };
+    }
+}
+
+fn create_parent_directory(stored_path: &Path) -> Result<(), String> {
+    if let Some(parent_directory) = stored_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(parent_directory) {
+            return Err(format!(
+                "Could not create directory {}: {}",
+                parent_directory.to_string_lossy(),
+                e
+            ));
+        }
+    }
+
+    return Ok(());
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::StorageBackend;
+use crate::s3_client::{S3Client, S3Uri};
+use crate::settings_parser::S3Settings;
+
+// user metadata the sha256 of an uploaded song is kept in, the etag is only the md5 of the
+// object for single part uploads without server side encryption
+const HASH_METADATA_KEY: &str = "sha256";
+
+/// Songs uploaded to an s3 compatible bucket, under a prefix
+pub struct S3Storage {
+    s3_client: S3Client,
+    bucket: String,
+    // "" or ends with "/"
+    prefix: String,
+}
+
+impl S3Storage {
+    /// The storage of an "s3://bucket" or "s3://bucket/prefix" uri
+    pub fn new(uri: &str, s3_settings: &S3Settings) -> Result<S3Storage, String> {
+        let path = match uri.strip_prefix("s3://") {
+            Some(path) => path,
+            None => return Err(format!("{} is not an s3://bucket/prefix uri", uri)),
+        };
+
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+
+        if bucket.is_empty() {
+            return Err(format!("{} is not an s3://bucket/prefix uri", uri));
+        }
+
+        let prefix = prefix.trim_matches('/');
+
+        return Ok(S3Storage {
+            s3_client: S3Client::new(s3_settings)?,
+            bucket: bucket.to_string(),
+            prefix: match prefix.is_empty() {
+                true => String::new(),
+                false => format!("{}/", prefix),
+            },
+        });
+    }
+
+    fn get_s3_uri(&self, key: &str) -> S3Uri {
+        return S3Uri {
+            bucket: self.bucket.to_owned(),
+            key: format!("{}{}", self.prefix, key),
+        };
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn name(&self) -> String {
+        return format!("s3://{}/{}", self.bucket, self.prefix);
+    }
+
+    fn get_stored_hash(&self, key: &str) -> Result<Option<String>, String> {
+        return match self.s3_client.head_object(&self.get_s3_uri(key)) {
+            Ok(object_head) => Ok(object_head
+                .and_then(|object_head| object_head.metadata.get(HASH_METADATA_KEY).cloned())),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    fn store_file(&self, file_path: &Path, key: &str, hash: &str) -> Result<(), String> {
+        let metadata = HashMap::from([(HASH_METADATA_KEY.to_string(), hash.to_string())]);
+
+        return match self
+            .s3_client
+            .put_file(&self.get_s3_uri(key), file_path, &metadata)
+        {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+
+    fn move_file(&self, from_key: &str, to_key: &str) -> Result<(), String> {
+        if let Err(e) = self
+            .s3_client
+            .copy_object(&self.get_s3_uri(from_key), &self.get_s3_uri(to_key))
+        {
+            return Err(e.to_string());
+        }
+
+        return self.delete_file(from_key);
+    }
+
+    fn delete_file(&self, key: &str) -> Result<(), String> {
+        return match self.s3_client.delete_object(&self.get_s3_uri(key), None) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+}
@@ -0,0 +1,452 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::{Deserialize, Serialize};
+
+use crate::music_sources::DownloadInformation;
+use crate::settings_parser::{Settings, TrimmingSettings};
+use crate::tool_locator;
+
+// segments and silences starting this close to the start, or ending this close to the end, are cut
+const EDGE_TOLERANCE_SECONDS: f64 = 0.5;
+
+// songs are left alone when trimming would leave less than this
+const MIN_KEPT_SECONDS: f64 = 10.0;
+
+const SILENCE_REASON: &str = "silence";
+
+/// A part of the original song that was cut, times are in seconds of the original song
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AppliedTrim {
+    // ex. "silence", "sponsorblock intro", "chapter Outro"
+    pub reason: String,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+/// The part of a song that is kept, and the trims that cut away the rest
+#[derive(Debug, PartialEq)]
+pub struct TrimPlan {
+    pub kept_start: f64,
+    pub kept_end: f64,
+    pub trims: Vec<AppliedTrim>,
+}
+
+// silences found by ffmpeg, as (start, end) in seconds
+struct SilenceDetection {
+    silences: Vec<(f64, f64)>,
+    duration: Option<f64>,
+}
+
+/// Cut the silence, intro and outro segments from the start and end of a new song.
+/// Returns the trims that were applied, empty if nothing was cut
+pub fn trim_song(
+    file_path: &Path,
+    download_information: &DownloadInformation,
+    settings: &Settings,
+) -> Result<Vec<AppliedTrim>, String> {
+    let trimming_settings = settings.trimming();
+
+    if !trimming_settings.is_enabled() {
+        return Ok(Vec::new());
+    }
+
+    let ffmpeg = tool_locator::locate_ffmpeg(settings.ffmpeg())?;
+
+    let silence_detection = match trimming_settings.trim_silence() {
+        true => detect_silences(&ffmpeg.path, file_path, trimming_settings)?,
+        false => SilenceDetection {
+            silences: Vec::new(),
+            duration: None,
+        },
+    };
+
+    // the duration of the file is more precise than the one of the video
+    let duration = match silence_detection.duration.or(download_information.duration) {
+        Some(duration) => duration,
+        None => {
+            return Err(format!(
+                "The duration of {} is not known",
+                file_path.to_string_lossy()
+            ))
+        }
+    };
+
+    let segments = get_cut_segments(&download_information.info_json, trimming_settings);
+
+    let trim_plan = plan_trims(duration, &segments, &silence_detection.silences);
+
+    if trim_plan.trims.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    cut_song(
+        &ffmpeg.path,
+        file_path,
+        trim_plan.kept_start,
+        trim_plan.kept_end,
+    )?;
+
+    return Ok(trim_plan.trims);
+}
+
+/// Plan which segments and silences to cut from the start and end of a song.
+/// Cuts are chained, so a silence after a cut intro is cut as well
+pub fn plan_trims(duration: f64, segments: &[AppliedTrim], silences: &[(f64, f64)]) -> TrimPlan {
+    let candidates: Vec<AppliedTrim> = segments
+        .iter()
+        .cloned()
+        .chain(silences.iter().map(|(start_time, end_time)| AppliedTrim {
+            reason: SILENCE_REASON.to_string(),
+            start_time: *start_time,
+            end_time: *end_time,
+        }))
+        .collect();
+
+    let mut kept_start = 0.0;
+    let mut trims = Vec::new();
+
+    // the candidate that starts at the kept start and reaches the furthest
+    while let Some(candidate) = candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.start_time <= kept_start + EDGE_TOLERANCE_SECONDS
+                && candidate.end_time.min(duration) > kept_start
+        })
+        .max_by(|a, b| a.end_time.total_cmp(&b.end_time))
+    {
+        trims.push(AppliedTrim {
+            reason: candidate.reason.to_owned(),
+            start_time: kept_start,
+            end_time: candidate.end_time.min(duration),
+        });
+
+        kept_start = candidate.end_time.min(duration);
+    }
+
+    let mut kept_end = duration;
+
+    // the candidate that ends at the kept end and reaches back the furthest
+    while let Some(candidate) = candidates
+        .iter()
+        .filter(|candidate| {
+            candidate.end_time >= kept_end - EDGE_TOLERANCE_SECONDS
+                && candidate.start_time < kept_end
+                && candidate.start_time > kept_start
+        })
+        .min_by(|a, b| a.start_time.total_cmp(&b.start_time))
+    {
+        trims.push(AppliedTrim {
+            reason: candidate.reason.to_owned(),
+            start_time: candidate.start_time,
+            end_time: kept_end,
+        });
+
+        kept_end = candidate.start_time;
+    }
+
+    // a song that is all intro or silence is more likely a bad segment than a song to throw away
+    if kept_end - kept_start < MIN_KEPT_SECONDS {
+        return TrimPlan {
+            kept_start: 0.0,
+            kept_end: duration,
+            trims: Vec::new(),
+        };
+    }
+
+    return TrimPlan {
+        kept_start,
+        kept_end,
+        trims,
+    };
+}
+
+// the chapters and SponsorBlock segments in the info json that are to be cut
+fn get_cut_segments(
+    info_json: &serde_json::Value,
+    trimming_settings: &TrimmingSettings,
+) -> Vec<AppliedTrim> {
+    let get_segments = |key: &str| -> Vec<serde_json::Value> {
+        return info_json
+            .get(key)
+            .and_then(|value| value.as_array())
+            .cloned()
+            .unwrap_or_default();
+    };
+
+    let get_times = |segment: &serde_json::Value| -> Option<(f64, f64)> {
+        let start_time = segment.get("start_time")?.as_f64()?;
+        let end_time = segment.get("end_time")?.as_f64()?;
+
+        return Some((start_time, end_time));
+    };
+
+    let cut_chapters: Vec<String> = trimming_settings
+        .cut_chapters()
+        .iter()
+        .map(|chapter| chapter.to_lowercase())
+        .collect();
+
+    let chapter_segments = get_segments("chapters").into_iter().filter_map(|chapter| {
+        let title = chapter.get("title")?.as_str()?.to_string();
+        let (start_time, end_time) = get_times(&chapter)?;
+
+        let lowercase_title = title.to_lowercase();
+        if !cut_chapters
+            .iter()
+            .any(|cut_chapter| lowercase_title.contains(cut_chapter))
+        {
+            return None;
+        }
+
+        return Some(AppliedTrim {
+            reason: format!("chapter {}", title),
+            start_time,
+            end_time,
+        });
+    });
+
+    let sponsorblock_segments = get_segments("sponsorblock_chapters")
+        .into_iter()
+        .filter_map(|segment| {
+            let category = segment.get("category")?.as_str()?.to_string();
+            let (start_time, end_time) = get_times(&segment)?;
+
+            if !trimming_settings
+                .sponsorblock_categories()
+                .contains(&category)
+            {
+                return None;
+            }
+
+            return Some(AppliedTrim {
+                reason: format!("sponsorblock {}", category),
+                start_time,
+                end_time,
+            });
+        });
+
+    return chapter_segments.chain(sponsorblock_segments).collect();
+}
+
+// the silences in the song, and the duration of the file
+fn detect_silences(
+    ffmpeg_path: &Path,
+    file_path: &Path,
+    trimming_settings: &TrimmingSettings,
+) -> Result<SilenceDetection, String> {
+    let command_output = match Command::new(ffmpeg_path)
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(file_path)
+        .args(["-map", "0:a:0", "-af"])
+        .arg(format!(
+            "silencedetect=noise={}dB:d={}",
+            trimming_settings.silence_threshold_db(),
+            trimming_settings.min_silence_seconds()
+        ))
+        .args(["-f", "null", "-"])
+        .output()
+    {
+        Ok(out) => out,
+        Err(e) => return Err(format!("Could not run ffmpeg: {}", e)),
+    };
+
+    let stderr_string = String::from_utf8_lossy(&command_output.stderr);
+
+    if !command_output.status.success() {
+        return Err(format!(
+            "ffmpeg could not detect the silences of {}: {}",
+            file_path.to_string_lossy(),
+            stderr_string.lines().last().unwrap_or("")
+        ));
+    }
+
+    return Ok(parse_silencedetect_output(&stderr_string));
+}
+
+// the silencedetect filter prints "silence_start: 12.5" and "silence_end: 14 | silence_duration: 1.5" lines
+fn parse_silencedetect_output(ffmpeg_output: &str) -> SilenceDetection {
+    let get_value = |line: &str, key: &str| -> Option<f64> {
+        let value_start = line.find(key)? + key.len();
+
+        return line[value_start..].split_whitespace().next()?.parse().ok();
+    };
+
+    // ffmpeg prints the duration of the input as "Duration: 00:03:21.45,"
+    let duration = ffmpeg_output.lines().find_map(|line| {
+        let duration_text = line.trim().strip_prefix("Duration: ")?.split(',').next()?;
+        let mut parts = duration_text.split(':');
+
+        let hours: f64 = parts.next()?.parse().ok()?;
+        let minutes: f64 = parts.next()?.parse().ok()?;
+        let seconds: f64 = parts.next()?.parse().ok()?;
+
+        return Some(hours * 3600.0 + minutes * 60.0 + seconds);
+    });
+
+    let mut silences = Vec::new();
+    let mut silence_start: Option<f64> = None;
+
+    for line in ffmpeg_output.lines() {
+        if let Some(start_time) = get_value(line, "silence_start: ") {
+            silence_start = Some(start_time);
+        } else if let Some(end_time) = get_value(line, "silence_end: ") {
+            if let Some(start_time) = silence_start.take() {
+                silences.push((start_time, end_time));
+            }
+        }
+    }
+
+    // older ffmpeg versions do not end a silence that lasts until the end of the file
+    if let (Some(start_time), Some(duration)) = (silence_start, duration) {
+        silences.push((start_time, duration));
+    }
+
+    return SilenceDetection { silences, duration };
+}
+
+// keep only the part of the song between the kept start and end
+fn cut_song(
+    ffmpeg_path: &Path,
+    file_path: &Path,
+    kept_start: f64,
+    kept_end: f64,
+) -> Result<(), String> {
+    let temporary_path = get_temporary_path(file_path)?;
+
+    cut_audio(
+        ffmpeg_path,
+        file_path,
+        kept_start,
+        Some(kept_end),
+        &temporary_path,
+    )?;
+
+    if let Err(e) = std::fs::rename(&temporary_path, file_path) {
+        return Err(format!(
+            "Could not move {} into place: {}",
+            temporary_path.to_string_lossy(),
+            e
+        ));
+    }
+
+    return Ok(());
+}
+
+/// Copy the audio between the start and end times, or the end of the file, into the output file
+pub fn cut_audio(
+    ffmpeg_path: &Path,
+    file_path: &Path,
+    start_time: f64,
+    end_time: Option<f64>,
+    output_path: &Path,
+) -> Result<(), String> {
+    let mut command = Command::new(ffmpeg_path);
+    command
+        .arg("-y")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .args(["-loglevel", "error"])
+        .arg("-i")
+        .arg(file_path)
+        .arg("-ss")
+        .arg(format!("{:.3}", start_time));
+
+    if let Some(end_time) = end_time {
+        command.arg("-to").arg(format!("{:.3}", end_time));
+    }
+
+    // the stream is copied, audio packets are short enough that cutting on them is precise
+    let command_output = match command
+        .args(["-map", "0:a:0", "-map_chapters", "-1", "-c", "copy"])
+        .arg(output_path)
+        .output()
+    {
+        Ok(out) => out,
+        Err(e) => return Err(format!("Could not run ffmpeg: {}", e)),
+    };
+
+    if !command_output.status.success() {
+        let _ = std::fs::remove_file(output_path);
+
+        return Err(format!(
+            "ffmpeg could not cut {}: {}",
+            file_path.to_string_lossy(),
+            String::from_utf8_lossy(&command_output.stderr).trim()
+        ));
+    }
+
+    return Ok(());
+}
+
+// ffmpeg picks the muxer from the extension, so the temporary file keeps it
+fn get_temporary_path(file_path: &Path) -> Result<PathBuf, String> {
+    return match (file_path.file_stem(), file_path.extension()) {
+        (Some(stem), Some(extension)) => Ok(file_path.with_file_name(format!(
+            "{}.trimming.{}",
+            stem.to_string_lossy(),
+            extension.to_string_lossy()
+        ))),
+        _ => Err(format!(
+            "Can not trim {}, it has no extension",
+            file_path.to_string_lossy()
+        )),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_silencedetect_output, plan_trims, AppliedTrim};
+
+    #[test]
+    fn test_plan_trims() {
+        let ffmpeg_output = "  Duration: 00:03:20.00, start: 0.000000, bitrate: 320 kb/s\n\
+            [silencedetect @ 0x5581] silence_start: 0\n\
+            [silencedetect @ 0x5581] silence_end: 1.5 | silence_duration: 1.5\n\
+            [silencedetect @ 0x5581] silence_start: 95.2\n\
+            [silencedetect @ 0x5581] silence_end: 96.4 | silence_duration: 1.2\n\
+            [silencedetect @ 0x5581] silence_start: 190.25\n";
+
+        let silence_detection = parse_silencedetect_output(ffmpeg_output);
+        let silences = silence_detection.silences;
+        assert_eq!(silence_detection.duration, Some(200.0));
+        assert_eq!(silences, vec![(0.0, 1.5), (95.2, 96.4), (190.25, 200.0)]);
+
+        // the intro starts after the silence, and the outro ends before the trailing silence
+        let segments = vec![
+            AppliedTrim {
+                reason: "sponsorblock intro".to_string(),
+                start_time: 1.6,
+                end_time: 12.0,
+            },
+            AppliedTrim {
+                reason: "chapter End screen".to_string(),
+                start_time: 180.0,
+                end_time: 190.0,
+            },
+        ];
+
+        let trim_plan = plan_trims(200.0, &segments, &silences);
+        assert_eq!(trim_plan.kept_start, 12.0);
+        assert_eq!(trim_plan.kept_end, 180.0);
+        assert_eq!(
+            trim_plan
+                .trims
+                .iter()
+                .map(|trim| trim.reason.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "silence",
+                "sponsorblock intro",
+                "silence",
+                "chapter End screen"
+            ]
+        );
+
+        // the silence in the middle of the song is kept, and nothing is cut from a song that would be empty
+        assert!(plan_trims(200.0, &[], &[(95.2, 96.4)]).trims.is_empty());
+        assert!(plan_trims(200.0, &[], &[(0.0, 195.0)]).trims.is_empty());
+    }
+}
@@ -1,10 +1,59 @@
 use getset::Getters;
-use std::ops::Deref;
 
 use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
 
-const SPACE_REGEX: &str = r"([ ]{2,})";
-const TITLE_SEPERATOR_REGEX: &str = r"[-]+";
+pub mod title_rules;
+
+use title_rules::TitleRules;
+
+// runs of any kind of whitespace, collapsed to one space
+const SPACE_REGEX: &str = r"\s+";
+// separators between the artist and the title, ex. "Artist - Title", "Artist — Title", "Artist // Title"
+const TITLE_SEPERATOR_REGEX: &str = r"\s+(?:-+|[–—]+|~+|/{2,})\s+";
+// separators without spaces, hyphens are left out as they are part of names like "Jay-Z"
+const UNSPACED_TITLE_SEPERATOR_REGEX: &str = r"\s*(?:-{2,}|[–—]+)\s*";
+
+// annotations in brackets, ex. "(Slowed)", "[Official Video]", "{HD}"
+const BRACKET_REGEX: &str = r"[(\[{]([^)\]}]*)[)\]}]";
+// featured artists written inline, ex. "Drake ft. Rihanna" or "Title feat. Rihanna"
+const FEATURING_REGEX: &str = r"(?i)(?:^|\s+)(?:feat\.?|ft\.?|featuring)\s+(.+)$";
+// version annotations that are often written without brackets at the end of the title
+const TRAILING_VERSION_REGEX: &str =
+    r"(?i)\s+(slowed(?:\s*\+\s*reverb(?:ed)?)?|sped\s+up|nightcore|reverb(?:ed)?)\s*$";
+// words of remix and version annotations, ex. "Astro Remix", "Radio Edit", "Slowed + Reverb"
+const VERSION_REGEX: &str = r"(?i)\b(?:remix|rmx|mix|edit|version|bootleg|flip|re\s*-?\s*work|vip|acoustic|live|instrumental|extended|cover|remaster(?:ed)?|slowed|reverb(?:ed)?|sped\s*up|nightcore|bass\s*boosted|8d)\b";
+// separators of several artists, ex. "MOONDEITY x INTERWORLD", "A, B & C"
+const MAIN_ARTIST_SEPERATOR_REGEX: &str = r"\s*,\s*|\s+(?:&|x|X|vs\.?)\s+";
+const FEATURED_ARTIST_SEPERATOR_REGEX: &str = r"\s*,\s*|\s+(?:&|x|X|and)\s+";
+
+// annotations made up only of these words are noise, ex. "Official Music Video", "HD", "Lyrics"
+const NOISE_WORDS: [&str; 24] = [
+    "official",
+    "music",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "visualiser",
+    "hd",
+    "hq",
+    "4k",
+    "1080p",
+    "720p",
+    "explicit",
+    "clean",
+    "mv",
+    "clip",
+    "free",
+    "download",
+    "out",
+    "now",
+    "premiere",
+    "full",
+    "song",
+];
 
 pub struct EmptyTitleExtractor;
 
@@ -15,11 +64,49 @@ pub struct InitializedTitleExtractor {
 
 #[derive(Getters)]
 pub struct FinishedTitleExtractor {
+    // title without featured artists, versions and noise
     #[getset(get = "pub")]
     name: String,
 
+    // main artists as written, ex. "MOONDEITY x INTERWORLD"
     #[getset(get = "pub")]
     artist: String,
+
+    // the main artists one by one
+    #[getset(get = "pub")]
+    artists: Vec<String>,
+
+    #[getset(get = "pub")]
+    featured_artists: Vec<String>,
+
+    // remix and version annotations, ex. "Astro Remix", "Slowed + Reverbed"
+    #[getset(get = "pub")]
+    versions: Vec<String>,
+
+    // annotations that were removed, ex. "Official Video", "HD"
+    #[getset(get = "pub")]
+    noise: Vec<String>,
+
+    // labels of the title rules that matched, in order
+    #[getset(get = "pub")]
+    matched_rules: Vec<String>,
+}
+
+// how an annotation of the title is used
+enum Annotation {
+    Featured(Vec<String>),
+    Version(String),
+    Noise(String),
+    // part of the title, ex. "(Part 2)"
+    Title,
+}
+
+// the parts of the title next to the artist
+struct TitleParts {
+    name: String,
+    featured_artists: Vec<String>,
+    versions: Vec<String>,
+    noise: Vec<String>,
 }
 
 impl EmptyTitleExtractor {
@@ -31,101 +118,595 @@ impl EmptyTitleExtractor {
 impl InitializedTitleExtractor {
     // Extract music title and artist from video title
     pub fn extract_from_title(&self) -> Result<FinishedTitleExtractor, String> {
-        //replace all | & @ \\ \" / (n spaces to 1 sapace)
-        //-> any sequence of special charaters is a seperator, getting rid of all sequence of spaces with one space
-        let space_regex = match Regex::new(SPACE_REGEX) {
-            Ok(some) => some,
-            Err(e) => return Err(format!("Error creating regex {}, {}", SPACE_REGEX, e)),
-        };
+        return self.extract_with_rules(&TitleRules::default(), None);
+    }
+
+    /// Extract the title and artist, applying the title rules in scope before the default extraction.
+    /// Rewrite rules change the title and go on, the first extract rule that matches decides
+    pub fn extract_with_rules(
+        &self,
+        title_rules: &TitleRules,
+        playlist_url: Option<&str>,
+    ) -> Result<FinishedTitleExtractor, String> {
+        let mut title = self.title.to_owned();
+        let mut matched_rules = Vec::new();
 
-        let title_seperator_regex = match Regex::new(TITLE_SEPERATOR_REGEX) {
-            Ok(regex) => regex,
-            Err(e) => {
-                return Err(format!(
-                    "Error creating regex {}, {}",
-                    TITLE_SEPERATOR_REGEX, e
-                ))
+        for title_rule in title_rules.get_rules_in_scope(&self.artist, playlist_url) {
+            if !title_rule.regex.is_match(&title) {
+                continue;
             }
-        };
 
-        let title: String = space_regex
-            .replace_all(&self.title, " ")
-            .deref()
-            .to_string();
-
-        let song_info: (String, String) = match title_seperator_regex.captures(&self.title) {
-            Some(capture_group) => {
-                // get last match in capture group
-                // can safely unwrap since first match is guaranteed to be non-null
-                let split_match = capture_group.get(capture_group.len() - 1).unwrap();
-
-                let song_artist = title.chars().take(split_match.start()).collect::<String>();
-                let song_name = title
-                    .chars()
-                    .skip(split_match.end() + 1)
-                    .take(self.title.len() - split_match.end() - 1)
-                    .collect::<String>();
-
-                (song_name, song_artist)
+            if let Some(replace) = &title_rule.replace {
+                matched_rules.push(title_rule.label.to_owned());
+                title = title_rule
+                    .regex
+                    .replace_all(&title, replace.as_str())
+                    .to_string();
+
+                continue;
+            }
+
+            // a match without a title is not an extraction
+            let rule_extraction = match title_rule.extract(&title) {
+                Some(rule_extraction) => rule_extraction,
+                None => continue,
+            };
+
+            matched_rules.push(title_rule.label.to_owned());
+
+            return finish_extraction(
+                (
+                    rule_extraction.title,
+                    rule_extraction.artist.unwrap_or(self.artist.to_owned()),
+                ),
+                rule_extraction.version,
+                matched_rules,
+            );
+        }
+
+        let song_info = split_title_and_artist(&title, &self.artist)?;
+
+        return finish_extraction(song_info, None, matched_rules);
+    }
+}
+
+impl FinishedTitleExtractor {
+    /// The title with its versions, ex. "Opium (Astro Remix) (Slowed)", as it is tagged
+    pub fn full_title(&self) -> String {
+        let mut full_title = self.name.to_owned();
+
+        for version in self.versions.iter() {
+            full_title.push_str(&format!(" ({})", version));
+        }
+
+        return full_title;
+    }
+
+    /// The artist with the featured artists, ex. "Drake feat. Rihanna", as it is tagged
+    pub fn full_artist(&self) -> String {
+        if self.featured_artists.is_empty() {
+            return self.artist.to_owned();
+        }
+
+        return format!("{} feat. {}", self.artist, self.featured_artists.join(", "));
+    }
+}
+
+// split the title into (song name, song artist) on its separator, the channel is the artist if there is none
+fn split_title_and_artist(raw_title: &str, channel: &str) -> Result<(String, String), String> {
+    // offsets are taken from and used on the same collapsed title, so they are always char boundaries
+    let title = get_regex(SPACE_REGEX)?
+        .replace_all(raw_title.trim(), " ")
+        .to_string();
+
+    // separators with spaces around them are more certain, the first one that can split the title is used
+    let split_match = match find_title_seperator(&title, TITLE_SEPERATOR_REGEX)? {
+        Some(split_match) => Some(split_match),
+        None => find_title_seperator(&title, UNSPACED_TITLE_SEPERATOR_REGEX)?,
+    };
+
+    // If no artist in video title, make the video author the artist
+    let (seperator_start, seperator_end) = match split_match {
+        Some(split_match) => split_match,
+        None => return Ok((title, channel.to_owned())),
+    };
+
+    let song_artist = title[..seperator_start].trim();
+    let song_name = title[seperator_end..].trim();
+
+    // a separator at either end leaves nothing to split off
+    if song_artist.is_empty() || song_name.is_empty() {
+        return Ok((title.to_owned(), channel.to_owned()));
+    }
+
+    return Ok((song_name.to_string(), song_artist.to_string()));
+}
+
+// (start, end) of the first separator that is outside brackets and does not cut a grapheme apart,
+// ex. the dash in "é" written as "e" and a combining accent can not be split on
+fn find_title_seperator(
+    title: &str,
+    seperator_regex: &str,
+) -> Result<Option<(usize, usize)>, String> {
+    let grapheme_boundaries: Vec<usize> = title
+        .grapheme_indices(true)
+        .map(|(index, _)| index)
+        .chain(std::iter::once(title.len()))
+        .collect();
+
+    for seperator_match in get_regex(seperator_regex)?.find_iter(title) {
+        let is_on_boundaries = grapheme_boundaries.contains(&seperator_match.start())
+            && grapheme_boundaries.contains(&seperator_match.end());
+
+        if is_on_boundaries && get_bracket_depth(&title[..seperator_match.start()]) == 0 {
+            return Ok(Some((seperator_match.start(), seperator_match.end())));
+        }
+    }
+
+    return Ok(None);
+}
+
+// number of brackets left open at the end of the text
+fn get_bracket_depth(text: &str) -> i32 {
+    return text.chars().fold(0, |depth, character| match character {
+        '(' | '[' | '{' => depth + 1,
+        ')' | ']' | '}' => (depth - 1).max(0),
+        _ => depth,
+    });
+}
+
+// parse the annotations of the song name and artist into the finished extraction
+fn finish_extraction(
+    song_info: (String, String),
+    rule_version: Option<String>,
+    matched_rules: Vec<String>,
+) -> Result<FinishedTitleExtractor, String> {
+    let title_parts = parse_title_parts(song_info.0.trim())?;
+
+    // featured artists can also follow the main artist, ex. "Drake ft. Rihanna - Title"
+    let (song_artist, artist_featured_artists) = split_featured_artists(song_info.1.trim())?;
+
+    let featured_artists: Vec<String> = artist_featured_artists
+        .into_iter()
+        .chain(title_parts.featured_artists)
+        .collect();
+
+    let versions: Vec<String> = rule_version
+        .into_iter()
+        .chain(title_parts.versions)
+        .collect();
+
+    return Ok(FinishedTitleExtractor {
+        name: title_parts.name,
+        artists: split_artists(&song_artist, MAIN_ARTIST_SEPERATOR_REGEX)?,
+        artist: song_artist,
+        featured_artists,
+        versions,
+        noise: title_parts.noise,
+        matched_rules,
+    });
+}
+
+fn get_regex(pattern: &str) -> Result<Regex, String> {
+    return match Regex::new(pattern) {
+        Ok(regex) => Ok(regex),
+        Err(e) => Err(format!("Error creating regex {}, {}", pattern, e)),
+    };
+}
+
+// split the annotations off the title, the part of the title after a "|" is only annotations
+fn parse_title_parts(raw_title: &str) -> Result<TitleParts, String> {
+    let bracket_regex = get_regex(BRACKET_REGEX)?;
+    let trailing_version_regex = get_regex(TRAILING_VERSION_REGEX)?;
+
+    let mut segments = raw_title.split('|');
+    let main_segment = segments.next().unwrap_or("");
+
+    let mut annotations: Vec<Annotation> = Vec::new();
+
+    // bracketed annotations that are part of the title stay in place
+    let mut name = bracket_regex
+        .replace_all(main_segment, |captures: &regex::Captures| {
+            let text = captures[1].trim().to_string();
+
+            return match classify_annotation(&text) {
+                Ok(Annotation::Title) => captures[0].to_string(),
+                Ok(annotation) => {
+                    annotations.push(annotation);
+                    String::new()
+                }
+                Err(_) => captures[0].to_string(),
+            };
+        })
+        .to_string();
+
+    let (name_without_featured, inline_featured_artists) = split_featured_artists(&name)?;
+    name = name_without_featured;
+
+    // trailing versions are peeled off from the end, so they come out last to first
+    let mut trailing_versions = Vec::new();
+
+    while let Some(captures) = trailing_version_regex.captures(&name) {
+        let (version_start, version) = match (captures.get(0), captures.get(1)) {
+            (Some(whole_match), Some(version)) => {
+                (whole_match.start(), version.as_str().to_string())
             }
-            // If no artist in video title, make the video author the artist
-            None => (self.title.to_owned(), self.artist.to_owned()),
+            _ => break,
         };
 
-        let song_name = song_info.0.trim();
-        let song_artist = song_info.1.trim();
+        trailing_versions.insert(0, version);
+        name.truncate(version_start);
+    }
+
+    let mut featured_artists = inline_featured_artists;
+    let mut versions = Vec::new();
+    let mut noise = Vec::new();
+
+    for annotation in annotations {
+        match annotation {
+            Annotation::Featured(artists) => featured_artists.extend(artists),
+            Annotation::Version(version) => versions.push(version),
+            Annotation::Noise(text) => noise.push(text),
+            Annotation::Title => (),
+        }
+    }
+
+    versions.extend(trailing_versions);
+
+    // segments after a "|" that are not versions are usually the name of the channel
+    for segment in segments {
+        let text = segment.trim().to_string();
+
+        if text.is_empty() {
+            continue;
+        }
+
+        match classify_annotation(&text)? {
+            Annotation::Featured(artists) => featured_artists.extend(artists),
+            Annotation::Version(version) => versions.push(version),
+            Annotation::Noise(text) => noise.push(text),
+            Annotation::Title => noise.push(text),
+        }
+    }
+
+    let name = get_regex(SPACE_REGEX)?
+        .replace_all(name.trim(), " ")
+        .trim_end_matches(['-', ' '])
+        .to_string();
+
+    return Ok(TitleParts {
+        name,
+        featured_artists,
+        versions,
+        noise,
+    });
+}
+
+// decide what an annotation is from its text
+fn classify_annotation(text: &str) -> Result<Annotation, String> {
+    let (before_featured, featured_artists) = split_featured_artists(text)?;
+
+    if before_featured.is_empty() && !featured_artists.is_empty() {
+        return Ok(Annotation::Featured(featured_artists));
+    }
+
+    let lowercase_text = text.to_lowercase();
+    let words: Vec<&str> = lowercase_text
+        .split(|character: char| character.is_whitespace() || "-/+".contains(character))
+        .filter(|word| !word.is_empty())
+        .collect();
+
+    if !words.is_empty() && words.iter().all(|word| NOISE_WORDS.contains(word)) {
+        return Ok(Annotation::Noise(text.to_string()));
+    }
 
-        return Ok(FinishedTitleExtractor {
-            name: song_name.to_owned(),
-            artist: song_artist.to_owned(),
-        });
+    if get_regex(VERSION_REGEX)?.is_match(text) {
+        return Ok(Annotation::Version(text.to_string()));
     }
+
+    return Ok(Annotation::Title);
+}
+
+// split "Drake ft. Rihanna & Future" into "Drake" and the featured artists
+fn split_featured_artists(text: &str) -> Result<(String, Vec<String>), String> {
+    let featuring_regex = get_regex(FEATURING_REGEX)?;
+
+    let captures = match featuring_regex.captures(text) {
+        Some(captures) => captures,
+        None => return Ok((text.to_string(), Vec::new())),
+    };
+
+    let (featuring_start, featured_text) = match (captures.get(0), captures.get(1)) {
+        (Some(whole_match), Some(featured_text)) => (whole_match.start(), featured_text.as_str()),
+        _ => return Ok((text.to_string(), Vec::new())),
+    };
+
+    return Ok((
+        text[..featuring_start].trim().to_string(),
+        split_artists(featured_text, FEATURED_ARTIST_SEPERATOR_REGEX)?,
+    ));
+}
+
+fn split_artists(artists: &str, seperator_regex: &str) -> Result<Vec<String>, String> {
+    return Ok(get_regex(seperator_regex)?
+        .split(artists)
+        .map(|artist| artist.trim().to_string())
+        .filter(|artist| !artist.is_empty())
+        .collect());
 }
 
 #[cfg(test)]
 mod tests {
     use super::EmptyTitleExtractor;
+    use proptest::prelude::*;
 
-    #[test]
-    fn test_title_extractor() {
-        let empty_title_extractor = EmptyTitleExtractor::init(
-            "Astro - Opium Remix (Slowed)".to_string(),
-            "Mayhem".to_string(),
-        );
-        let finalized_title_extractor = empty_title_extractor.extract_from_title().unwrap();
+    struct TitleCase {
+        title: &'static str,
+        channel: &'static str,
+        artist: &'static str,
+        artists: &'static [&'static str],
+        featured_artists: &'static [&'static str],
+        name: &'static str,
+        versions: &'static [&'static str],
+        noise: &'static [&'static str],
+    }
 
-        assert_eq!(finalized_title_extractor.artist(), "Astro");
-        assert_eq!(finalized_title_extractor.name(), "Opium Remix (Slowed)");
+    const TITLE_CASES: &[TitleCase] = &[
+        TitleCase {
+            title: "Astro - Opium Remix (Slowed)",
+            channel: "Mayhem",
+            artist: "Astro",
+            artists: &["Astro"],
+            featured_artists: &[],
+            name: "Opium Remix",
+            versions: &["Slowed"],
+            noise: &[],
+        },
+        TitleCase {
+            title: "HIMG",
+            channel: "Mayhem",
+            artist: "Mayhem",
+            artists: &["Mayhem"],
+            featured_artists: &[],
+            name: "HIMG",
+            versions: &[],
+            noise: &[],
+        },
+        TitleCase {
+            title: "MOONDEITY x INTERWORLD - ONE CHANCE | SLOWED + REVERBED",
+            channel: "Mayhem",
+            artist: "MOONDEITY x INTERWORLD",
+            artists: &["MOONDEITY", "INTERWORLD"],
+            featured_artists: &[],
+            name: "ONE CHANCE",
+            versions: &["SLOWED + REVERBED"],
+            noise: &[],
+        },
+        TitleCase {
+            title: "seekae - test & recognize [ flume re - work ] slowed",
+            channel: "Mayhem",
+            artist: "seekae",
+            artists: &["seekae"],
+            featured_artists: &[],
+            name: "test & recognize",
+            versions: &["flume re - work", "slowed"],
+            noise: &[],
+        },
+        TitleCase {
+            title: "Drake - Take Care ft. Rihanna (Official Video)",
+            channel: "DrakeVEVO",
+            artist: "Drake",
+            artists: &["Drake"],
+            featured_artists: &["Rihanna"],
+            name: "Take Care",
+            versions: &[],
+            noise: &["Official Video"],
+        },
+        TitleCase {
+            title: "Calvin Harris feat. Rihanna & Future - This Is What You Came For [HD]",
+            channel: "Calvin Harris",
+            artist: "Calvin Harris",
+            artists: &["Calvin Harris"],
+            featured_artists: &["Rihanna", "Future"],
+            name: "This Is What You Came For",
+            versions: &[],
+            noise: &["HD"],
+        },
+        TitleCase {
+            title: "Daft Punk - Get Lucky (feat. Pharrell Williams, Nile Rodgers) (Radio Edit) [Official Audio]",
+            channel: "Daft Punk",
+            artist: "Daft Punk",
+            artists: &["Daft Punk"],
+            featured_artists: &["Pharrell Williams", "Nile Rodgers"],
+            name: "Get Lucky",
+            versions: &["Radio Edit"],
+            noise: &["Official Audio"],
+        },
+        TitleCase {
+            title: "Kavinsky - Nightcall (Lyrics)",
+            channel: "Lyrics Channel",
+            artist: "Kavinsky",
+            artists: &["Kavinsky"],
+            featured_artists: &[],
+            name: "Nightcall",
+            versions: &[],
+            noise: &["Lyrics"],
+        },
+        TitleCase {
+            title: "Disclosure, AlunaGeorge - White Noise (Hot Since 82 Remix)",
+            channel: "Disclosure",
+            artist: "Disclosure, AlunaGeorge",
+            artists: &["Disclosure", "AlunaGeorge"],
+            featured_artists: &[],
+            name: "White Noise",
+            versions: &["Hot Since 82 Remix"],
+            noise: &[],
+        },
+        TitleCase {
+            title: "Burial - Archangel (Part 2) [Official Music Video] | Hyperdub",
+            channel: "Hyperdub",
+            artist: "Burial",
+            artists: &["Burial"],
+            featured_artists: &[],
+            name: "Archangel (Part 2)",
+            versions: &[],
+            noise: &["Official Music Video", "Hyperdub"],
+        },
+        TitleCase {
+            title: "Joji - Glimpse of Us sped up",
+            channel: "Mayhem",
+            artist: "Joji",
+            artists: &["Joji"],
+            featured_artists: &[],
+            name: "Glimpse of Us",
+            versions: &["sped up"],
+            noise: &[],
+        },
+        TitleCase {
+            title: "Fred again.. & Skrillex - Rumble (Live at Coachella) {4K}",
+            channel: "Fred again..",
+            artist: "Fred again.. & Skrillex",
+            artists: &["Fred again..", "Skrillex"],
+            featured_artists: &[],
+            name: "Rumble",
+            versions: &["Live at Coachella"],
+            noise: &["4K"],
+        },
+        TitleCase {
+            title: "Lofi Beats To Study To (Acoustic Version) | Official Audio",
+            channel: "Lofi Girl",
+            artist: "Lofi Girl",
+            artists: &["Lofi Girl"],
+            featured_artists: &[],
+            name: "Lofi Beats To Study To",
+            versions: &["Acoustic Version"],
+            noise: &["Official Audio"],
+        },
+    ];
 
-        let empty_title_extractor =
-            EmptyTitleExtractor::init("HIMG".to_string(), "Mayhem".to_string());
-        let finalized_title_extractor = empty_title_extractor.extract_from_title().unwrap();
+    // every character of the extracted text is, in order, in the source, as annotations are only taken out
+    fn is_taken_from(extracted: &str, source: &str) -> bool {
+        let mut source_characters = source
+            .chars()
+            .filter(|character| !character.is_whitespace());
 
-        assert_eq!(finalized_title_extractor.artist(), "Mayhem");
-        assert_eq!(finalized_title_extractor.name(), "HIMG");
+        return extracted
+            .chars()
+            .filter(|character| !character.is_whitespace())
+            .all(|character| {
+                source_characters.any(|source_character| source_character == character)
+            });
+    }
 
-        let empty_title_extractor = EmptyTitleExtractor::init(
-            "MOONDEITY x INTERWORLD - ONE CHANCE | SLOWED + REVERBED".to_string(),
-            "Mayhem".to_string(),
-        );
-        let finalized_title_extractor = empty_title_extractor.extract_from_title().unwrap();
+    // titles built from the pieces that trip extractors up
+    fn title_strategy() -> impl Strategy<Value = String> {
+        let piece = prop_oneof![
+            "[a-zA-Z]{1,8}",
+            "[ぁ-ん]{1,4}",
+            "[а-я]{1,5}",
+            Just("😀".to_string()),
+            Just("👩‍👩‍👧".to_string()),
+            Just("e\u{301}".to_string()),
+            Just(" ".to_string()),
+            Just("  ".to_string()),
+            Just(" - ".to_string()),
+            Just("-\u{301}".to_string()),
+            Just(" – ".to_string()),
+            Just("—".to_string()),
+            Just(" ~ ".to_string()),
+            Just(" // ".to_string()),
+            Just("(Slowed)".to_string()),
+            Just("[Official Video]".to_string()),
+            Just("(".to_string()),
+            Just("]".to_string()),
+            Just(" ft. ".to_string()),
+            Just(" | ".to_string()),
+        ];
 
-        assert_eq!(finalized_title_extractor.artist(), "MOONDEITY x INTERWORLD");
-        assert_eq!(
-            finalized_title_extractor.name(),
-            "ONE CHANCE | SLOWED + REVERBED"
-        );
+        return prop::collection::vec(piece, 0..12).prop_map(|pieces| pieces.concat());
+    }
 
-        let empty_title_extractor = EmptyTitleExtractor::init(
-            "seekae - test & recognize [ flume re - work ] slowed".to_string(),
-            "Mayhem".to_string(),
-        );
-        let finalized_title_extractor = empty_title_extractor.extract_from_title().unwrap();
+    proptest! {
+        #[test]
+        fn test_title_extractor_takes_from_input(title in title_strategy(), channel in "\\PC{0,8}") {
+            let finished_title_extractor =
+                EmptyTitleExtractor::init(title.to_owned(), channel.to_owned())
+                    .extract_from_title()
+                    .unwrap();
+
+            prop_assert!(is_taken_from(finished_title_extractor.name(), &title));
+            prop_assert!(
+                is_taken_from(finished_title_extractor.artist(), &title)
+                    || is_taken_from(finished_title_extractor.artist(), &channel)
+            );
+        }
+
+        #[test]
+        fn test_title_extractor_does_not_panic(title in any::<String>(), channel in any::<String>()) {
+            prop_assert!(EmptyTitleExtractor::init(title, channel).extract_from_title().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_title_extractor() {
+        for title_case in TITLE_CASES {
+            let finished_title_extractor = EmptyTitleExtractor::init(
+                title_case.title.to_string(),
+                title_case.channel.to_string(),
+            )
+            .extract_from_title()
+            .unwrap();
+
+            assert_eq!(
+                finished_title_extractor.artist(),
+                title_case.artist,
+                "{}",
+                title_case.title
+            );
+            assert_eq!(
+                finished_title_extractor.artists(),
+                title_case.artists,
+                "{}",
+                title_case.title
+            );
+            assert_eq!(
+                finished_title_extractor.featured_artists(),
+                title_case.featured_artists,
+                "{}",
+                title_case.title
+            );
+            assert_eq!(
+                finished_title_extractor.name(),
+                title_case.name,
+                "{}",
+                title_case.title
+            );
+            assert_eq!(
+                finished_title_extractor.versions(),
+                title_case.versions,
+                "{}",
+                title_case.title
+            );
+            assert_eq!(
+                finished_title_extractor.noise(),
+                title_case.noise,
+                "{}",
+                title_case.title
+            );
+        }
+
+        // the tagged title and artist put the versions and featured artists back
+        let finished_title_extractor = EmptyTitleExtractor::init(
+            "Drake - Take Care ft. Rihanna (Slowed) (Official Video)".to_string(),
+            "DrakeVEVO".to_string(),
+        )
+        .extract_from_title()
+        .unwrap();
 
-        assert_eq!(finalized_title_extractor.artist(), "seekae");
+        assert_eq!(finished_title_extractor.full_title(), "Take Care (Slowed)");
         assert_eq!(
-            finalized_title_extractor.name(),
-            "test & recognize [ flume re - work ] slowed"
+            finished_title_extractor.full_artist(),
+            "Drake feat. Rihanna"
         );
     }
 }
@@ -0,0 +1,207 @@
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+// named groups an extract rule can capture
+const TITLE_GROUP: &str = "title";
+const ARTIST_GROUP: &str = "artist";
+const VERSION_GROUP: &str = "version";
+
+#[derive(Deserialize)]
+struct TitleRulesFile {
+    rules: Vec<TitleRuleDefinition>,
+}
+
+// a rule as it is written in the rules file
+#[derive(Deserialize)]
+struct TitleRuleDefinition {
+    // shown when the rule matches, "rule <number>" if not set
+    name: Option<String>,
+    // only titles of this channel, ignoring case
+    channel: Option<String>,
+    // only titles of songs in the playlist with this url
+    playlist: Option<String>,
+    pattern: String,
+    // rewrite rules replace the matches with this, ex. "" to remove them or "$artist - $title"
+    // rules without it extract the "title", and optionally "artist" and "version" named groups
+    replace: Option<String>,
+}
+
+/// A rule of the rules file, with its pattern compiled
+#[derive(Clone)]
+pub struct TitleRule {
+    pub label: String,
+    pub regex: Regex,
+    pub replace: Option<String>,
+    channel: Option<String>,
+    playlist: Option<String>,
+}
+
+/// What an extract rule found in a title
+pub struct RuleExtraction {
+    pub title: String,
+    pub artist: Option<String>,
+    pub version: Option<String>,
+}
+
+/// The rules applied to titles, in the order of the rules file
+#[derive(Clone, Default)]
+pub struct TitleRules {
+    rules: Vec<TitleRule>,
+}
+
+impl TitleRule {
+    /// The title, artist and version the extract rule captures from the title
+    pub fn extract(&self, title: &str) -> Option<RuleExtraction> {
+        let captures = self.regex.captures(title)?;
+
+        let get_group = |group: &str| -> Option<String> {
+            return captures
+                .name(group)
+                .map(|value| value.as_str().trim().to_string())
+                .filter(|value| !value.is_empty());
+        };
+
+        return Some(RuleExtraction {
+            title: get_group(TITLE_GROUP)?,
+            artist: get_group(ARTIST_GROUP),
+            version: get_group(VERSION_GROUP),
+        });
+    }
+
+    // global rules have neither a channel nor a playlist
+    fn is_in_scope(&self, channel: &str, playlist_url: Option<&str>) -> bool {
+        let channel_matches = match &self.channel {
+            Some(rule_channel) => rule_channel.to_lowercase() == channel.to_lowercase(),
+            None => true,
+        };
+
+        let playlist_matches = match &self.playlist {
+            Some(rule_playlist) => Some(rule_playlist.as_str()) == playlist_url,
+            None => true,
+        };
+
+        return channel_matches && playlist_matches;
+    }
+}
+
+impl TitleRules {
+    /// The rules that apply to titles of the channel in the playlist, in order
+    pub fn get_rules_in_scope(&self, channel: &str, playlist_url: Option<&str>) -> Vec<&TitleRule> {
+        return self
+            .rules
+            .iter()
+            .filter(|rule| rule.is_in_scope(channel, playlist_url))
+            .collect();
+    }
+}
+
+/// Load the rules file, there are no rules if no file is configured
+pub fn load_title_rules(rules_path: Option<&Path>) -> Result<TitleRules, String> {
+    let rules_path = match rules_path {
+        Some(rules_path) => rules_path,
+        None => return Ok(TitleRules::default()),
+    };
+
+    let rules_text = match std::fs::read_to_string(rules_path) {
+        Ok(text) => text,
+        Err(e) => {
+            return Err(format!(
+                "Could not read title rules file {}: {}",
+                rules_path.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    return parse_title_rules(&rules_text);
+}
+
+/// Parse and compile the rules, a rule with an invalid pattern fails the whole file
+pub fn parse_title_rules(rules_text: &str) -> Result<TitleRules, String> {
+    let rules_file: TitleRulesFile = match serde_json::from_str(rules_text) {
+        Ok(rules_file) => rules_file,
+        Err(e) => return Err(format!("Could not parse title rules: {}", e)),
+    };
+
+    let mut rules = Vec::new();
+
+    for (rule_index, definition) in rules_file.rules.into_iter().enumerate() {
+        let label = definition
+            .name
+            .unwrap_or(format!("rule {}", rule_index + 1));
+
+        let regex = match Regex::new(&definition.pattern) {
+            Ok(regex) => regex,
+            Err(e) => return Err(format!("Invalid pattern in title {}: {}", label, e)),
+        };
+
+        // an extract rule that can not capture a title would never apply
+        if definition.replace.is_none()
+            && !regex.capture_names().any(|name| name == Some(TITLE_GROUP))
+        {
+            return Err(format!(
+                "Title {} has neither a replacement nor a \"{}\" group",
+                label, TITLE_GROUP
+            ));
+        }
+
+        rules.push(TitleRule {
+            label,
+            regex,
+            replace: definition.replace,
+            channel: definition.channel,
+            playlist: definition.playlist,
+        });
+    }
+
+    return Ok(TitleRules { rules });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_title_rules;
+
+    #[test]
+    fn test_title_rules() {
+        let title_rules = parse_title_rules(
+            r#"{
+                "rules": [
+                    { "pattern": "\\s*\\[NCS Release\\]", "replace": "" },
+                    {
+                        "name": "Mayhem uploads",
+                        "channel": "mayhem",
+                        "pattern": "^(?P<artist>.+?) - (?P<title>.+?) \\((?P<version>Slowed)\\)$"
+                    },
+                    { "playlist": "https://www.youtube.com/playlist?list=abc", "pattern": "x", "replace": "y" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let labels = |channel: &str, playlist_url: Option<&str>| -> Vec<String> {
+            return title_rules
+                .get_rules_in_scope(channel, playlist_url)
+                .iter()
+                .map(|rule| rule.label.to_owned())
+                .collect();
+        };
+
+        assert_eq!(labels("Mayhem", None), vec!["rule 1", "Mayhem uploads"]);
+        assert_eq!(
+            labels("NCS", Some("https://www.youtube.com/playlist?list=abc")),
+            vec!["rule 1", "rule 3"]
+        );
+
+        let extraction = title_rules.get_rules_in_scope("Mayhem", None)[1]
+            .extract("Astro - Opium (Slowed)")
+            .unwrap();
+        assert_eq!(extraction.artist.as_deref(), Some("Astro"));
+        assert_eq!(extraction.title, "Opium");
+        assert_eq!(extraction.version.as_deref(), Some("Slowed"));
+
+        // extract rules need a title group
+        assert!(parse_title_rules(r#"{ "rules": [{ "pattern": "(?P<artist>.+)" }] }"#).is_err());
+    }
+}
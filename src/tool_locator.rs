@@ -0,0 +1,210 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::environment_initializer;
+use crate::library_paths::{self, LibraryPaths};
+use crate::settings_parser::{FfmpegSettings, YtDlpSettings};
+
+/// Where a tool was found
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToolSource {
+    // path set in the settings
+    Configured,
+    // found on the PATH
+    SystemPath,
+    // downloaded and updated by us
+    Managed,
+}
+
+pub struct LocatedTool {
+    pub path: PathBuf,
+    pub source: ToolSource,
+}
+
+impl fmt::Display for ToolSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ToolSource::Configured => write!(f, "configured path"),
+            ToolSource::SystemPath => write!(f, "PATH"),
+            ToolSource::Managed => write!(f, "managed download"),
+        };
+    }
+}
+
+/// Find yt-dlp, in order: the configured path, the PATH, then the managed download if it is enabled.
+/// The managed download is not installed if it does not exist yet, see `ensure_yt_dlp`
+pub fn locate_yt_dlp(
+    yt_dlp_settings: &YtDlpSettings,
+    library_paths: &LibraryPaths,
+) -> Result<LocatedTool, String> {
+    if let Some(located_tool) = locate_unmanaged_tool(yt_dlp_settings.path(), "yt-dlp")? {
+        return Ok(located_tool);
+    }
+
+    if *yt_dlp_settings.managed() {
+        let managed_path = environment_initializer::get_managed_yt_dlp_path(library_paths);
+
+        if managed_path.exists() {
+            return Ok(LocatedTool {
+                path: managed_path,
+                source: ToolSource::Managed,
+            });
+        }
+    }
+
+    return Err(missing_yt_dlp_message(yt_dlp_settings));
+}
+
+/// Find yt-dlp like `locate_yt_dlp`, downloading the managed executable if that is the one to use
+pub fn ensure_yt_dlp(
+    yt_dlp_settings: &YtDlpSettings,
+    library_paths: &LibraryPaths,
+) -> Result<LocatedTool, String> {
+    if let Some(located_tool) = locate_unmanaged_tool(yt_dlp_settings.path(), "yt-dlp")? {
+        return Ok(located_tool);
+    }
+
+    if !*yt_dlp_settings.managed() {
+        return Err(missing_yt_dlp_message(yt_dlp_settings));
+    }
+
+    environment_initializer::init_yt_dlp_executable(yt_dlp_settings, library_paths)?;
+
+    return Ok(LocatedTool {
+        path: environment_initializer::get_managed_yt_dlp_path(library_paths),
+        source: ToolSource::Managed,
+    });
+}
+
+/// Find ffmpeg, from the configured path or the PATH
+pub fn locate_ffmpeg(ffmpeg_settings: &FfmpegSettings) -> Result<LocatedTool, String> {
+    return match locate_unmanaged_tool(ffmpeg_settings.ffmpeg_path(), "ffmpeg")? {
+        Some(located_tool) => Ok(located_tool),
+        None => Err(
+            "Could not find ffmpeg, install it on the PATH or set ffmpeg.ffmpeg_path in the settings"
+                .to_string(),
+        ),
+    };
+}
+
+/// Find ffprobe, from the configured path, next to the configured ffmpeg, or the PATH
+pub fn locate_ffprobe(ffmpeg_settings: &FfmpegSettings) -> Result<LocatedTool, String> {
+    let configured_path = match (
+        ffmpeg_settings.ffprobe_path(),
+        ffmpeg_settings.ffmpeg_path(),
+    ) {
+        (Some(ffprobe_path), _) => Some(ffprobe_path.to_owned()),
+        // ffprobe ships alongside ffmpeg
+        (None, Some(ffmpeg_path)) => ffmpeg_path
+            .parent()
+            .map(|directory| directory.join(executable_file_name("ffprobe"))),
+        (None, None) => None,
+    };
+
+    return match locate_unmanaged_tool(&configured_path, "ffprobe")? {
+        Some(located_tool) => Ok(located_tool),
+        None => Err(
+            "Could not find ffprobe, install it on the PATH or set ffmpeg.ffprobe_path in the settings"
+                .to_string(),
+        ),
+    };
+}
+
+/// Run the tool with the version argument, returns the first line of its output
+pub fn get_tool_version(tool_path: &Path, version_argument: &str) -> Result<String, String> {
+    let command_output = match Command::new(tool_path).arg(version_argument).output() {
+        Ok(out) => out,
+        Err(e) => {
+            return Err(format!(
+                "Could not run {}: {}",
+                tool_path.to_string_lossy(),
+                e
+            ))
+        }
+    };
+
+    if !command_output.status.success() {
+        return Err(format!(
+            "{} {} exited with {}",
+            tool_path.to_string_lossy(),
+            version_argument,
+            command_output.status
+        ));
+    }
+
+    let stdout_string = String::from_utf8_lossy(&command_output.stdout);
+
+    return match stdout_string.lines().next() {
+        Some(line) => Ok(line.trim().to_string()),
+        None => Err(format!(
+            "{} {} printed nothing",
+            tool_path.to_string_lossy(),
+            version_argument
+        )),
+    };
+}
+
+// the configured path if it is set, otherwise the tool from the PATH
+fn locate_unmanaged_tool(
+    configured_path: &Option<PathBuf>,
+    tool_name: &str,
+) -> Result<Option<LocatedTool>, String> {
+    if let Some(configured_path) = configured_path {
+        // a configured path that does not exist is a mistake, do not silently use another tool
+        if !configured_path.is_file() {
+            return Err(format!(
+                "Configured {} path {} does not exist",
+                tool_name,
+                configured_path.to_string_lossy()
+            ));
+        }
+
+        // absolute, as yt-dlp is started in the temporary directory and resolves
+        // --ffmpeg-location from there
+        return Ok(Some(LocatedTool {
+            path: library_paths::get_absolute_path(configured_path)?,
+            source: ToolSource::Configured,
+        }));
+    }
+
+    // a relative folder in the PATH is relative to the working directory too
+    return match find_on_path(tool_name) {
+        Some(path) => Ok(Some(LocatedTool {
+            path: library_paths::get_absolute_path(&path)?,
+            source: ToolSource::SystemPath,
+        })),
+        None => Ok(None),
+    };
+}
+
+// search the directories of the PATH environment variable for the executable
+fn find_on_path(tool_name: &str) -> Option<PathBuf> {
+    let path_variable = std::env::var_os("PATH")?;
+    let file_name = executable_file_name(tool_name);
+
+    for directory in std::env::split_paths(&path_variable) {
+        let candidate = directory.join(&file_name);
+
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+    }
+
+    return None;
+}
+
+fn executable_file_name(tool_name: &str) -> String {
+    return match std::env::consts::OS {
+        "windows" => format!("{}.exe", tool_name),
+        _ => tool_name.to_string(),
+    };
+}
+
+fn missing_yt_dlp_message(yt_dlp_settings: &YtDlpSettings) -> String {
+    if *yt_dlp_settings.managed() {
+        return "The managed yt-dlp executable has not been downloaded yet, run the run or update-yt-dlp command".to_string();
+    }
+
+    return "Could not find yt-dlp, install it on the PATH, set yt_dlp.path, or opt into the managed download with yt_dlp.managed in the settings".to_string();
+}
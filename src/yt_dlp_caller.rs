@@ -1,64 +1,337 @@
-use std::process::Command;
+use std::io::{BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
 
-use crate::music_sources::{DownloadedSong, SongInformation};
+use crate::download_error::{DownloadError, DownloadErrorClass};
+use crate::library_paths::LibraryPaths;
+use crate::music_sources::{DownloadInformation, DownloadedSong, SongInformation};
+use crate::settings_parser::{AudioSettings, Settings, TrimmingSettings};
+use crate::tool_locator::{self, ToolSource};
 
-// Calls the yt-dlp cli via os commands
-pub fn download_song(song_information: &SongInformation) -> Result<DownloadedSong, String> {
-    let working_directory = match std::env::current_dir() {
-        Ok(pwd) => pwd,
-        Err(e) => {
-            return Err(format!(
-                "Could not get working directory in download song: {}",
-                e
-            ));
+// prefixes marking our progress template lines in the yt-dlp output
+const DOWNLOAD_PROGRESS_PREFIX: &str = "musix-download";
+const POST_PROCESS_PROGRESS_PREFIX: &str = "musix-postprocess";
+
+// yt-dlp prints NA for fields that are not known
+const MISSING_FIELD: &str = "NA";
+
+/// Typed progress events parsed from the yt-dlp output
+#[derive(Debug, PartialEq)]
+pub enum DownloadProgressEvent {
+    Downloading {
+        downloaded_bytes: Option<u64>,
+        total_bytes: Option<u64>,
+        // bytes per second
+        speed: Option<f64>,
+        // seconds remaining
+        eta: Option<u64>,
+    },
+    PostProcessing {
+        postprocessor: String,
+        status: String,
+    },
+    Finished,
+}
+
+// line of output from either stream of the yt-dlp process
+enum OutputLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+// Calls the yt-dlp cli via os commands, streaming its progress to the progress callback
+pub fn download_song(
+    song_information: &SongInformation,
+    settings: &Settings,
+    library_paths: &LibraryPaths,
+    on_progress: &mut dyn FnMut(&DownloadProgressEvent),
+) -> Result<DownloadedSong, DownloadError> {
+    let yt_dlp = tool_locator::locate_yt_dlp(settings.yt_dlp(), library_paths)?;
+
+    // songs are downloaded into the temporary directory until they are post processed
+    let working_directory = library_paths.temporary_directory();
+
+    let mut command = Command::new(&yt_dlp.path);
+
+    // yt-dlp finds ffmpeg on the PATH itself, so it only needs to be told about configured ones
+    if let Ok(ffmpeg) = tool_locator::locate_ffmpeg(settings.ffmpeg()) {
+        if ffmpeg.source == ToolSource::Configured {
+            command.arg("--ffmpeg-location").arg(&ffmpeg.path);
         }
-    };
+    }
 
-    let command_output = match Command::new("./yt-dlp")
+    let mut child_process = match command
         .current_dir(&working_directory)
+        .args(get_audio_arguments(settings.audio()))
+        .args(get_trimming_arguments(settings.trimming()))
+        // named after the song, so a file left by an earlier download is never taken for this one
         .arg("-o")
-        .arg("tmp")
-        .arg("--audio-format")
-        .arg("mp3")
-        .arg("-x")
+        .arg("%(id)s.%(ext)s")
+        .arg("--force-overwrites")
+        // one progress line per update, in a format we can parse
+        .arg("--newline")
+        .arg("--progress")
+        .arg("--progress-template")
+        .arg(format!(
+            "download:{} %(progress.downloaded_bytes)s %(progress.total_bytes)s %(progress.total_bytes_estimate)s %(progress.speed)s %(progress.eta)s",
+            DOWNLOAD_PROGRESS_PREFIX
+        ))
+        .arg("--progress-template")
+        .arg(format!(
+            "postprocess:{} %(progress.status)s %(progress.postprocessor)s",
+            POST_PROCESS_PROGRESS_PREFIX
+        ))
+        // print the final info json, which includes the file path, once the file is in place
+        .arg("--print")
+        .arg("after_move:%()j")
         .arg(&song_information.url)
-        .output()
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
     {
-        Ok(out) => out,
+        Ok(child) => child,
         Err(e) => {
-            return Err(format!(
+            return Err(DownloadError::from(format!(
                 "Could not spawn process to download song from url {}: {}",
                 song_information.url, e
-            ));
+            )));
         }
     };
 
-    let download_song_path = working_directory.join("tmp.mp3");
+    // read both output streams on their own threads, funneling the lines into one channel
+    let (line_sender, line_receiver) = mpsc::channel::<OutputLine>();
 
-    if command_output.status.success() {
-        return Ok(DownloadedSong {
-            url: song_information.url.to_owned(),
-            title: song_information.title.to_owned(),
-            genre: song_information.genre.to_owned(),
-            artist: song_information.artist.to_owned(),
-            file_location: download_song_path,
-        });
-    } else {
-        let child_process_stderr_vec = command_output.stderr;
-
-        let stderr_string = match String::from_utf8(child_process_stderr_vec) {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(format!(
-                    "Could not convert std out bytes buffer to utf8 string: {}",
-                    e
-                ));
-            }
+    let stdout_reader = spawn_line_reader(
+        child_process.stdout.take(),
+        line_sender.clone(),
+        OutputLine::Stdout,
+    );
+    let stderr_reader =
+        spawn_line_reader(child_process.stderr.take(), line_sender, OutputLine::Stderr);
+
+    let mut info_json: Option<serde_json::Value> = None;
+    let mut stderr_lines = Vec::<String>::new();
+
+    // the channel closes once both streams are closed
+    for output_line in line_receiver {
+        let (line, from_stderr) = match output_line {
+            OutputLine::Stdout(line) => (line, false),
+            OutputLine::Stderr(line) => (line, true),
         };
 
-        return Err(format!(
-            "Could not download file, processes exited with stderr: {}",
-            stderr_string
+        // progress lines can end up on stderr when yt-dlp is quiet
+        if let Some(progress_event) = parse_progress_line(&line) {
+            on_progress(&progress_event);
+            continue;
+        }
+
+        if from_stderr {
+            stderr_lines.push(line);
+            continue;
+        }
+
+        // the only json printed to stdout is the final info json
+        if line.trim_start().starts_with('{') {
+            info_json = serde_json::from_str(line.trim()).ok();
+        }
+    }
+
+    let _ = stdout_reader.join();
+    let _ = stderr_reader.join();
+
+    let exit_status = match child_process.wait() {
+        Ok(status) => status,
+        Err(e) => {
+            return Err(DownloadError::from(format!(
+                "Could not wait for yt-dlp process downloading url {}: {}",
+                song_information.url, e
+            )));
+        }
+    };
+
+    if !exit_status.success() {
+        let stderr_string = stderr_lines.join("\n");
+
+        return Err(DownloadError::new(
+            DownloadErrorClass::classify(exit_status.code(), &stderr_string),
+            format!(
+                "Could not download file, processes exited with {} and stderr: {}",
+                exit_status, stderr_string
+            ),
         ));
     }
+
+    on_progress(&DownloadProgressEvent::Finished);
+
+    let info_json = match info_json {
+        Some(value) => value,
+        None => {
+            return Err(DownloadError::from(format!(
+                "yt-dlp did not print the info json for url {}",
+                song_information.url
+            )));
+        }
+    };
+
+    let download_information = DownloadInformation::from_info_json(info_json);
+
+    let printed_file_path = match &download_information.file_path {
+        Some(file_path) => PathBuf::from(file_path),
+        None => {
+            return Err(DownloadError::from(format!(
+                "yt-dlp did not report the downloaded file path for url {}",
+                song_information.url
+            )));
+        }
+    };
+
+    // printed path can be relative to the working directory
+    let download_song_path = working_directory.join(printed_file_path);
+
+    return Ok(DownloadedSong::from_song_information(
+        song_information,
+        download_song_path,
+        download_information,
+    ));
+}
+
+// read lines from the stream on a new thread and send them through the channel
+fn spawn_line_reader<R: Read + Send + 'static>(
+    stream: Option<R>,
+    line_sender: mpsc::Sender<OutputLine>,
+    wrap_line: fn(String) -> OutputLine,
+) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
+        let stream = match stream {
+            Some(stream) => stream,
+            None => return,
+        };
+
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+
+            if line_sender.send(wrap_line(line)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+// yt-dlp arguments for the audio extraction, format, and quality
+fn get_audio_arguments(audio_settings: &AudioSettings) -> Vec<String> {
+    let mut arguments = vec!["-x".to_string(), "--audio-format".to_string()];
+
+    // "best" keeps the best audio stream in its original container when possible
+    if *audio_settings.keep_original_container() {
+        arguments.push("best".to_string());
+    } else {
+        arguments.push(audio_settings.format().as_yt_dlp_format().to_string());
+    }
+
+    if let Some(bitrate) = audio_settings.bitrate() {
+        arguments.push("--audio-quality".to_string());
+        arguments.push(bitrate.to_owned());
+    }
+
+    return arguments;
+}
+
+// yt-dlp arguments that fetch the SponsorBlock segments, they end up in the info json as "sponsorblock_chapters"
+fn get_trimming_arguments(trimming_settings: &TrimmingSettings) -> Vec<String> {
+    if trimming_settings.sponsorblock_categories().is_empty() {
+        return Vec::new();
+    }
+
+    // marking only adds chapters, the segments are cut in post processing so we know what was cut
+    return vec![
+        "--sponsorblock-mark".to_string(),
+        trimming_settings.sponsorblock_categories().join(","),
+    ];
+}
+
+/// Parse a line printed with our progress templates into a progress event,
+/// returns None if the line is not a progress line
+pub fn parse_progress_line(line: &str) -> Option<DownloadProgressEvent> {
+    let mut fields = line.split_whitespace();
+
+    match fields.next() {
+        Some(DOWNLOAD_PROGRESS_PREFIX) => {
+            let downloaded_bytes = parse_progress_field::<f64>(fields.next());
+            let total_bytes = parse_progress_field::<f64>(fields.next());
+            let total_bytes_estimate = parse_progress_field::<f64>(fields.next());
+            let speed = parse_progress_field::<f64>(fields.next());
+            let eta = parse_progress_field::<f64>(fields.next());
+
+            return Some(DownloadProgressEvent::Downloading {
+                downloaded_bytes: downloaded_bytes.map(|bytes| bytes as u64),
+                // fragmented downloads only have an estimate of the total size
+                total_bytes: total_bytes
+                    .or(total_bytes_estimate)
+                    .map(|bytes| bytes as u64),
+                speed,
+                eta: eta.map(|seconds| seconds as u64),
+            });
+        }
+        Some(POST_PROCESS_PROGRESS_PREFIX) => {
+            let status = fields.next().unwrap_or(MISSING_FIELD).to_string();
+            let postprocessor = fields.collect::<Vec<&str>>().join(" ");
+
+            return Some(DownloadProgressEvent::PostProcessing {
+                postprocessor,
+                status,
+            });
+        }
+        _ => return None,
+    }
+}
+
+fn parse_progress_field<T: std::str::FromStr>(field: Option<&str>) -> Option<T> {
+    return match field {
+        Some(MISSING_FIELD) | None => None,
+        Some(value) => value.parse::<T>().ok(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_progress_line, DownloadProgressEvent};
+
+    #[test]
+    fn test_parse_progress_line() {
+        assert_eq!(
+            parse_progress_line("musix-download 1024 4096 NA 512.5 6"),
+            Some(DownloadProgressEvent::Downloading {
+                downloaded_bytes: Some(1024),
+                total_bytes: Some(4096),
+                speed: Some(512.5),
+                eta: Some(6),
+            })
+        );
+
+        // total bytes falls back to the estimate
+        assert_eq!(
+            parse_progress_line("musix-download 1024 NA 8192.0 NA NA"),
+            Some(DownloadProgressEvent::Downloading {
+                downloaded_bytes: Some(1024),
+                total_bytes: Some(8192),
+                speed: None,
+                eta: None,
+            })
+        );
+
+        assert_eq!(
+            parse_progress_line("musix-postprocess started FFmpegExtractAudio"),
+            Some(DownloadProgressEvent::PostProcessing {
+                postprocessor: "FFmpegExtractAudio".to_string(),
+                status: "started".to_string(),
+            })
+        );
+
+        assert_eq!(parse_progress_line("[youtube] Extracting URL"), None);
+    }
 }